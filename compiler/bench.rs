@@ -24,8 +24,12 @@ fn main() {
         let categories_src = std::hint::black_box(CATEGORIES_TPL);
         let topic_src = std::hint::black_box(TOPIC_TPL);
 
-        let categories_js = compiler::compile(categories_src, "tests/bench/categories.tpl");
-        let topic_js = compiler::compile(topic_src, "tests/bench/topic.tpl");
+        let categories_js = compiler::compile(
+            categories_src,
+            "tests/bench/categories.tpl",
+            &Default::default(),
+        );
+        let topic_js = compiler::compile(topic_src, "tests/bench/topic.tpl", &Default::default());
 
         std::hint::black_box(categories_js);
         std::hint::black_box(topic_js);
@@ -39,7 +43,11 @@ fn main() {
 fn bench_compile_categories(b: &mut test::bench::Bencher) {
     b.iter(|| {
         let categories_src = std::hint::black_box(CATEGORIES_TPL);
-        compiler::compile(categories_src, "tests/bench/categories.tpl")
+        compiler::compile(
+            categories_src,
+            "tests/bench/categories.tpl",
+            &Default::default(),
+        )
     })
 }
 
@@ -47,6 +55,6 @@ fn bench_compile_categories(b: &mut test::bench::Bencher) {
 fn bench_compile_topic(b: &mut test::bench::Bencher) {
     b.iter(|| {
         let topic_src = std::hint::black_box(TOPIC_TPL);
-        compiler::compile(topic_src, "tests/bench/topic.tpl")
+        compiler::compile(topic_src, "tests/bench/topic.tpl", &Default::default())
     })
 }
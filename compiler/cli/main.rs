@@ -0,0 +1,516 @@
+//! `benchpress` command line precompiler
+//!
+//! Compiles templates to the same JS modules as `precompile`, without
+//! needing to start Node.
+//!
+//! ```text
+//! benchpress -o build/templates src/templates
+//! ```
+
+mod watch;
+
+use compiler::{
+    compile_with_diagnostics,
+    CompileOptions,
+    CompileOutput,
+    Diagnostic,
+    Resolver,
+};
+use std::{
+    env,
+    fs,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    process::ExitCode,
+};
+
+const USAGE: &str = "\
+Usage: benchpress [OPTIONS] <INPUT>...
+
+Compile benchpress templates to JS modules. Directories are searched
+recursively for `.tpl` files, and outputs mirror the input tree.
+Included and extended templates are inlined, found by their name
+relative to each input directory, like `partials/post.tpl`.
+
+Options:
+  -o, --out-dir <DIR>  write compiled files to DIR instead of beside each template
+      --strict         treat deprecated syntax as an error
+      --format <FMT>   module format of the output: `umd` (default), `esm`, or `cjs`
+      --source-map     write a source map beside each output, as `<output>.map`
+      --declarations   write TypeScript declarations beside each output, as `.d.ts`
+      --standalone     remove lines holding only block tags like `{{{ if x }}}`
+  -w, --watch          recompile templates when they change
+  -h, --help           print this help
+  -V, --version        print the version
+";
+
+/// extension of templates found when searching directories
+const TEMPLATE_EXT: &str = "tpl";
+/// extension of compiled output files
+const OUTPUT_EXT: &str = "js";
+/// extension appended to an output's file name for its source map
+const SOURCE_MAP_EXT: &str = "map";
+/// extension of TypeScript declarations, replacing the output's
+const DECLARATIONS_EXT: &str = "d.ts";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Args {
+    inputs: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+    options: CompileOptions,
+    watch: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Help,
+    Version,
+    Compile(Args),
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--strict" => parsed.options.strict = true,
+            "--source-map" => parsed.options.source_map = true,
+            "--declarations" => parsed.options.declarations = true,
+            "--standalone" => parsed.options.standalone = true,
+            "-w" | "--watch" => parsed.watch = true,
+            "--format" => match args.next() {
+                Some(format) => parsed.options.format = format.parse()?,
+                None => return Err(format!("`{arg}` requires a format")),
+            },
+            "-o" | "--out-dir" => match args.next() {
+                Some(dir) => parsed.out_dir = Some(dir.into()),
+                None => return Err(format!("`{arg}` requires a directory")),
+            },
+            // everything after `--` is an input
+            "--" => parsed.inputs.extend(args.by_ref().map(PathBuf::from)),
+            _ => {
+                if let Some(dir) = arg.strip_prefix("--out-dir=") {
+                    parsed.out_dir = Some(dir.into());
+                } else if let Some(format) = arg.strip_prefix("--format=") {
+                    parsed.options.format = format.parse()?;
+                } else if arg.starts_with('-') {
+                    return Err(format!("unknown option `{arg}`"));
+                } else {
+                    parsed.inputs.push(arg.into());
+                }
+            }
+        }
+    }
+
+    if parsed.inputs.is_empty() {
+        return Err("no input files or directories given".to_string());
+    }
+
+    Ok(Command::Compile(parsed))
+}
+
+/// A template to compile and where to write the output
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Job {
+    source: PathBuf,
+    output: PathBuf,
+}
+
+/// recursively find templates in a directory, sorted for stable output
+fn find_templates(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            find_templates(&path, found)?;
+        } else if path.extension().is_some_and(|ext| ext == TEMPLATE_EXT) {
+            found.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// list the templates to compile for each input
+///
+/// Templates inside a directory input keep their path relative to it,
+/// so `src/a/b.tpl` from input `src` with `--out-dir out` becomes `out/a/b.js`.
+fn collect_jobs(inputs: &[PathBuf], out_dir: Option<&Path>) -> io::Result<Vec<Job>> {
+    let mut jobs = vec![];
+
+    for input in inputs {
+        let (root, sources) = if input.is_dir() {
+            let mut found = vec![];
+            find_templates(input, &mut found)?;
+            (input.as_path(), found)
+        } else if input.is_file() {
+            (input.parent().unwrap_or(Path::new("")), vec![input.clone()])
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` does not exist", input.display()),
+            ));
+        };
+
+        for source in sources {
+            let output = match out_dir {
+                Some(out_dir) => out_dir.join(source.strip_prefix(root).unwrap_or(&source)),
+                None => source.clone(),
+            }
+            .with_extension(OUTPUT_EXT);
+
+            jobs.push(Job { source, output });
+        }
+    }
+
+    Ok(jobs)
+}
+
+/// Directories holding the inputs, which included templates are found in
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Roots(Vec<PathBuf>);
+
+impl Roots {
+    /// each directory input, and the directory of each file input
+    fn from_inputs(inputs: &[PathBuf]) -> Self {
+        Roots(
+            inputs
+                .iter()
+                .map(|input| {
+                    if input.is_dir() {
+                        input.clone()
+                    } else {
+                        input.parent().unwrap_or(Path::new("")).to_path_buf()
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// files a template name could refer to, in order of priority,
+    /// so `partials/post` could be `<root>/partials/post.tpl` in any root
+    fn candidates<'a>(&'a self, name: &str) -> impl Iterator<Item = PathBuf> + 'a {
+        let mut file = PathBuf::from(name);
+        if file.extension().is_none_or(|ext| ext != TEMPLATE_EXT) {
+            file.as_mut_os_string().push(format!(".{TEMPLATE_EXT}"));
+        }
+
+        self.0.iter().map(move |root| root.join(&file))
+    }
+
+    /// resolve templates to the first candidate which exists
+    fn resolver(&self) -> Resolver {
+        let roots = self.clone();
+        Resolver::new(move |name| {
+            roots
+                .candidates(name)
+                .find_map(|path| fs::read_to_string(path).ok())
+        })
+    }
+}
+
+/// print diagnostics like rustc, with a blank line after each
+fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        eprintln!("{diagnostic}");
+    }
+}
+
+/// compile a single template, returning whether it succeeded
+fn compile_job(job: &Job, options: &CompileOptions) -> io::Result<bool> {
+    let source = fs::read_to_string(&job.source)?;
+    compile_source(job, &source, options)
+}
+
+/// compile the already-read source of a template, returning whether it succeeded
+fn compile_source(job: &Job, source: &str, options: &CompileOptions) -> io::Result<bool> {
+    let filename = job.source.to_string_lossy();
+
+    let CompileOutput {
+        mut code,
+        diagnostics,
+        source_map,
+        declarations,
+    } = compile_with_diagnostics(source, &filename, options);
+    print_diagnostics(&diagnostics);
+
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Ok(false);
+    }
+
+    if let Some(parent) = job.output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if let Some(map) = source_map {
+        let mut map_name = job.output.file_name().unwrap_or_default().to_os_string();
+        map_name.push(".");
+        map_name.push(SOURCE_MAP_EXT);
+
+        fs::write(job.output.with_file_name(&map_name), map)?;
+        code.push_str(&format!(
+            "\n//# sourceMappingURL={}\n",
+            map_name.to_string_lossy()
+        ));
+    }
+    if let Some(declarations) = declarations {
+        fs::write(job.output.with_extension(DECLARATIONS_EXT), declarations)?;
+    }
+    fs::write(&job.output, code)?;
+
+    Ok(true)
+}
+
+fn print_io_error(job: &Job, e: &io::Error) {
+    eprintln!(
+        "[benchpress] error: failed to compile `{}`: {e}\n",
+        job.source.display()
+    );
+}
+
+/// compile every job, returning how many failed
+fn compile_all(jobs: &[Job], options: &CompileOptions) -> usize {
+    let mut failed = 0;
+
+    for job in jobs {
+        match compile_job(job, options) {
+            Ok(true) => {}
+            Ok(false) => failed += 1,
+            Err(e) => {
+                print_io_error(job, &e);
+                failed += 1;
+            }
+        }
+    }
+
+    failed
+}
+
+fn main() -> ExitCode {
+    let mut args = match parse_args(env::args().skip(1)) {
+        Ok(Command::Help) => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Version) => {
+            println!("benchpress {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Compile(args)) => args,
+        Err(message) => {
+            eprint!("[benchpress] error: {message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    args.options.resolver = Some(Roots::from_inputs(&args.inputs).resolver());
+    if args.watch {
+        watch::watch(&args);
+    }
+
+    let jobs = match collect_jobs(&args.inputs, args.out_dir.as_deref()) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("[benchpress] error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let failed = compile_all(&jobs, &args.options);
+    if failed > 0 {
+        eprintln!(
+            "[benchpress] error: could not compile {failed} of {} templates",
+            jobs.len()
+        );
+        return ExitCode::FAILURE;
+    }
+
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use compiler::OutputFormat;
+    use pretty_assertions::assert_eq;
+
+    fn args(list: &[&str]) -> Result<Command, String> {
+        parse_args(list.iter().map(|s| s.to_string()))
+    }
+
+    /// a fresh directory for a test to write to
+    pub fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("benchpress-cli-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            args(&["--strict", "a.tpl", "-o", "out", "dir", "--standalone"]),
+            Ok(Command::Compile(Args {
+                inputs: vec!["a.tpl".into(), "dir".into()],
+                out_dir: Some("out".into()),
+                options: CompileOptions {
+                    strict: true,
+                    standalone: true,
+                    ..Default::default()
+                },
+                watch: false,
+            }))
+        );
+        assert_eq!(
+            args(&[
+                "-w",
+                "--out-dir=out",
+                "--format=esm",
+                "--source-map",
+                "--",
+                "-weird.tpl",
+            ]),
+            Ok(Command::Compile(Args {
+                inputs: vec!["-weird.tpl".into()],
+                out_dir: Some("out".into()),
+                options: CompileOptions {
+                    format: OutputFormat::Esm,
+                    source_map: true,
+                    ..Default::default()
+                },
+                watch: true,
+            }))
+        );
+        assert_eq!(args(&["a.tpl", "--help"]), Ok(Command::Help));
+        assert!(args(&[]).is_err());
+        assert!(args(&["a.tpl", "-o"]).is_err());
+        assert!(args(&["--frobnicate", "a.tpl"]).is_err());
+        assert!(args(&["--format", "iife", "a.tpl"]).is_err());
+    }
+
+    #[test]
+    fn test_collect_jobs() {
+        let dir = temp_dir("collect");
+        fs::create_dir_all(dir.join("src/partials")).unwrap();
+        fs::write(dir.join("src/b.tpl"), "").unwrap();
+        fs::write(dir.join("src/partials/a.tpl"), "").unwrap();
+        fs::write(dir.join("src/notes.txt"), "").unwrap();
+        fs::write(dir.join("single.tpl"), "").unwrap();
+
+        let inputs = [dir.join("src"), dir.join("single.tpl")];
+        assert_eq!(
+            collect_jobs(&inputs, Some(&dir.join("out"))).unwrap(),
+            vec![
+                Job {
+                    source: dir.join("src/b.tpl"),
+                    output: dir.join("out/b.js"),
+                },
+                Job {
+                    source: dir.join("src/partials/a.tpl"),
+                    output: dir.join("out/partials/a.js"),
+                },
+                Job {
+                    source: dir.join("single.tpl"),
+                    output: dir.join("out/single.js"),
+                },
+            ]
+        );
+        assert_eq!(
+            collect_jobs(&inputs[1..], None).unwrap(),
+            vec![Job {
+                source: dir.join("single.tpl"),
+                output: dir.join("single.js"),
+            }]
+        );
+        assert!(collect_jobs(&[dir.join("missing")], None).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compile_all() {
+        let dir = temp_dir("compile");
+        fs::write(dir.join("good.tpl"), "{{{ if a }}}yes{{{ end }}}").unwrap();
+        fs::write(dir.join("bad.tpl"), "{{{ else }}}").unwrap();
+
+        let jobs = collect_jobs(std::slice::from_ref(&dir), Some(&dir.join("out"))).unwrap();
+        assert_eq!(compile_all(&jobs, &CompileOptions::default()), 1);
+
+        let code = fs::read_to_string(dir.join("out/good.js")).unwrap();
+        assert!(code.contains("function compiled("));
+        assert!(!dir.join("out/bad.js").exists());
+        assert!(!dir.join("out/good.js.map").exists());
+
+        let options = CompileOptions {
+            source_map: true,
+            declarations: true,
+            ..Default::default()
+        };
+        assert_eq!(compile_all(&jobs, &options), 1);
+
+        let code = fs::read_to_string(dir.join("out/good.js")).unwrap();
+        assert!(code.ends_with("\n//# sourceMappingURL=good.js.map\n"));
+        let map = fs::read_to_string(dir.join("out/good.js.map")).unwrap();
+        assert!(map.starts_with("{\"version\":3,"));
+        let declarations = fs::read_to_string(dir.join("out/good.d.ts")).unwrap();
+        assert!(declarations.contains("export interface Context {\n    a?: unknown;\n  }"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_includes() {
+        let dir = temp_dir("resolve");
+        fs::create_dir_all(dir.join("src/partials")).unwrap();
+        fs::write(
+            dir.join("src/partials/layout.tpl"),
+            "<main>{{{ block \"content\" }}}default{{{ end }}}</main>",
+        )
+        .unwrap();
+        fs::write(dir.join("src/partials/footer.tpl"), "<footer/>").unwrap();
+        fs::write(
+            dir.join("src/page.tpl"),
+            "{{{ extends \"partials/layout\" }}}\
+             {{{ block \"content\" }}}page<!-- IMPORT partials/footer.tpl -->{{{ end }}}",
+        )
+        .unwrap();
+        fs::write(dir.join("src/broken.tpl"), "{{{ include \"missing\" }}}").unwrap();
+
+        let inputs = [dir.join("src")];
+        assert_eq!(
+            Roots::from_inputs(&inputs)
+                .candidates("partials/post")
+                .collect::<Vec<_>>(),
+            vec![dir.join("src/partials/post.tpl")]
+        );
+        assert_eq!(
+            Roots::from_inputs(&[dir.join("src/page.tpl")]),
+            Roots(vec![dir.join("src")])
+        );
+
+        let jobs = collect_jobs(&inputs, Some(&dir.join("out"))).unwrap();
+        let options = CompileOptions {
+            resolver: Some(Roots::from_inputs(&inputs).resolver()),
+            ..Default::default()
+        };
+        assert_eq!(compile_all(&jobs, &options), 1);
+
+        let code = fs::read_to_string(dir.join("out/page.js")).unwrap();
+        assert!(code.contains("<main>"));
+        assert!(code.contains("page"));
+        assert!(code.contains("<footer/>"));
+        assert!(!code.contains("default"));
+        assert!(!code.contains("__include"));
+        assert!(!dir.join("out/broken.js").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,392 @@
+//! `--watch` mode, recompiling templates as they change
+//!
+//! Inputs are polled rather than watched with OS file events, which keeps
+//! the binary free of platform-specific dependencies. Templates whose
+//! metadata changed are read and hashed, and only compiled if their
+//! content is different from when they were last compiled, or if a template
+//! they include or extend changed.
+
+use crate::{
+    collect_jobs,
+    compile_source,
+    print_io_error,
+    Args,
+    Job,
+    Roots,
+};
+use compiler::{
+    CompileOptions,
+    Resolver,
+};
+use std::{
+    collections::{
+        hash_map::DefaultHasher,
+        HashMap,
+        HashSet,
+    },
+    fs,
+    hash::{
+        Hash,
+        Hasher,
+    },
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+    thread,
+    time::{
+        Duration,
+        SystemTime,
+    },
+};
+
+/// how long to wait between checking for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Entry {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+/// Files looked at while resolving the includes of a template,
+/// with `None` for those which didn't exist
+type Includes = Vec<(PathBuf, Option<Entry>)>;
+
+/// Content hashes of templates as of when they were last compiled
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<PathBuf, Entry>,
+    includes: HashMap<PathBuf, Includes>,
+}
+
+fn hash(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// whether metadata shows a file hasn't been touched since the entry was made
+fn untouched(old: &Entry, metadata: &fs::Metadata) -> bool {
+    old.modified.is_some() && old.modified == metadata.modified().ok() && old.len == metadata.len()
+}
+
+/// read a file and make its entry, or `None` if it can't be read
+fn read_entry(path: &Path) -> Option<(String, Entry)> {
+    let metadata = fs::metadata(path).ok()?;
+    let source = fs::read_to_string(path).ok()?;
+    let entry = Entry {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+        hash: hash(&source),
+    };
+
+    Some((source, entry))
+}
+
+/// resolve templates from `roots`, recording each file looked at
+fn recording_resolver(roots: &Roots) -> (Resolver, Arc<Mutex<Includes>>) {
+    let roots = roots.clone();
+    let includes = Arc::new(Mutex::new(Includes::new()));
+    let seen = includes.clone();
+
+    let resolver = Resolver::new(move |name| {
+        let mut seen = seen.lock().unwrap();
+        for path in roots.candidates(name) {
+            match read_entry(&path) {
+                Some((source, entry)) => {
+                    seen.push((path, Some(entry)));
+                    return Some(source);
+                }
+                None => seen.push((path, None)),
+            }
+        }
+        None
+    });
+
+    (resolver, includes)
+}
+
+impl Cache {
+    /// read a template if its content changed since it was last compiled,
+    /// along with the entry to [`Cache::record`] once it has been
+    fn read_if_changed(&mut self, path: &Path) -> io::Result<Option<(String, Entry)>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified().ok();
+        let len = metadata.len();
+
+        let old = self.entries.get(path);
+        // skip reading the file if it hasn't been touched
+        if old.is_some_and(|old| untouched(old, &metadata)) {
+            return Ok(None);
+        }
+
+        let source = fs::read_to_string(path)?;
+        let entry = Entry {
+            modified,
+            len,
+            hash: hash(&source),
+        };
+        if old.is_some_and(|old| old.hash == entry.hash) {
+            // only touched, so skip reading it next time
+            self.entries.insert(path.to_path_buf(), entry);
+            return Ok(None);
+        }
+
+        Ok(Some((source, entry)))
+    }
+
+    /// whether any file looked at for the includes of a template changed
+    /// since it was compiled, including ones which didn't exist then
+    fn includes_changed(&mut self, path: &Path) -> bool {
+        let includes = match self.includes.get_mut(path) {
+            Some(includes) => includes,
+            None => return false,
+        };
+
+        for (path, old) in includes {
+            match (&*old, fs::metadata(&*path)) {
+                (None, Err(_)) => continue,
+                (Some(old), Ok(metadata)) if untouched(old, &metadata) => continue,
+                _ => {}
+            }
+
+            let new = read_entry(path).map(|(_, entry)| entry);
+            if new.as_ref().map(|x| x.hash) != old.as_ref().map(|x| x.hash) {
+                return true;
+            }
+            // only touched, so skip reading it next time
+            *old = new;
+        }
+
+        false
+    }
+
+    /// remember the content a template and its includes were compiled from
+    fn record(&mut self, path: &Path, entry: Entry, includes: Includes) {
+        self.entries.insert(path.to_path_buf(), entry);
+        self.includes.insert(path.to_path_buf(), includes);
+    }
+
+    /// compile a template again, even if it hasn't changed
+    fn forget(&mut self, path: &Path) {
+        self.entries.remove(path);
+        self.includes.remove(path);
+    }
+
+    /// forget templates which are no longer inputs
+    fn retain(&mut self, jobs: &[Job]) {
+        let sources = jobs
+            .iter()
+            .map(|job| job.source.as_path())
+            .collect::<HashSet<_>>();
+        self.entries
+            .retain(|path, _| sources.contains(path.as_path()));
+        self.includes
+            .retain(|path, _| sources.contains(path.as_path()));
+    }
+}
+
+/// compile the templates which changed, returning how many were compiled
+/// and how many of those failed
+fn rebuild(
+    cache: &mut Cache,
+    jobs: &[Job],
+    roots: &Roots,
+    options: &CompileOptions,
+    verbose: bool,
+) -> (usize, usize) {
+    let mut compiled = 0;
+    let mut failed = 0;
+
+    for job in jobs {
+        if cache.includes_changed(&job.source) {
+            cache.forget(&job.source);
+        }
+
+        // only recorded once the output was written, so failed writes are retried
+        let result = cache
+            .read_if_changed(&job.source)
+            .and_then(|source| match source {
+                Some((source, entry)) => {
+                    let (resolver, includes) = recording_resolver(roots);
+                    let options = CompileOptions {
+                        resolver: Some(resolver),
+                        ..options.clone()
+                    };
+                    let compiled = compile_source(job, &source, &options)?;
+
+                    let includes = std::mem::take(&mut *includes.lock().unwrap());
+                    cache.record(&job.source, entry, includes);
+                    Ok(Some(compiled))
+                }
+                None => Ok(None),
+            });
+
+        match result {
+            Ok(None) => continue,
+            Ok(Some(true)) => {
+                if verbose {
+                    eprintln!("[benchpress] compiled `{}`", job.source.display());
+                }
+            }
+            Ok(Some(false)) => failed += 1,
+            Err(e) => {
+                print_io_error(job, &e);
+                failed += 1;
+            }
+        }
+        compiled += 1;
+    }
+
+    (compiled, failed)
+}
+
+/// compile everything, then keep recompiling templates as they change
+pub fn watch(args: &Args) -> ! {
+    let mut cache = Cache::default();
+    let mut first = true;
+    let mut last_error = None;
+
+    loop {
+        match collect_jobs(&args.inputs, args.out_dir.as_deref()) {
+            Ok(jobs) => {
+                last_error = None;
+                cache.retain(&jobs);
+
+                let roots = Roots::from_inputs(&args.inputs);
+                let (compiled, failed) = rebuild(&mut cache, &jobs, &roots, &args.options, !first);
+                if first {
+                    eprintln!(
+                        "[benchpress] compiled {compiled} templates, {failed} failed. \
+                         Watching for changes..."
+                    );
+                    first = false;
+                }
+            }
+            Err(e) => {
+                // inputs may be briefly missing while being moved, so only report new problems
+                let message = e.to_string();
+                if last_error.as_ref() != Some(&message) {
+                    eprintln!("[benchpress] error: {message}");
+                    last_error = Some(message);
+                }
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::temp_dir;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_cache() {
+        let dir = temp_dir("watch-cache");
+        let path = dir.join("a.tpl");
+        let mut cache = Cache::default();
+
+        let read = |cache: &mut Cache| {
+            cache
+                .read_if_changed(&path)
+                .unwrap()
+                .map(|(source, entry)| {
+                    cache.record(&path, entry, vec![]);
+                    source
+                })
+        };
+
+        fs::write(&path, "one").unwrap();
+        assert!(cache.read_if_changed(&path).unwrap().is_some());
+        // not compiled, so still changed
+        assert_eq!(read(&mut cache).as_deref(), Some("one"));
+        assert_eq!(read(&mut cache), None);
+
+        // rewritten with the same content
+        fs::write(&path, "one").unwrap();
+        assert_eq!(read(&mut cache), None);
+
+        fs::write(&path, "two!").unwrap();
+        assert_eq!(read(&mut cache).as_deref(), Some("two!"));
+
+        cache.retain(&[]);
+        assert_eq!(read(&mut cache).as_deref(), Some("two!"));
+
+        fs::remove_file(&path).unwrap();
+        assert!(cache.read_if_changed(&path).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let dir = temp_dir("watch-rebuild");
+        fs::write(dir.join("a.tpl"), "{a}").unwrap();
+        fs::write(dir.join("b.tpl"), "{{{ else }}}").unwrap();
+
+        let mut cache = Cache::default();
+        let jobs = collect_jobs(std::slice::from_ref(&dir), Some(&dir.join("out"))).unwrap();
+        let roots = Roots::from_inputs(std::slice::from_ref(&dir));
+        let options = CompileOptions::default();
+
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (2, 1));
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (0, 0));
+
+        fs::write(dir.join("b.tpl"), "{b}").unwrap();
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (1, 0));
+        assert!(dir.join("out/b.js").exists());
+
+        // a template whose output couldn't be written is tried again
+        fs::remove_dir_all(dir.join("out")).unwrap();
+        fs::write(dir.join("out"), "").unwrap();
+        fs::write(dir.join("a.tpl"), "{a}!").unwrap();
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (1, 1));
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (1, 1));
+        fs::remove_file(dir.join("out")).unwrap();
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (1, 0));
+        assert!(dir.join("out/a.js").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rebuild_includes() {
+        let dir = temp_dir("watch-includes");
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        fs::write(dir.join("partials/post.tpl"), "post").unwrap();
+        fs::write(dir.join("a.tpl"), "{{{ include \"partials/post\" }}}").unwrap();
+        fs::write(dir.join("b.tpl"), "{{{ include \"missing\" }}}").unwrap();
+
+        let mut cache = Cache::default();
+        let jobs = collect_jobs(std::slice::from_ref(&dir), Some(&dir.join("out"))).unwrap();
+        let roots = Roots::from_inputs(std::slice::from_ref(&dir));
+        let options = CompileOptions::default();
+
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (3, 1));
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (0, 0));
+
+        // the partial and the template including it
+        fs::write(dir.join("partials/post.tpl"), "edited").unwrap();
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (2, 0));
+        let code = fs::read_to_string(dir.join("out/a.js")).unwrap();
+        assert!(code.contains("edited"));
+
+        // a template which couldn't be found before
+        fs::write(dir.join("missing.tpl"), "found").unwrap();
+        let jobs = collect_jobs(std::slice::from_ref(&dir), Some(&dir.join("out"))).unwrap();
+        assert_eq!(rebuild(&mut cache, &jobs, &roots, &options, false), (2, 0));
+        assert!(dir.join("out/b.js").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,332 @@
+use crate::parse::{
+    Span,
+    SpanExt,
+};
+use std::fmt;
+
+/// How serious a diagnostic is
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// Stable identifier for each kind of diagnostic
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Code {
+    // the compiler failed in a way it shouldn't have
+    InternalError,
+    // `{{{ if }}}` closed with `<!-- END -->` or similar
+    MixedTokens,
+    // block opened but never closed
+    UnterminatedBlock,
+    // `{{{ end }}}` or `<!-- END -->` without a matching opener
+    ExtraTokens,
+    // something that looks like a token but failed to parse
+    ProbableSyntaxError,
+    // `@key`, `@value`, `@index` outside of curly braces
+    BareKeyword,
+    // `<!-- BEGIN stuff -->` inside another block
+    AmbiguousBegin,
+    // `{{{ else }}}` outside of a block, or a second `{{{ else }}}`
+    UnexpectedToken,
+    // `{{{ include }}}` of a template the resolver couldn't find
+    UnresolvedInclude,
+    // a template which ends up including itself
+    IncludeCycle,
+    // content of a template extending another, which isn't in a block
+    IgnoredContent,
+    // a number literal too large to represent
+    InvalidNumber,
+    // a named block sharing its name with another block or a top-level iteration
+    DuplicateBlock,
+    // `{{{ include }}}` inside a loop, left to the runtime, which renders
+    // it with the root data rather than the current item
+    RuntimeInclude,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::InternalError => "internal-error",
+            Code::MixedTokens => "mixed-tokens",
+            Code::UnterminatedBlock => "unterminated-block",
+            Code::ExtraTokens => "extra-tokens",
+            Code::ProbableSyntaxError => "probable-syntax-error",
+            Code::BareKeyword => "bare-keyword",
+            Code::AmbiguousBegin => "ambiguous-begin",
+            Code::UnexpectedToken => "unexpected-token",
+            Code::UnresolvedInclude => "unresolved-include",
+            Code::IncludeCycle => "include-cycle",
+            Code::IgnoredContent => "ignored-content",
+            Code::InvalidNumber => "invalid-number",
+            Code::DuplicateBlock => "duplicate-block",
+            Code::RuntimeInclude => "runtime-include",
+        }
+    }
+
+    /// deprecated syntax slated to become an error in v3.0.0,
+    /// which strict mode already treats as an error
+    pub fn is_deprecated_syntax(&self) -> bool {
+        matches!(
+            self,
+            Code::MixedTokens | Code::UnterminatedBlock | Code::ExtraTokens | Code::BareKeyword
+        )
+    }
+}
+
+/// A span of template source with a message attached
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Label {
+    pub filename: String,
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column, counted in characters
+    pub column: usize,
+    /// length of the span, counted in characters
+    pub length: usize,
+    /// full text of the line the span starts on
+    pub source_line: String,
+    pub message: String,
+}
+
+impl Label {
+    pub(crate) fn new(span: Span, message: impl Into<String>) -> Self {
+        let (line, column) = span.get_line_column();
+
+        Label {
+            filename: span.extra.filename.to_string(),
+            line: span.location_line(),
+            column,
+            length: span.fragment().chars().count(),
+            source_line: line.to_string(),
+            message: message.into(),
+        }
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            filename: self.filename.as_str(),
+            line: self.line,
+            column: self.column,
+            length: self.length,
+            sourceLine: self.source_line.as_str(),
+            message: self.message.as_str(),
+        }
+    }
+}
+
+/// A warning or error produced while compiling a template
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    pub filename: String,
+    /// where the problem is
+    pub primary: Option<Label>,
+    /// other locations relevant to the problem
+    pub secondary: Vec<Label>,
+    pub help: Option<String>,
+    pub note: Option<String>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(
+        severity: Severity,
+        code: Code,
+        filename: &str,
+        message: impl Into<String>,
+    ) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            message: message.into(),
+            filename: filename.to_string(),
+            primary: None,
+            secondary: Vec::new(),
+            help: None,
+            note: None,
+        }
+    }
+
+    pub(crate) fn warning(code: Code, span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, span.extra.filename, message)
+    }
+
+    pub(crate) fn error(code: Code, span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, span.extra.filename, message)
+    }
+
+    pub(crate) fn internal(filename: &str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, Code::InternalError, filename, message).note(
+            "This is not an issue with your template, please report this issue on the benchpress Github page.",
+        )
+    }
+
+    pub(crate) fn primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.primary = Some(Label::new(span, message));
+        self
+    }
+
+    pub(crate) fn secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label::new(span, message));
+        self
+    }
+
+    pub(crate) fn help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub(crate) fn note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            severity: self.severity.as_str(),
+            code: self.code.as_str(),
+            message: self.message.as_str(),
+            filename: self.filename.as_str(),
+            primary: self.primary.as_ref().map_or(json::JsonValue::Null, Label::to_json),
+            secondary: self.secondary.iter().map(Label::to_json).collect::<Vec<_>>(),
+            help: self.help.as_deref(),
+            note: self.note.as_deref(),
+        }
+    }
+}
+
+/// rustc-style rendering of a label
+///
+/// ```text
+///      --> file.tpl:3:5
+///       |
+///     3 |     {{{ if abc }}}
+///       |     ^^^^^^^^^^^^^^ message
+/// ```
+fn fmt_label(f: &mut fmt::Formatter<'_>, arrow: &str, label: &Label) -> fmt::Result {
+    // keep tabs so the carets line up with the source line
+    let padding: String = label
+        .source_line
+        .chars()
+        .take(label.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    writeln!(
+        f,
+        "     {} {}:{}:{}",
+        arrow, label.filename, label.line, label.column
+    )?;
+    writeln!(f, "      |")?;
+    writeln!(f, "{:>5} | {}", label.line, label.source_line)?;
+    write!(f, "      | {}{}", padding, "^".repeat(label.length))?;
+    if label.message.is_empty() {
+        writeln!(f)
+    } else {
+        writeln!(f, " {}", label.message)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.code {
+            Code::InternalError => "internal error",
+            _ => self.severity.as_str(),
+        };
+        writeln!(f, "[benchpress] {}: {}", kind, self.message)?;
+
+        match &self.primary {
+            Some(label) => fmt_label(f, "-->", label)?,
+            None => writeln!(f, "     --> {}", self.filename)?,
+        }
+        for label in &self.secondary {
+            fmt_label(f, ":::", label)?;
+        }
+
+        if let Some(help) = &self.help {
+            writeln!(f, "      | help: {help}")?;
+        }
+        if let Some(note) = &self.note {
+            writeln!(f, "      | note: {note}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::test::{
+        assert_eq,
+        sp,
+    };
+    use nom::Slice;
+
+    #[test]
+    fn test_label() {
+        let src = sp("first line\n\tsecond {{{ if abc }}} line");
+        let label = Label::new(src.slice(19..33), "here");
+
+        assert_eq!(
+            label,
+            Label {
+                filename: "<test>".to_string(),
+                line: 2,
+                column: 9,
+                length: 14,
+                source_line: "\tsecond {{{ if abc }}} line".to_string(),
+                message: "here".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let src = sp("a {{{ if abc }}}\n\tb <!-- END -->");
+        let diagnostic =
+            Diagnostic::warning(Code::MixedTokens, src, "mixing token types is deprecated")
+                .primary(src.slice(2..16), "opened here")
+                .secondary(src.slice(20..32), "closed here")
+                .note("Migrate all to modern syntax.");
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "[benchpress] warning: mixing token types is deprecated
+     --> <test>:1:3
+      |
+    1 | a {{{ if abc }}}
+      |   ^^^^^^^^^^^^^^ opened here
+     ::: <test>:2:4
+      |
+    2 | \tb <!-- END -->
+      | \t  ^^^^^^^^^^^^ closed here
+      | note: Migrate all to modern syntax.
+"
+        );
+
+        assert_eq!(
+            Diagnostic::internal("foo.tpl", "Eof").to_string(),
+            "[benchpress] internal error: Eof
+     --> foo.tpl
+      | note: This is not an issue with your template, please report this issue on the benchpress Github page.
+"
+        );
+    }
+}
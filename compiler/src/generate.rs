@@ -1,2 +1,4 @@
+pub mod declarations;
 pub mod generator;
-mod templates;
+pub(crate) mod source_map;
+pub(crate) mod templates;
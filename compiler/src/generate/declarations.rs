@@ -0,0 +1,293 @@
+//! TypeScript declarations for compiled templates
+//!
+//! The context interface comes from the template's [`Schema`], so fields
+//! only used in conditionals are optional.
+
+use crate::{
+    generate::templates::{
+        self,
+        indent,
+    },
+    parse::{
+        tree::Instruction,
+        Span,
+    },
+    schema::{
+        Field,
+        Schema,
+    },
+    OutputFormat,
+};
+
+/// Runtime types shared by every template
+const RUNTIME_TYPES: &str = "\
+export type Collection<T> = T[] | { [key: string]: T };
+
+export interface Helpers {
+  __escape(value: unknown): string;
+  [name: string]: (...args: any[]) => unknown;
+}
+
+export type Guard = (value: unknown) => unknown;
+
+export type Iter = (
+  obj: unknown,
+  each: (key: string, index: number, length: number, value: unknown) => unknown,
+  alt: () => unknown,
+) => unknown;
+
+export type Helper = (context: Context, helpers: Helpers, name: string, args: unknown[]) => unknown;
+
+export type TemplateFunction = (
+  helpers: Helpers,
+  context: Context,
+  guard: Guard,
+  iter: Iter,
+  helper: Helper,
+) => unknown;";
+
+/// TypeScript object type with the fields read from a value,
+/// which are optional if only used in conditionals
+fn object_type(field: &Field) -> String {
+    if field.fields.is_empty() {
+        return "{}".to_string();
+    }
+
+    let fields = field
+        .fields
+        .iter()
+        .map(|(name, field)| {
+            format!(
+                "  {}{}: {};",
+                property_name(name),
+                if field.is_conditional_only() { "?" } else { "" },
+                indent(&to_type(field), 2)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("{{\n{fields}\n}}")
+}
+
+/// TypeScript type for the values matching a field
+fn to_type(field: &Field) -> String {
+    let collection = field
+        .element
+        .as_ref()
+        .map(|element| format!("Collection<{}>", to_type(element)));
+
+    match (collection, field.fields.is_empty()) {
+        (None, true) => "unknown".to_string(),
+        (Some(collection), true) => collection,
+        (None, false) => object_type(field),
+        (Some(collection), false) => format!("{} & {}", collection, object_type(field)),
+    }
+}
+
+/// quote property names which aren't valid identifiers
+fn property_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+
+    if valid {
+        name.to_string()
+    } else {
+        json::stringify(name)
+    }
+}
+
+/// names of the block methods generated for a body,
+/// in the same order and with the same rules as the generator
+fn block_names<'a>(body: &[Instruction<Span<'a>>], names: &mut Vec<&'a str>) {
+    for instruction in body {
+        match instruction {
+            Instruction::If { body, alt, .. } => {
+                block_names(body, names);
+                block_names(alt, names);
+            }
+            Instruction::Iter { subject, .. } => {
+                let name = *subject.span().fragment();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            Instruction::Block { name, body } => {
+                block_names(body, names);
+                if !names.contains(name.fragment()) {
+                    names.push(name.fragment());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// generate a `.d.ts` module describing the code generated for a template
+pub fn declarations(input: &[Instruction<Span>], format: OutputFormat, filename: &str) -> String {
+    let schema = Schema::from_tree(input);
+
+    let mut names = vec![];
+    block_names(input, &mut names);
+    let blocks = names
+        .iter()
+        .map(|name| format!("\n  '{}': TemplateFunction;", templates::escape_path(name)))
+        .collect::<String>();
+
+    let types = format!(
+        "export interface Context {}\n\n{}\n\nexport interface Blocks {{{}\n}}",
+        object_type(&schema.context),
+        RUNTIME_TYPES,
+        blocks
+    );
+    let header = format!("// Generated by benchpress from `{filename}`. Do not edit.");
+
+    match format {
+        OutputFormat::Umd | OutputFormat::CommonJs => format!(
+            "{header}
+
+declare function compiled(
+  helpers: compiled.Helpers,
+  context: compiled.Context,
+  guard: compiled.Guard,
+  iter: compiled.Iter,
+  helper: compiled.Helper,
+): unknown;
+
+declare namespace compiled {{
+  {}
+
+  export const blocks: Blocks;
+}}
+
+export = compiled;
+",
+            // without trailing whitespace on blank lines
+            indent(&types, 2).replace("\n  \n", "\n\n"),
+        ),
+        OutputFormat::Esm => format!(
+            "{header}
+
+{types}
+
+declare function compiled(
+  helpers: Helpers,
+  context: Context,
+  guard: Guard,
+  iter: Iter,
+  helper: Helper,
+): unknown;
+
+declare namespace compiled {{
+  export const blocks: Blocks;
+}}
+
+export const blocks: Blocks;
+
+export default compiled;
+"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CompileOptions;
+    use pretty_assertions::assert_eq;
+
+    fn compile(source: &str, format: OutputFormat) -> String {
+        let options = CompileOptions {
+            format,
+            declarations: true,
+            ..Default::default()
+        };
+        crate::compile_with_diagnostics(source, "page.tpl", &options)
+            .declarations
+            .unwrap()
+    }
+
+    /// just the `Context` interface
+    fn context(source: &str) -> String {
+        let out = compile(source, OutputFormat::Esm);
+        let start = out.find("export interface Context").unwrap();
+        let end = out.find("\n\nexport type Collection").unwrap();
+        out[start..end].to_string()
+    }
+
+    #[test]
+    fn test_context() {
+        assert_eq!(context("no paths"), "export interface Context {}");
+        assert_eq!(
+            context(
+                "{title}{{{ if !user.loggedIn }}}{user.first-name}{{{ end }}}{{{ if x }}}{{{ end }}}\
+                 {{{ each posts }}}{./title}{{{ each ./tags }}}{@value}{{{ end }}}{{{ end }}}\
+                 {posts.length}{@root.title}{join(list, \", \")}"
+            ),
+            "export interface Context {
+  list: unknown;
+  posts: Collection<{
+    tags: Collection<unknown>;
+    title: unknown;
+  }> & {
+    length: unknown;
+  };
+  title: unknown;
+  user: {
+    \"first-name\": unknown;
+    loggedIn?: unknown;
+  };
+  x?: unknown;
+}"
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        let out = compile(
+            "{{{ each a }}}{{{ each ./b }}}{{{ end }}}{{{ end }}}\
+             {{{ if c }}}{{{ each c }}}{{{ end }}}{{{ end }}}{{{ each a }}}{{{ end }}}\
+             {{{ block \"main\" }}}{{{ each d }}}{{{ end }}}{{{ end }}}",
+            OutputFormat::Esm,
+        );
+        assert!(out.contains(
+            "export interface Blocks {
+  'a': TemplateFunction;
+  'c': TemplateFunction;
+  'd': TemplateFunction;
+  'main': TemplateFunction;
+}"
+        ));
+
+        // the same names as the generated code
+        let code = crate::compile(
+            "{{{ each a }}}{{{ end }}}{{{ block \"main\" }}}{{{ each d }}}{{{ end }}}{{{ end }}}",
+            "page.tpl",
+            &Default::default(),
+        );
+        assert!(code.contains("'d': function d("));
+        assert!(code.contains("'main': function main("));
+        assert!(code.contains("compiled.blocks['main'](helpers, context, guard, iter, helper)"));
+
+        let out = compile("", OutputFormat::Esm);
+        assert!(out.contains("export interface Blocks {\n}"));
+    }
+
+    #[test]
+    fn test_format() {
+        let esm = compile("{a}", OutputFormat::Esm);
+        assert!(esm.starts_with("// Generated by benchpress from `page.tpl`. Do not edit.\n"));
+        assert!(esm.contains("\n  context: Context,\n"));
+        assert!(esm.ends_with("export const blocks: Blocks;\n\nexport default compiled;\n"));
+
+        let umd = compile("{a}", OutputFormat::Umd);
+        assert!(umd.contains("\n  context: compiled.Context,\n"));
+        assert!(umd.contains("declare namespace compiled {\n  export interface Context {\n"));
+        assert!(umd.ends_with("  export const blocks: Blocks;\n}\n\nexport = compiled;\n"));
+        assert!(!umd.contains(" \n"));
+        assert_eq!(compile("{a}", OutputFormat::CommonJs), umd);
+    }
+}
@@ -1,20 +1,29 @@
 use crate::{
-    generate::templates,
+    generate::{
+        source_map::Marks,
+        templates,
+    },
     parse::{
         expression::Expression,
         tree::Instruction,
+        FileInfo,
         Span,
     },
+    OutputFormat,
 };
 
 use std::collections::HashSet;
 
 /// generate code for a body
 /// recursively applied to If and Iter children
+///
+/// the code for each instruction is marked with its template location,
+/// which is a no-op unless a source map was requested
 fn gen_body<'a>(
     entry: Vec<Instruction<Span<'a>>>,
     top: bool,
     block_names: &mut HashSet<&'a str>,
+    marks: &mut Marks<'a>,
 ) -> (String, Vec<String>) {
     if entry.is_empty() {
         return ("\"\"".to_string(), Vec::new());
@@ -26,11 +35,14 @@ fn gen_body<'a>(
         .into_iter()
         .map(|elem| match elem {
             // output a string literal in JS
-            Instruction::Text(value) => json::stringify(json::from(*value.fragment())),
+            Instruction::Text(value) => {
+                marks.mark(value, json::stringify(json::from(*value.fragment())))
+            }
             // output a ternary in JS
             Instruction::If { subject, body, alt } => {
-                let (b, mut b_blocks) = gen_body(body, top, block_names);
-                let (a, mut a_blocks) = gen_body(alt, top, block_names);
+                let span = subject.span();
+                let (b, mut b_blocks) = gen_body(body, top, block_names, marks);
+                let (a, mut a_blocks) = gen_body(alt, top, block_names, marks);
 
                 blocks.append(&mut b_blocks);
                 blocks.append(&mut a_blocks);
@@ -42,7 +54,10 @@ fn gen_body<'a>(
                     (subject, false)
                 };
 
-                templates::if_else(neg, &templates::expression(expr), &b, &a)
+                marks.mark(
+                    span,
+                    templates::if_else(neg, &templates::expression(expr), &b, &a),
+                )
             }
             // output a call to `iter` in JS
             Instruction::Iter {
@@ -51,18 +66,20 @@ fn gen_body<'a>(
                 body,
                 alt,
             } => {
-                let subject_raw = *subject.span().fragment();
+                let span = subject.span();
+                let subject_raw = *span.fragment();
 
                 let block = templates::iter(
                     depth,
                     &templates::expression(subject),
-                    &gen_body(body, false, &mut HashSet::new()).0,
-                    &gen_body(alt, false, &mut HashSet::new()).0,
+                    &gen_body(body, false, &mut HashSet::new(), marks).0,
+                    &gen_body(alt, false, &mut HashSet::new(), marks).0,
                 );
+                let block = marks.mark(span, block);
 
                 // if top level, pull out into a block method
                 if top && !block_names.contains(subject_raw) {
-                    let out = templates::block_call(subject_raw);
+                    let out = marks.mark(span, templates::block_call(subject_raw));
                     blocks.push(templates::block(subject_raw, &block));
                     block_names.insert(subject_raw);
 
@@ -72,11 +89,31 @@ fn gen_body<'a>(
                 }
             }
             // generate an escape call and guard expression
-            Instruction::InterpEscaped(subject) => {
-                format!("{}({})", templates::ESCAPE, templates::expression(subject))
-            }
+            Instruction::InterpEscaped(subject) => marks.mark(
+                subject.span(),
+                format!("{}({})", templates::ESCAPE, templates::expression(subject)),
+            ),
             // generate a guard expression
-            Instruction::InterpRaw(subject) => templates::expression(subject).into(),
+            Instruction::InterpRaw(subject) => {
+                marks.mark(subject.span(), templates::expression(subject).into())
+            }
+            // call the runtime include helper
+            Instruction::Include { name } => marks.mark(name, templates::include(name.fragment())),
+            // if top level, pull out into a block method, so it can be rendered alone
+            Instruction::Block { name, body } => {
+                let name_raw = *name.fragment();
+                let (code, mut body_blocks) = gen_body(body, top, block_names, marks);
+                blocks.append(&mut body_blocks);
+
+                if top && !block_names.contains(name_raw) {
+                    blocks.push(templates::block(name_raw, &code));
+                    block_names.insert(name_raw);
+
+                    marks.mark(name, templates::block_call(name_raw))
+                } else {
+                    code
+                }
+            }
         })
         .filter(|x| !x.is_empty())
         .collect::<Vec<String>>();
@@ -84,9 +121,54 @@ fn gen_body<'a>(
     (templates::concat(&output), blocks)
 }
 
+/// names of the templates included at runtime, in order of first appearance
+pub fn include_names<'a>(body: &[Instruction<Span<'a>>], names: &mut Vec<&'a str>) {
+    for instruction in body {
+        match instruction {
+            Instruction::If { body, alt, .. } | Instruction::Iter { body, alt, .. } => {
+                include_names(body, names);
+                include_names(alt, names);
+            }
+            Instruction::Block { body, .. } => include_names(body, names),
+            Instruction::Include { name } if !names.contains(name.fragment()) => {
+                names.push(name.fragment());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn wrap(body: &str, blocks: &[String], includes: &[String], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Umd => templates::wrapper(body, blocks, includes),
+        OutputFormat::Esm => templates::esm_wrapper(body, blocks, includes),
+        OutputFormat::CommonJs => templates::commonjs_wrapper(body, blocks, includes),
+    }
+}
+
+fn includes(input: &[Instruction<Span>]) -> Vec<String> {
+    let mut names = vec![];
+    include_names(input, &mut names);
+    names.into_iter().map(String::from).collect()
+}
+
 /// generate code from parser output
-pub fn generate(input: Vec<Instruction<Span>>) -> String {
-    let (body, blocks) = gen_body(input, true, &mut HashSet::new());
+pub fn generate(input: Vec<Instruction<Span>>, format: OutputFormat) -> String {
+    let includes = includes(&input);
+    let (body, blocks) = gen_body(input, true, &mut HashSet::new(), &mut Marks::default());
+
+    wrap(&body, &blocks, &includes, format)
+}
+
+/// generate code from parser output, along with a source map of it as JSON
+pub fn generate_with_source_map(
+    input: Vec<Instruction<Span>>,
+    format: OutputFormat,
+    file: FileInfo,
+) -> (String, String) {
+    let includes = includes(&input);
+    let mut marks = Marks::enabled();
+    let (body, blocks) = gen_body(input, true, &mut HashSet::new(), &mut marks);
 
-    templates::wrapper(&body, &blocks)
+    marks.finish(&wrap(&body, &blocks, &includes, format), file)
 }
@@ -0,0 +1,397 @@
+//! Source Map v3 generation
+//!
+//! When a source map is requested, the generator prefixes the code for each
+//! instruction with a marker holding the index of its template location.
+//! Once the whole module is generated, the markers are stripped and their
+//! positions in the output become the mappings.
+//!
+//! Included templates are inlined with spans into their own sources,
+//! so each template the code came from is listed as a separate source.
+//!
+//! Marker characters can't otherwise appear in generated code: template text
+//! and string literals are JSON-encoded, which escapes control characters,
+//! and paths and names only contain printable characters.
+
+use crate::parse::{
+    FileInfo,
+    Span,
+    SpanExt,
+};
+
+const MARK_START: char = '\u{0}';
+const MARK_END: char = '\u{1}';
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Template locations recorded while generating code
+#[derive(Debug, Default)]
+pub struct Marks<'a> {
+    enabled: bool,
+    spans: Vec<Span<'a>>,
+}
+
+/// A generated position and the template position it came from,
+/// all 0-based with columns counted in UTF-16 code units
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Mapping {
+    gen_line: usize,
+    gen_column: usize,
+    /// index into `sources`
+    source: usize,
+    src_line: usize,
+    src_column: usize,
+}
+
+impl<'a> Marks<'a> {
+    pub fn enabled() -> Self {
+        Marks {
+            enabled: true,
+            spans: Vec::new(),
+        }
+    }
+
+    /// prefix `code` with a marker for the template location `span`
+    pub fn mark(&mut self, span: Span<'a>, code: String) -> String {
+        if !self.enabled || code.is_empty() {
+            return code;
+        }
+
+        let index = self.spans.len();
+        self.spans.push(span);
+        format!("{MARK_START}{index}{MARK_END}{code}")
+    }
+
+    /// strip the markers from generated code,
+    /// returning the clean code and a source map as JSON
+    pub fn finish(self, code: &str, file: FileInfo) -> (String, String) {
+        // the main template first, then included templates as they appear
+        let mut sources = vec![file];
+        for span in &self.spans {
+            if !sources.contains(&span.extra) {
+                sources.push(span.extra);
+            }
+        }
+
+        let mut output = String::with_capacity(code.len());
+        let mut mappings = Vec::with_capacity(self.spans.len());
+        let mut gen_line = 0;
+        let mut gen_column = 0;
+
+        let mut chars = code.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                MARK_START => {
+                    let index: String = chars.by_ref().take_while(|&c| c != MARK_END).collect();
+                    let span = self.spans[index.parse::<usize>().unwrap()];
+                    let (line, column) = source_position(span);
+
+                    mappings.push(Mapping {
+                        gen_line,
+                        gen_column,
+                        source: sources.iter().position(|x| *x == span.extra).unwrap(),
+                        src_line: line,
+                        src_column: column,
+                    });
+                }
+                '\n' => {
+                    output.push(c);
+                    gen_line += 1;
+                    gen_column = 0;
+                }
+                _ => {
+                    output.push(c);
+                    gen_column += c.len_utf16();
+                }
+            }
+        }
+
+        let map = json::object! {
+            version: 3,
+            sources: sources.iter().map(|x| x.filename).collect::<Vec<_>>(),
+            sourcesContent: sources.iter().map(|x| x.full_source).collect::<Vec<_>>(),
+            names: [],
+            mappings: encode_mappings(&mappings),
+        };
+
+        (output, map.dump())
+    }
+}
+
+/// 0-based line and UTF-16 column of the start of a span
+fn source_position(span: Span) -> (usize, usize) {
+    let line = span.get_line();
+    let column = line[..(span.get_column() - 1)].encode_utf16().count();
+
+    (span.location_line() as usize - 1, column)
+}
+
+/// append a base64 VLQ encoded number
+fn vlq(out: &mut String, value: isize) {
+    // sign is stored in the lowest bit
+    let mut value = if value < 0 {
+        ((-value as usize) << 1) | 1
+    } else {
+        (value as usize) << 1
+    };
+
+    loop {
+        let mut digit = value & 0b11111;
+        value >>= 5;
+        if value > 0 {
+            // continuation bit
+            digit |= 0b100000;
+        }
+        out.push(BASE64[digit] as char);
+
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+/// encode the `mappings` field, with each segment relative to the previous one
+fn encode_mappings(mappings: &[Mapping]) -> String {
+    let mut out = String::new();
+    let mut line = 0;
+    let mut prev_gen_column = 0;
+    let mut prev_source = 0;
+    let mut prev_src_line = 0;
+    let mut prev_src_column = 0;
+
+    for (i, mapping) in mappings.iter().enumerate() {
+        if mapping.gen_line > line {
+            out.push_str(&";".repeat(mapping.gen_line - line));
+            line = mapping.gen_line;
+            prev_gen_column = 0;
+        } else if i > 0 {
+            out.push(',');
+        }
+
+        vlq(
+            &mut out,
+            mapping.gen_column as isize - prev_gen_column as isize,
+        );
+        vlq(&mut out, mapping.source as isize - prev_source as isize);
+        vlq(&mut out, mapping.src_line as isize - prev_src_line as isize);
+        vlq(
+            &mut out,
+            mapping.src_column as isize - prev_src_column as isize,
+        );
+
+        prev_gen_column = mapping.gen_column;
+        prev_source = mapping.source;
+        prev_src_line = mapping.src_line;
+        prev_src_column = mapping.src_column;
+    }
+
+    out
+}
+
+/// a `sourceMappingURL` comment with the source map inlined as a data URL
+#[cfg(any(target_arch = "wasm32", test))]
+pub fn inline_comment(map: &str) -> String {
+    let mut encoded = String::with_capacity(map.len() / 3 * 4 + 4);
+    for chunk in map.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i)) as usize & 0b111111] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    format!("\n//# sourceMappingURL=data:application/json;charset=utf-8;base64,{encoded}\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::test::sp;
+    use nom::Slice;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_vlq() {
+        let encode = |value| {
+            let mut out = String::new();
+            vlq(&mut out, value);
+            out
+        };
+
+        assert_eq!(encode(0), "A");
+        assert_eq!(encode(1), "C");
+        assert_eq!(encode(-1), "D");
+        assert_eq!(encode(15), "e");
+        assert_eq!(encode(16), "gB");
+        assert_eq!(encode(123), "2H");
+        assert_eq!(encode(-2048), "hgE");
+    }
+
+    #[test]
+    fn test_finish() {
+        let src = sp("é {a}\n  {{{ if b }}}");
+        let mut marks = Marks::enabled();
+        let first = marks.mark(src.slice(..3), "'é '".to_string());
+        let second = marks.mark(src.slice(16..17), "(b ? '' : '')".to_string());
+        let code = format!("return {first} +\n  {second};");
+
+        let (code, map) = marks.finish(&code, src.extra);
+        assert_eq!(code, "return 'é ' +\n  (b ? '' : '');");
+
+        let map = json::parse(&map).unwrap();
+        assert_eq!(map["version"], 3);
+        assert_eq!(map["sources"][0], "<test>");
+        assert_eq!(map["sourcesContent"][0], "é {a}\n  {{{ if b }}}");
+        // [7, 0, 0, 0] then next line [2, 0, 1, 9]
+        assert_eq!(map["mappings"], "OAAA;EACS");
+    }
+
+    /// decode the `mappings` field back into absolute positions
+    fn decode(mappings: &str) -> Vec<Mapping> {
+        let mut out = vec![];
+        let mut values = [0isize; 4];
+
+        for (gen_line, line) in mappings.split(';').enumerate() {
+            values[0] = 0;
+            for segment in line.split(',').filter(|s| !s.is_empty()) {
+                let (mut i, mut shift, mut acc) = (0, 0, 0usize);
+                for c in segment.bytes() {
+                    let digit = BASE64.iter().position(|&b| b == c).unwrap();
+                    acc |= (digit & 0b11111) << shift;
+                    shift += 5;
+                    if digit & 0b100000 == 0 {
+                        let value = (acc >> 1) as isize;
+                        values[i] += if acc & 1 == 1 { -value } else { value };
+                        (i, shift, acc) = (i + 1, 0, 0);
+                    }
+                }
+
+                out.push(Mapping {
+                    gen_line,
+                    gen_column: values[0] as usize,
+                    source: values[1] as usize,
+                    src_line: values[2] as usize,
+                    src_column: values[3] as usize,
+                });
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    fn test_generate() {
+        let source = "<p>\n  {{{ if ok }}}{name}{{{ end }}}\n</p>";
+        let options = crate::CompileOptions {
+            source_map: true,
+            ..Default::default()
+        };
+        let output = crate::compile_with_diagnostics(source, "page.tpl", &options);
+        let map = json::parse(output.source_map.as_deref().unwrap()).unwrap();
+        assert_eq!(map["sources"][0], "page.tpl");
+
+        // the markers are gone, leaving the same code as without a source map
+        let plain = crate::compile(source, "page.tpl", &Default::default());
+        assert_eq!(output.code, plain);
+
+        let position = |needle: &str| {
+            output
+                .code
+                .lines()
+                .enumerate()
+                .find_map(|(line, text)| text.find(needle).map(|column| (line, column)))
+                .unwrap()
+        };
+        let mappings = decode(map["mappings"].as_str().unwrap());
+        let lookup = |(line, column)| {
+            mappings
+                .iter()
+                .find(|m| m.gen_line == line && m.gen_column == column)
+                .map(|m| (m.src_line, m.src_column))
+        };
+
+        assert_eq!(lookup(position("\"<p>\\n  \"")), Some((0, 0)));
+        assert_eq!(
+            lookup(position("(guard((context != null) ? context['ok']")),
+            Some((1, 9))
+        );
+        assert_eq!(lookup(position("__escape(guard")), Some((1, 16)));
+        assert_eq!(lookup(position("\"\\n</p>\"")), Some((1, 32)));
+    }
+
+    #[test]
+    fn test_include() {
+        let options = crate::CompileOptions {
+            source_map: true,
+            resolver: Some(crate::Resolver::new(|_| Some("<b>{name}</b>".to_string()))),
+            ..Default::default()
+        };
+        let output = crate::compile_with_diagnostics(
+            "<p>\n  {{{ include \"bold\" }}}</p>",
+            "page.tpl",
+            &options,
+        );
+        let map = json::parse(output.source_map.as_deref().unwrap()).unwrap();
+        assert_eq!(map["sources"], json::array!["page.tpl", "bold"]);
+        assert_eq!(
+            map["sourcesContent"],
+            json::array!["<p>\n  {{{ include \"bold\" }}}</p>", "<b>{name}</b>"]
+        );
+
+        let sources = decode(map["mappings"].as_str().unwrap())
+            .iter()
+            .map(|m| (m.source, m.src_line, m.src_column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            vec![(0, 0, 0), (1, 0, 0), (1, 0, 4), (1, 0, 9), (0, 1, 24)]
+        );
+    }
+
+    /// source maps don't change the code generated for any of the test templates
+    #[test]
+    fn test_templates() {
+        let source_dir =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/templates/source");
+        let options = crate::CompileOptions {
+            source_map: true,
+            ..Default::default()
+        };
+
+        for entry in std::fs::read_dir(source_dir).unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let filename = path.to_string_lossy();
+
+            let output = crate::compile_with_diagnostics(&source, &filename, &options);
+            let plain = crate::compile_with_diagnostics(&source, &filename, &Default::default());
+            assert_eq!(output.code, plain.code, "{filename}");
+            assert!(!output.code.contains(MARK_START), "{}", filename);
+            assert_eq!(output.source_map.is_some(), !output.code.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut marks = Marks::default();
+        assert_eq!(marks.mark(sp("a"), "'a'".to_string()), "'a'");
+    }
+
+    #[test]
+    fn test_inline_comment() {
+        assert_eq!(
+            inline_comment("{}"),
+            "\n//# sourceMappingURL=data:application/json;charset=utf-8;base64,e30=\n"
+        );
+        assert!(inline_comment("abc").ends_with("base64,YWJj\n"));
+        assert!(inline_comment("abcd").ends_with("base64,YWJjZA==\n"));
+    }
+}
@@ -5,6 +5,7 @@ pub const CONTEXT: &str = "context";
 pub const HELPERS: &str = "helpers";
 pub const HELPER: &str = "helper";
 pub const ESCAPE: &str = "__escape";
+pub const INCLUDE: &str = "__include";
 pub const GUARD: &str = "guard";
 pub const KEY: &str = "key";
 pub const VALUE: &str = "value";
@@ -12,8 +13,11 @@ pub const GUARD_VALUE: &str = "guard(value)";
 pub const INDEX: &str = "index";
 pub const LENGTH: &str = "length";
 pub const BLOCKS: &str = "compiled.blocks";
+pub const INCLUDES: &str = "compiled.includes";
 pub const FIRST: &str = "index === 0";
 pub const LAST: &str = "index === length - 1";
+pub const ODD: &str = "(index % 2 === 1)";
+pub const EVEN: &str = "(index % 2 === 0)";
 pub const TRUE: &str = "true";
 pub const FALSE: &str = "false";
 pub const RUNTIME_PARAMS: &str = "helpers, context, guard, iter, helper";
@@ -24,6 +28,12 @@ pub fn key_i(i: u32) -> String {
     format!("{KEY}{i}")
 }
 
+/// value with an indexed suffix
+/// for nested scoped
+pub fn value_i(i: u32) -> String {
+    format!("{VALUE}{i}")
+}
+
 /// indent each line (except the first) by a given number of spaces
 pub fn indent(source: &str, amount: usize) -> String {
     let joiner = format!("\n{}", " ".repeat(amount));
@@ -32,20 +42,14 @@ pub fn indent(source: &str, amount: usize) -> String {
 
 /// block method template
 pub fn block(name: &str, body: &str) -> String {
-    let mut first = true;
-    let safe_name: String = name
-        .chars()
-        .filter(|&x| {
-            x.is_alphabetic()
-                || x == '_'
-                || if first {
-                    first = false;
-                    false
-                } else {
-                    x.is_numeric()
-                }
-        })
-        .collect();
+    // identifiers can't start with a digit,
+    // and an empty name leaves an anonymous function
+    let mut safe_name = String::new();
+    for x in name.chars() {
+        if x.is_alphabetic() || x == '_' || (x.is_numeric() && !safe_name.is_empty()) {
+            safe_name.push(x);
+        }
+    }
 
     format!(
         "
@@ -74,8 +78,33 @@ pub fn block_call(name: &str) -> String {
     format!("{}['{}']({})", BLOCKS, escape_path(name), RUNTIME_PARAMS)
 }
 
+/// call to the runtime include helper
+pub fn include(name: &str) -> String {
+    format!(
+        "{}({}, {}, '{}', [{}])",
+        HELPER,
+        CONTEXT,
+        HELPERS,
+        INCLUDE,
+        json::stringify(name)
+    )
+}
+
+/// names of the templates included at runtime, so they can be loaded first
+fn includes_assignment(includes: &[String]) -> String {
+    if includes.is_empty() {
+        return String::new();
+    }
+
+    let names = includes
+        .iter()
+        .map(|name| json::stringify(name.as_str()))
+        .join(", ");
+    format!("\n{INCLUDES} = [{names}];")
+}
+
 /// module wrapper template
-pub fn wrapper(body: &str, blocks: &[String]) -> String {
+pub fn wrapper(body: &str, blocks: &[String], includes: &[String]) -> String {
     let blocks_str = indent(&blocks.join(",\n"), 4);
 
     format!(
@@ -95,7 +124,7 @@ pub fn wrapper(body: &str, blocks: &[String]) -> String {
 
   {} = {{
     {}
-  }};
+  }};{}
 
   return compiled;
 }})
@@ -108,7 +137,69 @@ pub fn wrapper(body: &str, blocks: &[String]) -> String {
         CONTEXT,
         indent(body, 6),
         BLOCKS,
-        blocks_str
+        blocks_str,
+        indent(&includes_assignment(includes), 2)
+    )
+}
+
+/// compiled function shared by the ES module and CommonJS wrappers
+fn compiled_function(body: &str) -> String {
+    format!(
+        "function compiled({}) {{
+  var {} = {}.{};
+  var {} = {};
+  return {};
+}}",
+        RUNTIME_PARAMS,
+        ESCAPE,
+        HELPERS,
+        ESCAPE,
+        VALUE,
+        CONTEXT,
+        indent(body, 4)
+    )
+}
+
+/// ES module wrapper template
+pub fn esm_wrapper(body: &str, blocks: &[String], includes: &[String]) -> String {
+    let blocks_str = indent(&blocks.join(",\n"), 2);
+
+    format!(
+        "
+{}
+
+export const blocks = {{
+  {}
+}};
+{} = blocks;{}
+
+export default compiled;
+",
+        compiled_function(body),
+        blocks_str,
+        BLOCKS,
+        includes_assignment(includes)
+    )
+}
+
+/// CommonJS module wrapper template
+pub fn commonjs_wrapper(body: &str, blocks: &[String], includes: &[String]) -> String {
+    let blocks_str = indent(&blocks.join(",\n"), 2);
+
+    format!(
+        "
+{}
+
+{} = {{
+  {}
+}};{}
+
+module.exports = compiled;
+",
+        compiled_function(body),
+        BLOCKS,
+        blocks_str,
+        includes_assignment(includes)
     )
 }
 
@@ -133,10 +224,12 @@ pub fn if_else(neg: bool, subject: &str, body: &str, alt: &str) -> String {
 /// iter template
 pub fn iter(depth: u32, subject: &str, body: &str, alt: &str) -> String {
     let key = key_i(depth);
+    let value = value_i(depth);
 
     format!(
         "iter({}, function each({}, {}, {}, {}) {{
   var {} = {};
+  var {} = {};
   return {};
 }}, function alt() {{
   return {};
@@ -145,14 +238,27 @@ pub fn iter(depth: u32, subject: &str, body: &str, alt: &str) -> String {
         key,
         INDEX,
         LENGTH,
-        VALUE,
+        value,
         KEY,
         key,
+        VALUE,
+        value,
         indent(body, 4),
         indent(alt, 4)
     )
 }
 
+/// most numbers a range can hold, since its bounds can come from data
+pub const MAX_RANGE: usize = 10_000;
+
+/// range template, an array of numbers from `start` to `end`,
+/// which is empty if it would be infinite or longer than [`MAX_RANGE`]
+pub fn range(start: &str, end: &str) -> String {
+    format!(
+        "(function (start, end) {{ var out = []; var length = Math.floor(end - start) + 1; if (isFinite(length) && length <= {MAX_RANGE}) {{ for (var i = 0; i < length; i += 1) {{ out.push(start + i); }} }} return out; }})(+{start}, +{end})"
+    )
+}
+
 /// create a string concatenation in JS
 pub fn concat(input: &[String]) -> String {
     input.join(" + \n")
@@ -160,6 +266,8 @@ pub fn concat(input: &[String]) -> String {
 
 use crate::parse::{
     expression::{
+        number,
+        unescape,
         Expression,
         Keyword,
     },
@@ -178,6 +286,9 @@ pub fn escape_path(input: &str) -> String {
             '"' => "\\\"".to_string(),
             '\'' => "\\\'".to_string(),
             '\\' => "\\\\".to_string(),
+            // never emit the control characters used as source map markers
+            '\0' => "\\x00".to_string(),
+            '\u{1}' => "\\x01".to_string(),
             _ => x.to_string(),
         })
         .collect()
@@ -187,15 +298,30 @@ pub fn escape_path(input: &str) -> String {
 ///
 /// convert `foo.bar.str.length` to
 /// (context != null && context.foo != null && context.foo.bar != null && context.foo.bar.str != null) ? context.foo.bar.str.length : null
+///
+/// Paths with `[expr]` subscripts step through a temporary instead,
+/// so each subscript is evaluated once, and only if the value before it exists.
 pub fn guard(input: Path<Span>) -> String {
+    let subscripted = input
+        .iter()
+        .any(|part| matches!(part, PathPart::Index(..) | PathPart::IndexDepth(..)));
+    if subscripted {
+        return guard_subscripted(input);
+    }
+
     let mut paths: Vec<String> = vec![CONTEXT.to_string()];
 
     for part in input {
         let prev = paths.last().unwrap();
-        let joined_path = format!("{}['{}']", prev, escape_path(part.inner()));
+        let joined_path = match part {
+            PathPart::Index(_, expr) | PathPart::IndexDepth(_, expr, _) => {
+                format!("{}[{}]", prev, expression((**expr).clone()))
+            }
+            _ => format!("{}['{}']", prev, escape_path(part.inner())),
+        };
         paths.push(joined_path);
 
-        if let PathPart::PartDepth(_, n) = part {
+        if let PathPart::PartDepth(_, n) | PathPart::IndexDepth(_, _, n) = part {
             let prev = paths.last().unwrap();
             let joined_path = format!("{prev}[key{n}]");
             paths.push(joined_path);
@@ -212,50 +338,83 @@ pub fn guard(input: Path<Span>) -> String {
     format!("{GUARD}(({exp}) ? {whole_path} : null)")
 }
 
-use std::borrow::Cow;
-
-/// Unescape contents of string literal
-fn unescape(input: &str) -> String {
-    // remove first and last quote
-    let input = &input[1..(input.len() - 1)];
-    let mut output = String::new();
-
-    let mut chars = input.chars();
-    while let Some(c) = chars.next() {
-        output.push(if c == '\\' {
-            match chars.next() {
-                Some(c) => match c {
-                    'r' => '\r',
-                    'n' => '\n',
-                    't' => '\t',
-                    _ => c,
-                },
-                _ => c,
+/// convert `users[fn()].name` to
+/// (function (object) { return (object != null && (object = object['users']) != null && (object = object[fn()]) != null) ? object['name'] : null; })(context)
+fn guard_subscripted(input: Path<Span>) -> String {
+    let mut steps = vec![];
+    for part in input {
+        steps.push(match part {
+            PathPart::Index(_, expr) | PathPart::IndexDepth(_, expr, _) => {
+                format!("[{}]", expression((**expr).clone()))
             }
-        } else {
-            c
+            _ => format!("['{}']", escape_path(part.inner())),
         });
+
+        if let PathPart::PartDepth(_, n) | PathPart::IndexDepth(_, _, n) = part {
+            steps.push(format!("[{}]", key_i(*n)));
+        }
     }
 
-    output
+    let last = steps.pop().unwrap_or_default();
+    let exp = std::iter::once("object != null".to_string())
+        .chain(
+            steps
+                .iter()
+                .map(|step| format!("(object = object{step}) != null")),
+        )
+        .join(" && ");
+
+    format!("{GUARD}((function (object) {{ return ({exp}) ? object{last} : null; }})({CONTEXT}))")
 }
 
+use std::borrow::Cow;
+
 /// create JS code for a given expression
 pub fn expression(input: Expression<Span>) -> Cow<str> {
     match input {
         Expression::StringLiteral(value) => {
             json::stringify(json::from(unescape(value.fragment()))).into()
         }
-        Expression::Keyword { keyword, .. } => match keyword {
-            Keyword::Root => CONTEXT.into(),
-            Keyword::Key => KEY.into(),
-            Keyword::Index => INDEX.into(),
-            Keyword::Value => GUARD_VALUE.into(),
-            Keyword::First => FIRST.into(),
-            Keyword::Last => LAST.into(),
-            Keyword::True => TRUE.into(),
-            Keyword::False => FALSE.into(),
-        },
+        Expression::NumberLiteral(value) => {
+            json::stringify(json::from(number(value.fragment()))).into()
+        }
+        Expression::KeywordDepth {
+            keyword: Keyword::Key,
+            depth,
+            ..
+        } => key_i(depth).into(),
+        Expression::KeywordDepth {
+            keyword: Keyword::Value,
+            depth,
+            ..
+        } => format!("{GUARD}({})", value_i(depth)).into(),
+        // only keys and values are kept for each depth
+        Expression::Keyword { keyword, .. } | Expression::KeywordDepth { keyword, .. } => {
+            match keyword {
+                Keyword::Root => CONTEXT.into(),
+                Keyword::Key => KEY.into(),
+                Keyword::Index => INDEX.into(),
+                Keyword::Value => GUARD_VALUE.into(),
+                Keyword::First => FIRST.into(),
+                Keyword::Last => LAST.into(),
+                Keyword::Odd => ODD.into(),
+                Keyword::Even => EVEN.into(),
+                Keyword::True => TRUE.into(),
+                Keyword::False => FALSE.into(),
+            }
+        }
+        Expression::Array { items, .. } => format!(
+            "[{}]",
+            items
+                .into_iter()
+                .map(expression)
+                .collect::<Vec<Cow<str>>>()
+                .join(", ")
+        )
+        .into(),
+        Expression::Range { start, end, .. } => {
+            range(&expression(*start), &expression(*end)).into()
+        }
         Expression::Path { path, .. } => guard(&path).into(),
         Expression::Helper { name, args, .. } | Expression::LegacyHelper { name, args, .. } => {
             let args_str = args
@@ -267,18 +426,51 @@ pub fn expression(input: Expression<Span>) -> Cow<str> {
             format!("{HELPER}({CONTEXT}, {HELPERS}, '{name}', [{args_str}])").into()
         }
         Expression::Negative { expr, .. } => format!("!{}", expression(*expr)).into(),
+        // counts the same items `iter` would loop over
+        Expression::Length { subject, .. } => {
+            format!("Object.keys(Object({})).length", expression(*subject)).into()
+        }
+        Expression::Empty { subject, .. } => format!(
+            "(Object.keys(Object({})).length === 0)",
+            expression(*subject)
+        )
+        .into(),
         Expression::Equ { lhs, rhs, .. } => {
             format!("({} == {})", expression(*lhs), expression(*rhs)).into()
         }
         Expression::Neq { lhs, rhs, .. } => {
             format!("({} != {})", expression(*lhs), expression(*rhs)).into()
         }
+        Expression::Lt { lhs, rhs, .. } => {
+            format!("({} < {})", expression(*lhs), expression(*rhs)).into()
+        }
+        Expression::Lte { lhs, rhs, .. } => {
+            format!("({} <= {})", expression(*lhs), expression(*rhs)).into()
+        }
+        Expression::Gt { lhs, rhs, .. } => {
+            format!("({} > {})", expression(*lhs), expression(*rhs)).into()
+        }
+        Expression::Gte { lhs, rhs, .. } => {
+            format!("({} >= {})", expression(*lhs), expression(*rhs)).into()
+        }
         Expression::And { lhs, rhs, .. } => {
             format!("({} && {})", expression(*lhs), expression(*rhs)).into()
         }
         Expression::Or { lhs, rhs, .. } => {
             format!("({} || {})", expression(*lhs), expression(*rhs)).into()
         }
+        Expression::Ternary {
+            condition,
+            then,
+            otherwise,
+            ..
+        } => format!(
+            "({} ? {} : {})",
+            expression(*condition),
+            expression(*then),
+            expression(*otherwise)
+        )
+        .into(),
     }
 }
 
@@ -318,12 +510,15 @@ mod tests {
   return 'every meta tag';
 }"
         );
+
+        assert!(block("1..5", "''").starts_with("'1..5': function (helpers"));
+        assert!(block("page2..3", "''").starts_with("'page2..3': function page23(helpers"));
     }
 
     #[test]
     fn wrapper_test() {
         assert_eq!(
-            wrapper("'stuff'", &[]),
+            wrapper("'stuff'", &[], &[]),
             "
 (function (factory) {
   if (typeof module === 'object' && module.exports) {
@@ -348,7 +543,11 @@ mod tests {
         );
 
         assert_eq!(
-            wrapper("'stuff'", &["one".to_string(), "two\nthree".to_string(),]),
+            wrapper(
+                "'stuff'",
+                &["one".to_string(), "two\nthree".to_string()],
+                &["partials/post".to_string(), "footer".to_string()]
+            ),
             "
 (function (factory) {
   if (typeof module === 'object' && module.exports) {
@@ -368,6 +567,7 @@ mod tests {
     two
     three
   };
+  compiled.includes = [\"partials/post\", \"footer\"];
 
   return compiled;
 })
@@ -375,6 +575,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn esm_wrapper_test() {
+        assert_eq!(
+            esm_wrapper(
+                "'stuff' +\n'more'",
+                &["one".to_string(), "two\nthree".to_string()],
+                &[]
+            ),
+            "
+function compiled(helpers, context, guard, iter, helper) {
+  var __escape = helpers.__escape;
+  var value = context;
+  return 'stuff' +
+    'more';
+}
+
+export const blocks = {
+  one,
+  two
+  three
+};
+compiled.blocks = blocks;
+
+export default compiled;
+"
+        );
+    }
+
+    #[test]
+    fn commonjs_wrapper_test() {
+        assert_eq!(
+            commonjs_wrapper("'stuff'", &["one".to_string()], &["footer".to_string()]),
+            "
+function compiled(helpers, context, guard, iter, helper) {
+  var __escape = helpers.__escape;
+  var value = context;
+  return 'stuff';
+}
+
+compiled.blocks = {
+  one
+};
+compiled.includes = [\"footer\"];
+
+module.exports = compiled;
+"
+        );
+    }
+
+    #[test]
+    fn include_test() {
+        assert_eq!(
+            include("partials/\"post\""),
+            "helper(context, helpers, '__include', [\"partials/\\\"post\\\"\"])"
+        );
+    }
+
     #[test]
     fn if_else_test() {
         assert_eq!(
@@ -397,8 +654,9 @@ mod tests {
     fn iter_test() {
         assert_eq!(
             iter(9, "stuff", "'for ' + \n'each one'", "'if ' + \n'none'"),
-            "iter(stuff, function each(key9, index, length, value) {
+            "iter(stuff, function each(key9, index, length, value9) {
   var key = key9;
+  var value = value9;
   return 'for ' + 
     'each one';
 }, function alt() {
@@ -422,7 +680,15 @@ mod tests {
         assert_eq!(
             guard(&[PathPart::Part(sp("foo\\bar"))]),
             "guard((context != null) ? context['foo\\\\bar'] : null)"
-        )
+        );
+
+        let key = Expression::Keyword {
+            span: sp("@key"),
+            keyword: Keyword::Key,
+        };
+        assert_eq!(guard(
+            &[PathPart::IndexDepth(sp("[@key]"), Box::new(key), 0), PathPart::Index(sp("[\"a b\"]"), Box::new(Expression::StringLiteral(sp("\"a b\""))))],
+        ), "guard((function (object) { return (object != null && (object = object[key]) != null && (object = object[key0]) != null) ? object[\"a b\"] : null; })(context))");
     }
 
     #[test]
@@ -434,6 +700,51 @@ mod tests {
             "\"stuff\\n \\\"about\\\" things\""
         );
 
+        assert_eq!(expression(Expression::NumberLiteral(sp("-007.50"))), "-7.5");
+        assert_eq!(expression(Expression::NumberLiteral(sp("42"))), "42");
+        assert_eq!(
+            expression(Expression::Gte {
+                span: sp("(a >= 1)"),
+                lhs: Box::new(Expression::Keyword {
+                    span: sp("@index"),
+                    keyword: Keyword::Index
+                }),
+                rhs: Box::new(Expression::NumberLiteral(sp("1"))),
+            }),
+            "(index >= 1)"
+        );
+        assert_eq!(
+            expression(Expression::Ternary {
+                span: sp("@first ? 1 : \"x\""),
+                condition: Box::new(Expression::Keyword {
+                    span: sp("@first"),
+                    keyword: Keyword::First
+                }),
+                then: Box::new(Expression::NumberLiteral(sp("1"))),
+                otherwise: Box::new(Expression::StringLiteral(sp("\"x\""))),
+            }),
+            "(index === 0 ? 1 : \"x\")"
+        );
+
+        assert_eq!(
+            expression(Expression::Range {
+                span: sp("1..3"),
+                start: Box::new(Expression::NumberLiteral(sp("1"))),
+                end: Box::new(Expression::NumberLiteral(sp("3"))),
+            }),
+            "(function (start, end) { var out = []; var length = Math.floor(end - start) + 1; if (isFinite(length) && length <= 10000) { for (var i = 0; i < length; i += 1) { out.push(start + i); } } return out; })(+1, +3)"
+        );
+        assert_eq!(
+            expression(Expression::Array {
+                span: sp("[\"a\", 1]"),
+                items: vec![
+                    Expression::StringLiteral(sp("\"a\"")),
+                    Expression::NumberLiteral(sp("1")),
+                ],
+            }),
+            "[\"a\", 1]"
+        );
+
         assert_eq!(
             expression(Expression::Path {
                 span: sp("thing"),
@@ -466,6 +777,28 @@ mod tests {
             "index === length - 1"
         );
 
+        assert_eq!(
+            expression(Expression::Negative {
+                span: sp("!@odd"),
+                expr: Box::new(Expression::Keyword {
+                    span: sp("@odd"),
+                    keyword: Keyword::Odd
+                })
+            }),
+            "!(index % 2 === 1)"
+        );
+
+        assert_eq!(
+            expression(Expression::Empty {
+                span: sp("@root.@empty"),
+                subject: Box::new(Expression::Keyword {
+                    span: sp("@root"),
+                    keyword: Keyword::Root
+                })
+            }),
+            "(Object.keys(Object(context)).length === 0)"
+        );
+
         assert_eq!(expression(Expression::Helper {
             span: sp("localeToHTML(userLang, defaultLang)"),
             name: sp("localeToHTML"),
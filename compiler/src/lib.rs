@@ -1,5 +1,34 @@
+mod diagnostic;
 mod generate;
 mod parse;
+mod render;
+mod schema;
+mod template;
+
+pub use diagnostic::{
+    Code,
+    Diagnostic,
+    Label,
+    Severity,
+};
+pub use parse::include::{
+    ResolveFn,
+    Resolver,
+};
+pub use render::{
+    HelperFn,
+    Helpers,
+};
+pub use schema::{
+    DataPath,
+    Field,
+    HelperCall,
+    Schema,
+};
+pub use template::{
+    DecodeError,
+    Template,
+};
 
 #[cfg(target_arch = "wasm32")]
 mod console {
@@ -53,10 +82,135 @@ mod console {
     };
 }
 
-#[cfg_attr(target_arch = "wasm32", wasm_bindgen::prelude::wasm_bindgen)]
-pub fn compile(source: &str, filename: &str) -> String {
-    console_error_panic_hook::set_once();
+/// Module format of generated code
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OutputFormat {
+    /// factory supporting both CommonJS `module.exports` and AMD `define`
+    #[default]
+    Umd,
+    /// native ES module, with `compiled` as the default export
+    Esm,
+    /// plain CommonJS, assigning `compiled` to `module.exports`
+    CommonJs,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "umd" => Ok(OutputFormat::Umd),
+            "esm" => Ok(OutputFormat::Esm),
+            "cjs" | "commonjs" => Ok(OutputFormat::CommonJs),
+            _ => Err(format!(
+                "unknown output format `{s}`, expected `umd`, `esm`, or `cjs`"
+            )),
+        }
+    }
+}
+
+/// Options controlling how a template is compiled
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct CompileOptions {
+    /// treat deprecated syntax as an error instead of a warning
+    pub strict: bool,
+    /// module format of the generated code
+    pub format: OutputFormat,
+    /// also generate a source map, mapping the code back to the template
+    pub source_map: bool,
+    /// also generate TypeScript declarations for the code
+    pub declarations: bool,
+    /// remove lines holding only block tokens, like `{{{ if x }}}`,
+    /// and whitespace from the output
+    pub standalone: bool,
+    /// inline included templates at compile time,
+    /// instead of rendering them at runtime
+    pub resolver: Option<Resolver>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl CompileOptions {
+    /// read options from a plain JS object, ignoring missing or invalid fields
+    /// except an unknown `format`, which throws an `Error`
+    fn from_js(options: &wasm_bindgen::JsValue) -> Result<Self, wasm_bindgen::JsValue> {
+        let get = |key: &str| {
+            if options.is_object() {
+                js_sys::Reflect::get(options, &key.into()).ok()
+            } else {
+                None
+            }
+        };
+
+        let format = match get("format").filter(|x| !x.is_undefined() && !x.is_null()) {
+            Some(format) => format
+                .as_string()
+                .ok_or_else(|| String::from("output format must be a string"))
+                .and_then(|x| x.parse())
+                .map_err(|e| js_sys::Error::new(&e))?,
+            None => OutputFormat::default(),
+        };
+
+        Ok(CompileOptions {
+            strict: get("strict").and_then(|x| x.as_bool()).unwrap_or(false),
+            format,
+            source_map: get("sourceMap").and_then(|x| x.as_bool()).unwrap_or(false),
+            declarations: get("declarations")
+                .and_then(|x| x.as_bool())
+                .unwrap_or(false),
+            standalone: get("standalone").and_then(|x| x.as_bool()).unwrap_or(false),
+            resolver: get("partials")
+                .filter(|x| x.is_object())
+                .map(|x| partials_resolver(&x.into())),
+        })
+    }
+}
+
+/// resolve templates from a plain JS object mapping names to sources
+#[cfg(target_arch = "wasm32")]
+fn partials_resolver(partials: &js_sys::Object) -> Resolver {
+    let sources = js_sys::Object::entries(partials)
+        .iter()
+        .filter_map(|entry| {
+            let entry = js_sys::Array::from(&entry);
+            Some((entry.get(0).as_string()?, entry.get(1).as_string()?))
+        })
+        .collect::<std::collections::HashMap<_, _>>();
+
+    Resolver::new(move |name| sources.get(name).cloned())
+}
+
+/// Generated code along with any warnings or errors produced while compiling
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompileOutput {
+    pub code: String,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Source Map v3 JSON, if requested with [`CompileOptions::source_map`]
+    /// and the template compiled
+    pub source_map: Option<String>,
+    /// `.d.ts` module, if requested with [`CompileOptions::declarations`]
+    /// and the template compiled
+    pub declarations: Option<String>,
+}
 
+impl CompileOutput {
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            code: self.code.as_str(),
+            diagnostics: self.diagnostics.iter().map(Diagnostic::to_json).collect::<Vec<_>>(),
+            sourceMap: self.source_map.as_deref(),
+            declarations: self.declarations.as_deref(),
+        }
+    }
+}
+
+/// Split a template into tokens, or `None` if it couldn't be tokenized.
+/// With `standalone`, lines holding only block tokens are removed.
+fn tokenize<'a>(
+    source: &'a str,
+    filename: &'a str,
+    standalone: bool,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Vec<parse::tokens::Token<parse::Span<'a>>>> {
     let program = parse::Span::new_extra(
         source,
         parse::FileInfo {
@@ -64,33 +218,453 @@ pub fn compile(source: &str, filename: &str) -> String {
             full_source: source,
         },
     );
-    let tokens = match nom::combinator::all_consuming(parse::tokens::tokens)(program) {
+    let result =
+        nom::combinator::all_consuming(|input| parse::tokens::tokens(input, diagnostics))(program);
+    let tokens = match result {
         Ok((_, tokens)) => tokens,
         Err(e) => {
-            console::error!("[benchpress] internal error: {e:?}");
-            console::error!("     --> {filename}");
-            console::error!("      | note: This is not an issue with your template, please report this issue on the benchpress Github page.\n");
+            let diagnostic = match &e {
+                nom::Err::Error(inner) | nom::Err::Failure(inner) => {
+                    Diagnostic::internal(filename, format!("{:?}", inner.code))
+                        .primary(inner.input, "failed to parse from here")
+                }
+                nom::Err::Incomplete(_) => Diagnostic::internal(filename, format!("{e:?}")),
+            };
+            diagnostics.push(diagnostic);
 
-            return String::new();
+            return None;
         }
     };
-    let fixed = parse::tree::fix_extra_tokens(tokens);
-    let mut iter = fixed.into_iter();
+
+    let tokens = parse::tree::fix_extra_tokens(tokens, diagnostics);
+    if standalone {
+        Some(parse::tokens::strip_standalone_lines(tokens))
+    } else {
+        Some(tokens)
+    }
+}
+
+/// Parse a template into instructions, or `None` if it couldn't be parsed.
+/// Included templates are inlined from `sources`.
+/// In strict mode, deprecated syntax is escalated to an error.
+fn parse<'a>(
+    source: &'a str,
+    filename: &'a str,
+    sources: &'a parse::include::Sources,
+    options: &CompileOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Vec<parse::tree::Instruction<parse::Span<'a>>>> {
+    let tokens = tokenize(source, filename, options.standalone, diagnostics)?;
+    let partials = parse::include::Partials::new(sources, options.standalone, diagnostics);
+    let mut iter = tokens.into_iter();
     let mut tree = vec![];
-    match parse::tree::tree(0, &[], &mut iter, &mut tree) {
+    match parse::tree::tree(0, &[], &[], &mut iter, &mut tree, diagnostics, &partials) {
         Ok(None) => {}
         Ok(Some(rest)) => {
-            console::error!("[benchpress] internal error: LeftOver({rest:?})");
-            console::error!("     --> {filename}");
-            console::error!("      | note: This is not an issue with your template, please report this issue on the benchpress Github page.\n");
+            let span = rest.span();
+            diagnostics.push(
+                Diagnostic::error(Code::UnexpectedToken, span, "unexpected token")
+                    .primary(
+                        span,
+                        "this token doesn't belong to a block, so nothing after it was compiled",
+                    )
+                    .help(format!("remove the token or escape it like `\\{span}`")),
+            );
         }
         Err(e) => {
-            console::error!("[benchpress] internal error: {e:?}");
-            console::error!("     --> {filename}");
-            console::error!("      | note: This is not an issue with your template, please report this issue on the benchpress Github page.\n");
+            diagnostics.push(
+                Diagnostic::error(Code::UnexpectedToken, e.span, "unexpected token")
+                    .primary(e.span, "this token can't be used here")
+                    .help(format!("remove the token or escape it like `\\{}`", e.span)),
+            );
+
+            return None;
+        }
+    }
+    parse::tree::check_block_names(&tree, diagnostics);
+
+    if options.strict {
+        for diagnostic in diagnostics.iter_mut() {
+            if diagnostic.code.is_deprecated_syntax() {
+                diagnostic.severity = Severity::Error;
+            }
+        }
+
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            return None;
+        }
+    }
+
+    Some(tree)
+}
+
+/// Parse a template into instructions, failing if any errors were found
+fn try_parse<'a>(
+    source: &'a str,
+    filename: &'a str,
+    sources: &'a parse::include::Sources,
+    options: &CompileOptions,
+) -> Result<Vec<parse::tree::Instruction<parse::Span<'a>>>, CompileError> {
+    let mut diagnostics = vec![];
+    match parse(source, filename, sources, options, &mut diagnostics) {
+        Some(tree) if !diagnostics.iter().any(Diagnostic::is_error) => Ok(tree),
+        _ => Err(CompileError { diagnostics }),
+    }
+}
+
+/// Compile a template, collecting diagnostics instead of printing them
+pub fn compile_with_diagnostics(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+) -> CompileOutput {
+    console_error_panic_hook::set_once();
+
+    let sources = parse::include::Sources::load(source, filename, options.resolver.as_ref());
+    let mut diagnostics = vec![];
+    let tree = parse(source, filename, &sources, options, &mut diagnostics);
+    let declarations = match &tree {
+        Some(tree) if options.declarations => Some(generate::declarations::declarations(
+            tree,
+            options.format,
+            filename,
+        )),
+        _ => None,
+    };
+    let (code, source_map) = match tree {
+        Some(tree) if options.source_map => {
+            let file = parse::FileInfo {
+                filename,
+                full_source: source,
+            };
+            let (code, map) =
+                generate::generator::generate_with_source_map(tree, options.format, file);
+            (code, Some(map))
+        }
+        Some(tree) => (generate::generator::generate(tree, options.format), None),
+        None => (String::new(), None),
+    };
+
+    CompileOutput {
+        code,
+        diagnostics,
+        source_map,
+        declarations,
+    }
+}
+
+/// Error produced by [`try_compile`] when a template fails to compile
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompileError {
+    /// every diagnostic produced, including warnings
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl CompileError {
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_error())
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors = self.errors().map(|d| d.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", errors.join("\n").trim_end())
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[cfg(target_arch = "wasm32")]
+impl CompileError {
+    /// a JS `Error` with `diagnostics` attached, plus
+    /// `filename`, `line`, and `column` of the first error
+    fn to_js(&self) -> wasm_bindgen::JsValue {
+        let error = js_sys::Error::new(&self.to_string());
+        let set = |key: &str, value: wasm_bindgen::JsValue| {
+            js_sys::Reflect::set(&error, &key.into(), &value).unwrap();
+        };
 
-            return String::new();
+        if let Some(first) = self.errors().next() {
+            set("filename", first.filename.as_str().into());
+            if let Some(label) = &first.primary {
+                set("line", label.line.into());
+                set("column", label.column.into());
+            }
         }
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .map(Diagnostic::to_json)
+            .collect::<Vec<_>>();
+        set(
+            "diagnostics",
+            js_sys::JSON::parse(&json::stringify(diagnostics)).unwrap(),
+        );
+
+        error.into()
+    }
+}
+
+fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Warning => console::warn!("{diagnostic}"),
+            Severity::Error => console::error!("{diagnostic}"),
+        }
+    }
+}
+
+/// Compile a template, printing any diagnostics to the console
+pub fn compile(source: &str, filename: &str, options: &CompileOptions) -> String {
+    let CompileOutput {
+        code, diagnostics, ..
+    } = compile_with_diagnostics(source, filename, options);
+    print_diagnostics(&diagnostics);
+
+    code
+}
+
+/// Compile a template, failing if any errors were found
+pub fn try_compile(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+) -> Result<String, CompileError> {
+    let CompileOutput {
+        code, diagnostics, ..
+    } = compile_with_diagnostics(source, filename, options);
+
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        Err(CompileError { diagnostics })
+    } else {
+        Ok(code)
+    }
+}
+
+/// Render a template with the given context, without compiling it to JS,
+/// failing if any errors were found
+pub fn render(
+    source: &str,
+    filename: &str,
+    context: &json::JsonValue,
+    helpers: &Helpers,
+    options: &CompileOptions,
+) -> Result<String, CompileError> {
+    let sources = parse::include::Sources::load(source, filename, options.resolver.as_ref());
+    let tree = try_parse(source, filename, &sources, options)?;
+
+    Ok(render::render(&tree, context, helpers))
+}
+
+/// Infer what data a template reads from its context and which helpers it calls,
+/// failing if any errors were found
+pub fn infer_schema(
+    source: &str,
+    filename: &str,
+    options: &CompileOptions,
+) -> Result<Schema, CompileError> {
+    let sources = parse::include::Sources::load(source, filename, options.resolver.as_ref());
+    let tree = try_parse(source, filename, &sources, options)?;
+
+    Ok(Schema::from_tree(&tree))
+}
+
+/// Compile a template, printing warnings to the console
+/// and throwing an `Error` if any errors were found.
+/// With `sourceMap`, the source map is inlined as a comment.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen(js_name = compile)]
+pub fn compile_js(
+    source: &str,
+    filename: &str,
+    options: wasm_bindgen::JsValue,
+) -> Result<String, wasm_bindgen::JsValue> {
+    let options = CompileOptions::from_js(&options)?;
+    let CompileOutput {
+        mut code,
+        diagnostics,
+        source_map,
+        ..
+    } = compile_with_diagnostics(source, filename, &options);
+    if let Some(map) = source_map {
+        code.push_str(&generate::source_map::inline_comment(&map));
+    }
+
+    let (errors, warnings): (Vec<_>, Vec<_>) =
+        diagnostics.iter().cloned().partition(Diagnostic::is_error);
+    print_diagnostics(&warnings);
+
+    if errors.is_empty() {
+        Ok(code)
+    } else {
+        Err(CompileError { diagnostics }.to_js())
+    }
+}
+
+/// Compile a template, returning `{ code, diagnostics, sourceMap, declarations }` to JS,
+/// or throwing an `Error` if the options are invalid
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen(js_name = compileWithDiagnostics)]
+pub fn compile_with_diagnostics_js(
+    source: &str,
+    filename: &str,
+    options: wasm_bindgen::JsValue,
+) -> Result<wasm_bindgen::JsValue, wasm_bindgen::JsValue> {
+    let options = CompileOptions::from_js(&options)?;
+    let output = compile_with_diagnostics(source, filename, &options)
+        .to_json()
+        .dump();
+
+    Ok(js_sys::JSON::parse(&output).unwrap())
+}
+
+/// Infer the JSON Schema of contexts a template can render,
+/// throwing an `Error` if any errors were found
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen(js_name = inferSchema)]
+pub fn infer_schema_js(
+    source: &str,
+    filename: &str,
+    options: wasm_bindgen::JsValue,
+) -> Result<wasm_bindgen::JsValue, wasm_bindgen::JsValue> {
+    let options = CompileOptions::from_js(&options)?;
+    let schema = infer_schema(source, filename, &options).map_err(|e| e.to_js())?;
+
+    Ok(js_sys::JSON::parse(&schema.to_json_schema().dump()).unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_strict() {
+        let source = "{{{ if abc }}} yes <!-- ENDIF abc -->";
+
+        let output = compile_with_diagnostics(source, "<test>", &CompileOptions::default());
+        assert!(!output.code.is_empty());
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].severity, Severity::Warning);
+
+        let strict = CompileOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let output = compile_with_diagnostics(source, "<test>", &strict);
+        assert_eq!(output.code, "");
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].code, Code::MixedTokens);
+        assert_eq!(output.diagnostics[0].severity, Severity::Error);
+
+        // other warnings are not affected by strict mode
+        let output = compile_with_diagnostics("{{{ if (a = b) }}}", "<test>", &strict);
+        assert!(!output.code.is_empty());
+        assert_eq!(output.diagnostics[0].code, Code::ProbableSyntaxError);
+        assert_eq!(output.diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_invalid_number() {
+        let source = format!("{{{}}}", "1".repeat(400));
+        let output = compile_with_diagnostics(&source, "<test>", &CompileOptions::default());
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].code, Code::InvalidNumber);
+        assert_eq!(output.diagnostics[0].severity, Severity::Error);
+
+        let source = format!(
+            "{{{{{{ if (count > {}) }}}}}}x{{{{{{ end }}}}}}",
+            "9".repeat(400)
+        );
+        assert!(try_compile(&source, "<test>", &Default::default()).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_block() {
+        let codes = |source| {
+            let output = compile_with_diagnostics(source, "<test>", &CompileOptions::default());
+            output
+                .diagnostics
+                .iter()
+                .map(|d| (d.code, d.severity))
+                .collect::<Vec<_>>()
+        };
+        let duplicate = vec![(Code::DuplicateBlock, Severity::Error)];
+
+        assert_eq!(
+            codes("{{{ block \"posts\" }}}B{{{ end }}}{{{ each posts }}}I{{{ end }}}"),
+            duplicate
+        );
+        assert_eq!(
+            codes("{{{ each posts }}}I{{{ end }}}{{{ if a }}}{{{ block \"posts\" }}}B{{{ end }}}{{{ end }}}"),
+            duplicate
+        );
+        assert_eq!(
+            codes("{{{ block \"x\" }}}1{{{ end }}}{{{ block \"x\" }}}2{{{ end }}}"),
+            duplicate
+        );
+        // iterations over the same subject are fine
+        assert_eq!(
+            codes("{{{ each posts }}}{{{ end }}}{{{ each posts }}}{{{ end }}}{{{ block \"x\" }}}{{{ each y }}}{{{ end }}}{{{ end }}}"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_unparenthesized_operators() {
+        let source = "{{{ if a && b || c }}}x{{{ end }}}{{{ if x == \"y\" && !z }}}y{{{ end }}}";
+        let output = compile_with_diagnostics(source, "<test>", &CompileOptions::default());
+        assert_eq!(output.diagnostics, vec![]);
+        assert!(output.code.contains("((guard("));
+    }
+
+    #[test]
+    fn test_format() {
+        let compile = |format| {
+            let options = CompileOptions {
+                format,
+                ..Default::default()
+            };
+            try_compile("{{{ each a }}}{@value}{{{ end }}}", "<test>", &options).unwrap()
+        };
+
+        assert!(compile(OutputFormat::Umd).contains("define(factory);"));
+        assert!(compile(OutputFormat::Esm).contains("export default compiled;"));
+        assert!(compile(OutputFormat::CommonJs).contains("module.exports = compiled;"));
+
+        assert_eq!("cjs".parse(), Ok(OutputFormat::CommonJs));
+        assert!("iife".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_try_compile() {
+        assert!(try_compile("{{{ if a }}}yes{{{ end }}}", "<test>", &Default::default()).is_ok());
+
+        let err = try_compile(
+            "{{{ if a }}}x{{{ else }}}y{{{ else }}}z{{{ end }}}",
+            "<test>",
+            &Default::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.errors().count(), 1);
+        let label = err.diagnostics[0].primary.as_ref().unwrap();
+        assert_eq!((label.line, label.column), (1, 27));
+        assert!(err
+            .to_string()
+            .starts_with("[benchpress] error: unexpected token"));
+
+        // warnings alone don't cause failure unless strict
+        let source = "{{{ if abc }}} yes <!-- ENDIF abc -->";
+        assert!(try_compile(source, "<test>", &Default::default()).is_ok());
+        assert!(try_compile(
+            source,
+            "<test>",
+            &CompileOptions {
+                strict: true,
+                ..Default::default()
+            }
+        )
+        .is_err());
     }
-    generate::generator::generate(tree)
 }
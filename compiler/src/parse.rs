@@ -7,6 +7,7 @@ use nom::{
 };
 
 pub mod expression;
+pub mod include;
 pub mod path;
 pub mod tokens;
 pub mod tree;
@@ -18,9 +19,24 @@ pub struct FileInfo<'a> {
 }
 pub type Span<'a> = nom_locate::LocatedSpan<&'a str, FileInfo<'a>>;
 
-trait SpanExt {
+/// Access to the source text of a span, however it's stored
+pub trait Fragment {
+    fn text(&self) -> &str;
+}
+impl Fragment for Span<'_> {
+    fn text(&self) -> &str {
+        self.fragment()
+    }
+}
+impl Fragment for &str {
+    fn text(&self) -> &str {
+        self
+    }
+}
+
+pub(crate) trait SpanExt {
     fn get_line(&self) -> &str;
-    fn get_line_column_padding(&self) -> (&str, usize, String);
+    fn get_line_column(&self) -> (&str, usize);
 }
 impl SpanExt for Span<'_> {
     fn get_line(&self) -> &str {
@@ -34,24 +50,13 @@ impl SpanExt for Span<'_> {
         )
     }
 
-    fn get_line_column_padding(&self) -> (&str, usize, String) {
+    /// get the line containing the span and the 1-based column,
+    /// counted in characters rather than bytes
+    fn get_line_column(&self) -> (&str, usize) {
         let line = self.get_line();
+        let column = line[..(self.get_column() - 1)].chars().count() + 1;
 
-        let mut column = 0;
-        let mut tabs = 0;
-        let mut spaces = 0;
-        for c in line[..(self.get_column() - 1)].chars() {
-            column += 1;
-
-            match c {
-                '\t' => {
-                    tabs += 1;
-                }
-                _ => spaces += 1,
-            }
-        }
-
-        (line, column, "\t".repeat(tabs) + &(" ".repeat(spaces)))
+        (line, column)
     }
 }
 
@@ -81,7 +86,7 @@ pub mod test {
     pub use _assert_eq_unspan as assert_eq_unspan;
     pub use pretty_assertions::assert_eq;
 
-    pub fn sp(s: &str) -> Span {
+    pub fn sp(s: &str) -> Span<'_> {
         Span::new_extra(
             s,
             FileInfo {
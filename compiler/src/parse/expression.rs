@@ -17,11 +17,15 @@ use nom::{
     character::complete::{
         alpha1,
         alphanumeric1,
+        char,
+        digit1,
+        multispace0,
     },
     combinator::{
         consumed,
         map,
         map_res,
+        not,
         opt,
         recognize,
     },
@@ -30,12 +34,12 @@ use nom::{
         many0_count,
         many1_count,
         separated_list0,
-        separated_list1,
     },
     sequence::{
         delimited,
         pair,
         preceded,
+        terminated,
         tuple,
     },
     IResult,
@@ -60,17 +64,51 @@ pub enum Keyword {
     True,
     // @false
     False,
+    // @odd, whether the 0-based @index is odd
+    Odd,
+    // @even, so the first item is even
+    Even,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Expression<S> {
     // "this \"works\" as you'd expect"
     StringLiteral(S),
+    // 1, -2.5
+    NumberLiteral(S),
     // @value, @first, etc
     Keyword {
         span: S,
         keyword: Keyword,
     },
+    // `@key` or `@value` of an enclosing loop,
+    // from the names in `each key, value in arr`
+    KeywordDepth {
+        span: S,
+        keyword: Keyword,
+        depth: u32,
+    },
+    // @length, a.b.@length
+    Length {
+        span: S,
+        subject: Box<Expression<S>>,
+    },
+    // @empty, a.b.@empty
+    Empty {
+        span: S,
+        subject: Box<Expression<S>>,
+    },
+    // [a, "b", 3]
+    Array {
+        span: S,
+        items: Vec<Expression<S>>,
+    },
+    // start..end, counting up by one and including `end`
+    Range {
+        span: S,
+        start: Box<Expression<S>>,
+        end: Box<Expression<S>>,
+    },
     // a.b.c.d
     Path {
         span: S,
@@ -105,6 +143,30 @@ pub enum Expression<S> {
         lhs: Box<Expression<S>>,
         rhs: Box<Expression<S>>,
     },
+    // a.b < 10
+    Lt {
+        span: S,
+        lhs: Box<Expression<S>>,
+        rhs: Box<Expression<S>>,
+    },
+    // a.b <= 10
+    Lte {
+        span: S,
+        lhs: Box<Expression<S>>,
+        rhs: Box<Expression<S>>,
+    },
+    // count > 0
+    Gt {
+        span: S,
+        lhs: Box<Expression<S>>,
+        rhs: Box<Expression<S>>,
+    },
+    // count >= limit
+    Gte {
+        span: S,
+        lhs: Box<Expression<S>>,
+        rhs: Box<Expression<S>>,
+    },
     // a.b && cond
     And {
         span: S,
@@ -117,25 +179,180 @@ pub enum Expression<S> {
         lhs: Box<Expression<S>>,
         rhs: Box<Expression<S>>,
     },
+    // cond ? "yes" : other.thing
+    Ternary {
+        span: S,
+        condition: Box<Expression<S>>,
+        then: Box<Expression<S>>,
+        otherwise: Box<Expression<S>>,
+    },
+}
+
+impl<S> Expression<S> {
+    /// convert the span type, keeping the structure
+    pub fn map_spans<T>(self, f: &mut impl FnMut(S) -> T) -> Expression<T> {
+        let boxed = |expr: Box<Expression<S>>, f: &mut _| Box::new(expr.map_spans(f));
+
+        match self {
+            Expression::StringLiteral(span) => Expression::StringLiteral(f(span)),
+            Expression::NumberLiteral(span) => Expression::NumberLiteral(f(span)),
+            Expression::Keyword { span, keyword } => Expression::Keyword {
+                span: f(span),
+                keyword,
+            },
+            Expression::KeywordDepth {
+                span,
+                keyword,
+                depth,
+            } => Expression::KeywordDepth {
+                span: f(span),
+                keyword,
+                depth,
+            },
+            Expression::Length { span, subject } => Expression::Length {
+                span: f(span),
+                subject: boxed(subject, f),
+            },
+            Expression::Empty { span, subject } => Expression::Empty {
+                span: f(span),
+                subject: boxed(subject, f),
+            },
+            Expression::Array { span, items } => Expression::Array {
+                span: f(span),
+                items: items.into_iter().map(|item| item.map_spans(f)).collect(),
+            },
+            Expression::Range { span, start, end } => Expression::Range {
+                span: f(span),
+                start: boxed(start, f),
+                end: boxed(end, f),
+            },
+            Expression::Path { span, path } => Expression::Path {
+                span: f(span),
+                path: path.into_iter().map(|part| part.map_spans(f)).collect(),
+            },
+            Expression::Negative { span, expr } => Expression::Negative {
+                span: f(span),
+                expr: boxed(expr, f),
+            },
+            Expression::Helper { span, name, args } => Expression::Helper {
+                span: f(span),
+                name: f(name),
+                args: args.into_iter().map(|arg| arg.map_spans(f)).collect(),
+            },
+            Expression::LegacyHelper { span, name, args } => Expression::LegacyHelper {
+                span: f(span),
+                name: f(name),
+                args: args.into_iter().map(|arg| arg.map_spans(f)).collect(),
+            },
+            Expression::Equ { span, lhs, rhs } => Expression::Equ {
+                span: f(span),
+                lhs: boxed(lhs, f),
+                rhs: boxed(rhs, f),
+            },
+            Expression::Neq { span, lhs, rhs } => Expression::Neq {
+                span: f(span),
+                lhs: boxed(lhs, f),
+                rhs: boxed(rhs, f),
+            },
+            Expression::Lt { span, lhs, rhs } => Expression::Lt {
+                span: f(span),
+                lhs: boxed(lhs, f),
+                rhs: boxed(rhs, f),
+            },
+            Expression::Lte { span, lhs, rhs } => Expression::Lte {
+                span: f(span),
+                lhs: boxed(lhs, f),
+                rhs: boxed(rhs, f),
+            },
+            Expression::Gt { span, lhs, rhs } => Expression::Gt {
+                span: f(span),
+                lhs: boxed(lhs, f),
+                rhs: boxed(rhs, f),
+            },
+            Expression::Gte { span, lhs, rhs } => Expression::Gte {
+                span: f(span),
+                lhs: boxed(lhs, f),
+                rhs: boxed(rhs, f),
+            },
+            Expression::And { span, lhs, rhs } => Expression::And {
+                span: f(span),
+                lhs: boxed(lhs, f),
+                rhs: boxed(rhs, f),
+            },
+            Expression::Or { span, lhs, rhs } => Expression::Or {
+                span: f(span),
+                lhs: boxed(lhs, f),
+                rhs: boxed(rhs, f),
+            },
+            Expression::Ternary {
+                span,
+                condition,
+                then,
+                otherwise,
+            } => Expression::Ternary {
+                span: f(span),
+                condition: boxed(condition, f),
+                then: boxed(then, f),
+                otherwise: boxed(otherwise, f),
+            },
+        }
+    }
 }
 
 impl<'a> Expression<Span<'a>> {
     pub fn span(&self) -> Span<'a> {
         match self {
             Expression::StringLiteral(span)
+            | Expression::NumberLiteral(span)
             | Expression::Keyword { span, .. }
+            | Expression::KeywordDepth { span, .. }
+            | Expression::Length { span, .. }
+            | Expression::Empty { span, .. }
+            | Expression::Array { span, .. }
+            | Expression::Range { span, .. }
             | Expression::Path { span, .. }
             | Expression::Negative { span, .. }
             | Expression::Helper { span, .. }
             | Expression::LegacyHelper { span, .. }
             | Expression::Equ { span, .. }
             | Expression::Neq { span, .. }
+            | Expression::Lt { span, .. }
+            | Expression::Lte { span, .. }
+            | Expression::Gt { span, .. }
+            | Expression::Gte { span, .. }
             | Expression::And { span, .. }
-            | Expression::Or { span, .. } => *span,
+            | Expression::Or { span, .. }
+            | Expression::Ternary { span, .. } => *span,
         }
     }
 }
 
+/// Unescape contents of string literal
+pub fn unescape(input: &str) -> String {
+    // remove first and last quote
+    let input = &input[1..(input.len() - 1)];
+    let mut output = String::new();
+
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        output.push(if c == '\\' {
+            match chars.next() {
+                Some(c) => match c {
+                    'r' => '\r',
+                    'n' => '\n',
+                    't' => '\t',
+                    _ => c,
+                },
+                _ => c,
+            }
+        } else {
+            c
+        });
+    }
+
+    output
+}
+
 fn string_literal(input: Span) -> IResult<Span, Expression<Span>> {
     map(
         recognize(delimited(
@@ -147,13 +364,69 @@ fn string_literal(input: Span) -> IResult<Span, Expression<Span>> {
     )(input)
 }
 
+/// characters which can continue an identifier
+const IDENTIFIER_CHARS: &str = "_-:@/";
+
+fn number_literal(input: Span) -> IResult<Span, Expression<Span>> {
+    let (rest, number) = recognize(tuple((
+        opt(char('-')),
+        digit1,
+        opt(pair(char('.'), digit1)),
+    )))(input)?;
+
+    // something like `1st` or `2.x` is a path,
+    // but `1-->` ends a legacy token and `1..` starts a range
+    let continues_path =
+        rest.starts_with(|c: char| c.is_alphanumeric() || c == '.' || IDENTIFIER_CHARS.contains(c));
+    if continues_path && !rest.starts_with("-->") && !rest.starts_with("..") {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Digit,
+        )));
+    }
+
+    // too many digits to be anything but infinity, reported as an error by `tokens`
+    if !number.fragment().parse::<f64>().is_ok_and(f64::is_finite) {
+        return Err(nom::Err::Failure(nom::error::Error::new(
+            number,
+            nom::error::ErrorKind::Float,
+        )));
+    }
+
+    Ok((rest, Expression::NumberLiteral(number)))
+}
+
+/// Value of a number literal, which was checked when parsing
+pub fn number(input: &str) -> f64 {
+    input.parse().unwrap_or_default()
+}
+
 pub fn keyword(input: Span) -> IResult<Span, Expression<Span>> {
     fn word(input: Span) -> IResult<Span, Span> {
         alpha1(input)
     }
 
     map_res(consumed(preceded(tag("@"), word)), |(span, ident)| {
+        // without a subject, `@length` and `@empty` measure `@value`
+        let value = || {
+            Box::new(Expression::Keyword {
+                span: span.slice(..0),
+                keyword: Keyword::Value,
+            })
+        };
         let keyword = match *ident.fragment() {
+            "length" => {
+                return Ok(Expression::Length {
+                    span,
+                    subject: value(),
+                })
+            }
+            "empty" => {
+                return Ok(Expression::Empty {
+                    span,
+                    subject: value(),
+                })
+            }
             "root" => Keyword::Root,
             "key" => Keyword::Key,
             "index" => Keyword::Index,
@@ -162,6 +435,8 @@ pub fn keyword(input: Span) -> IResult<Span, Expression<Span>> {
             "last" => Keyword::Last,
             "true" => Keyword::True,
             "false" => Keyword::False,
+            "odd" => Keyword::Odd,
+            "even" => Keyword::Even,
             _ => return Err("Invalid keyword"),
         };
 
@@ -171,7 +446,7 @@ pub fn keyword(input: Span) -> IResult<Span, Expression<Span>> {
 
 fn identifier(input: Span) -> IResult<Span, Span> {
     let (rest, res): (Span, Span) =
-        recognize(many1_count(alt((alphanumeric1, is_a("_-:@/")))))(input)?;
+        recognize(many1_count(alt((alphanumeric1, is_a(IDENTIFIER_CHARS)))))(input)?;
     // exclude `-->` from being recognized as part of an expression path
     if res.ends_with("--") && rest.starts_with('>') {
         let split = res.len() - 2;
@@ -181,27 +456,47 @@ fn identifier(input: Span) -> IResult<Span, Span> {
     }
 }
 
+/// `@length` or `@empty` after a value, like `posts.@length`
+fn measure(input: Span) -> IResult<Span, Span> {
+    terminated(alt((tag("@length"), tag("@empty"))), not(identifier))(input)
+}
+
+/// `[expr]`, like `[0]`, `["key"]`, or `[@key]`
+fn subscript(input: Span) -> IResult<Span, PathPart<Span>> {
+    map(
+        consumed(delimited(tag("["), ws(expression), tag("]"))),
+        |(span, expr)| PathPart::Index(span, Box::new(expr)),
+    )(input)
+}
+
 fn path(input: Span) -> IResult<Span, Expression<Span>> {
     map(
-        consumed(pair(
+        consumed(tuple((
             many0(map(alt((tag("./"), tag("../"))), PathPart::Part)),
-            separated_list1(tag("."), map(identifier, PathPart::Part)),
-        )),
-        |(span, (mut first, mut second))| {
-            first.append(&mut second);
-            Expression::Path { span, path: first }
+            map(identifier, PathPart::Part),
+            many0(alt((
+                preceded(
+                    pair(tag("."), not(measure)),
+                    map(identifier, PathPart::Part),
+                ),
+                subscript,
+            ))),
+        ))),
+        |(span, (mut path, first, mut rest))| {
+            path.push(first);
+            path.append(&mut rest);
+            Expression::Path { span, path }
         },
     )(input)
 }
 
 fn negative(input: Span) -> IResult<Span, Expression<Span>> {
-    map(
-        consumed(preceded(ws(tag("!")), expression)),
-        |(span, expr)| Expression::Negative {
+    map(consumed(preceded(ws(tag("!")), unary)), |(span, expr)| {
+        Expression::Negative {
             span,
             expr: Box::new(expr),
-        },
-    )(input)
+        }
+    })(input)
 }
 
 fn helper(input: Span) -> IResult<Span, Expression<Span>> {
@@ -241,55 +536,219 @@ fn legacy_helper(input: Span) -> IResult<Span, Expression<Span>> {
     )(input)
 }
 
-fn binary<'a, F>(
-    op: &'static str,
-    f: F,
-) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Expression<Span<'a>>>
-where
-    F: Fn(Span<'a>, Expression<Span<'a>>, Expression<Span<'a>>) -> Expression<Span<'a>>,
-{
+/// `( expr )`, where the span of a binary operation is widened to the parentheses
+fn parenthesized(input: Span) -> IResult<Span, Expression<Span>> {
     map(
-        consumed(tuple((
-            tag("("),
-            ws(expression),
-            tag(op),
-            ws(expression),
-            tag(")"),
-        ))),
-        move |(span, (_, lhs, _, rhs, _))| f(span, lhs, rhs),
-    )
+        consumed(delimited(tag("("), ws(expression), tag(")"))),
+        |(outer, mut expr)| {
+            if let Expression::Equ { span, .. }
+            | Expression::Neq { span, .. }
+            | Expression::Lt { span, .. }
+            | Expression::Lte { span, .. }
+            | Expression::Gt { span, .. }
+            | Expression::Gte { span, .. }
+            | Expression::And { span, .. }
+            | Expression::Or { span, .. }
+            | Expression::Range { span, .. }
+            | Expression::Ternary { span, .. } = &mut expr
+            {
+                *span = outer;
+            }
+            expr
+        },
+    )(input)
 }
 
-pub fn expression(input: Span) -> IResult<Span, Expression<Span>> {
+/// `[a, b, c]`
+fn array_literal(input: Span) -> IResult<Span, Expression<Span>> {
+    map(
+        consumed(delimited(
+            terminated(tag("["), multispace0),
+            separated_list0(tag(","), ws(expression)),
+            tag("]"),
+        )),
+        |(span, items)| Expression::Array { span, items },
+    )(input)
+}
+
+fn primary(input: Span) -> IResult<Span, Expression<Span>> {
     // This order is important
-    alt((
-        negative,
+    let (mut rest, mut expr) = alt((
+        parenthesized,
         legacy_helper,
         helper,
         string_literal,
+        number_literal,
+        array_literal,
         keyword,
         path,
-        binary("==", |span, lhs, rhs| Expression::Equ {
-            span,
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
-        }),
-        binary("!=", |span, lhs, rhs| Expression::Neq {
-            span,
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
-        }),
-        binary("&&", |span, lhs, rhs| Expression::And {
-            span,
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
-        }),
-        binary("||", |span, lhs, rhs| Expression::Or {
-            span,
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
-        }),
-    ))(input)
+    ))(input)?;
+
+    // measure the value with `.@length` or `.@empty`
+    while let Ok((after, keyword)) = preceded(tag("."), measure)(rest) {
+        let span = input.slice(..(after.location_offset() - input.location_offset()));
+        let subject = Box::new(expr);
+        expr = if *keyword.fragment() == "@length" {
+            Expression::Length { span, subject }
+        } else {
+            Expression::Empty { span, subject }
+        };
+        rest = after;
+    }
+
+    Ok((rest, expr))
+}
+
+fn unary(input: Span) -> IResult<Span, Expression<Span>> {
+    alt((negative, primary))(input)
+}
+
+type Parser<'a> = fn(Span<'a>) -> IResult<Span<'a>, Expression<Span<'a>>>;
+type Combine<'a> =
+    fn(Span<'a>, Box<Expression<Span<'a>>>, Box<Expression<Span<'a>>>) -> Expression<Span<'a>>;
+
+/// a chain of left-associative binary operators of the same precedence,
+/// like `a == b != c`, with `operand` parsing anything of higher precedence
+fn binary<'a>(
+    input: Span<'a>,
+    operand: Parser<'a>,
+    operators: &[(&'static str, Combine<'a>)],
+) -> IResult<Span<'a>, Expression<Span<'a>>> {
+    let (mut rest, mut lhs) = operand(input)?;
+
+    'chain: loop {
+        // longer operators come first, so `<=` isn't read as `<`
+        for (op, combine) in operators {
+            match preceded(ws(tag(*op)), operand)(rest) {
+                Ok((after, rhs)) => {
+                    let span = input.slice(..(after.location_offset() - input.location_offset()));
+                    lhs = combine(span, Box::new(lhs), Box::new(rhs));
+                    rest = after;
+                    continue 'chain;
+                }
+                Err(nom::Err::Error(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        return Ok((rest, lhs));
+    }
+}
+
+fn comparison(input: Span) -> IResult<Span, Expression<Span>> {
+    binary(
+        input,
+        unary,
+        &[
+            ("==", |span, lhs, rhs| Expression::Equ { span, lhs, rhs }),
+            ("!=", |span, lhs, rhs| Expression::Neq { span, lhs, rhs }),
+            ("<=", |span, lhs, rhs| Expression::Lte { span, lhs, rhs }),
+            ("<", |span, lhs, rhs| Expression::Lt { span, lhs, rhs }),
+            (">=", |span, lhs, rhs| Expression::Gte { span, lhs, rhs }),
+            (">", |span, lhs, rhs| Expression::Gt { span, lhs, rhs }),
+        ],
+    )
+}
+
+fn and(input: Span) -> IResult<Span, Expression<Span>> {
+    binary(
+        input,
+        comparison,
+        &[("&&", |span, lhs, rhs| Expression::And { span, lhs, rhs })],
+    )
+}
+
+fn or(input: Span) -> IResult<Span, Expression<Span>> {
+    binary(
+        input,
+        and,
+        &[("||", |span, lhs, rhs| Expression::Or { span, lhs, rhs })],
+    )
+}
+
+/// `start..end`, which can't be chained
+fn range(input: Span) -> IResult<Span, Expression<Span>> {
+    let (rest, start) = or(input)?;
+    // `../` starts a relative path
+    let end = preceded(ws(terminated(tag(".."), not(char('/')))), or);
+
+    match opt(end)(rest)? {
+        (rest, Some(end)) => Ok((
+            rest,
+            Expression::Range {
+                span: input.slice(..(rest.location_offset() - input.location_offset())),
+                start: Box::new(start),
+                end: Box::new(end),
+            },
+        )),
+        (rest, None) => Ok((rest, start)),
+    }
+}
+
+fn ternary(input: Span) -> IResult<Span, Expression<Span>> {
+    let (rest, condition) = range(input)?;
+    // `:` can be part of a path, so `a ? b:c` reads `b:c` as the first branch
+    let branches = pair(
+        preceded(ws(tag("?")), ternary),
+        preceded(ws(tag(":")), ternary),
+    );
+
+    match opt(branches)(rest)? {
+        (rest, Some((then, otherwise))) => Ok((
+            rest,
+            Expression::Ternary {
+                span: input.slice(..(rest.location_offset() - input.location_offset())),
+                condition: Box::new(condition),
+                then: Box::new(then),
+                otherwise: Box::new(otherwise),
+            },
+        )),
+        (rest, None) => Ok((rest, condition)),
+    }
+}
+
+/// `| name` or `| name(arg1, arg2, ...)`
+fn filter(input: Span) -> IResult<Span, (Span, Vec<Expression<Span>>)> {
+    preceded(
+        ws(terminated(tag("|"), not(tag("|")))),
+        pair(
+            identifier,
+            map(
+                opt(delimited(
+                    tag("("),
+                    separated_list0(tag(","), ws(expression)),
+                    tag(")"),
+                )),
+                Option::unwrap_or_default,
+            ),
+        ),
+    )(input)
+}
+
+/// An expression, where operators bind in order of precedence:
+/// `!`, then comparisons, then `&&`, then `||`, then `..`, then `? :`.
+///
+/// The whole expression can be piped through helpers, like `a | f | g(1)`,
+/// which is the same as `g(f(a), 1)`.
+pub fn expression(input: Span) -> IResult<Span, Expression<Span>> {
+    let (mut rest, mut expr) = ternary(input)?;
+
+    loop {
+        match filter(rest) {
+            Ok((after, (name, extra))) => {
+                let mut args = vec![expr];
+                args.extend(extra);
+                expr = Expression::Helper {
+                    span: input.slice(..(after.location_offset() - input.location_offset())),
+                    name,
+                    args,
+                };
+                rest = after;
+            }
+            Err(nom::Err::Error(_)) => return Ok((rest, expr)),
+            Err(err) => return Err(err),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -324,10 +783,37 @@ mod test {
         pub fn span_to_str(self) -> Expression<&'a str> {
             match self {
                 Expression::StringLiteral(span) => Expression::StringLiteral(*span.fragment()),
+                Expression::NumberLiteral(span) => Expression::NumberLiteral(*span.fragment()),
                 Expression::Keyword { span, keyword } => Expression::Keyword {
                     span: *span.fragment(),
                     keyword,
                 },
+                Expression::KeywordDepth {
+                    span,
+                    keyword,
+                    depth,
+                } => Expression::KeywordDepth {
+                    span: *span.fragment(),
+                    keyword,
+                    depth,
+                },
+                Expression::Length { span, subject } => Expression::Length {
+                    span: *span.fragment(),
+                    subject: Box::new(subject.span_to_str()),
+                },
+                Expression::Empty { span, subject } => Expression::Empty {
+                    span: *span.fragment(),
+                    subject: Box::new(subject.span_to_str()),
+                },
+                Expression::Array { span, items } => Expression::Array {
+                    span: *span.fragment(),
+                    items: items.into_iter().map(|item| item.span_to_str()).collect(),
+                },
+                Expression::Range { span, start, end } => Expression::Range {
+                    span: *span.fragment(),
+                    start: Box::new(start.span_to_str()),
+                    end: Box::new(end.span_to_str()),
+                },
                 Expression::Path { span, path } => Expression::Path {
                     span: *span.fragment(),
                     path: path.into_iter().map(|p| p.span_to_str()).collect(),
@@ -356,6 +842,26 @@ mod test {
                     lhs: Box::new(lhs.span_to_str()),
                     rhs: Box::new(rhs.span_to_str()),
                 },
+                Expression::Lt { span, lhs, rhs } => Expression::Lt {
+                    span: *span.fragment(),
+                    lhs: Box::new(lhs.span_to_str()),
+                    rhs: Box::new(rhs.span_to_str()),
+                },
+                Expression::Lte { span, lhs, rhs } => Expression::Lte {
+                    span: *span.fragment(),
+                    lhs: Box::new(lhs.span_to_str()),
+                    rhs: Box::new(rhs.span_to_str()),
+                },
+                Expression::Gt { span, lhs, rhs } => Expression::Gt {
+                    span: *span.fragment(),
+                    lhs: Box::new(lhs.span_to_str()),
+                    rhs: Box::new(rhs.span_to_str()),
+                },
+                Expression::Gte { span, lhs, rhs } => Expression::Gte {
+                    span: *span.fragment(),
+                    lhs: Box::new(lhs.span_to_str()),
+                    rhs: Box::new(rhs.span_to_str()),
+                },
                 Expression::And { span, lhs, rhs } => Expression::And {
                     span: *span.fragment(),
                     lhs: Box::new(lhs.span_to_str()),
@@ -366,6 +872,17 @@ mod test {
                     lhs: Box::new(lhs.span_to_str()),
                     rhs: Box::new(rhs.span_to_str()),
                 },
+                Expression::Ternary {
+                    span,
+                    condition,
+                    then,
+                    otherwise,
+                } => Expression::Ternary {
+                    span: *span.fragment(),
+                    condition: Box::new(condition.span_to_str()),
+                    then: Box::new(then.span_to_str()),
+                    otherwise: Box::new(otherwise.span_to_str()),
+                },
             }
         }
     }
@@ -418,6 +935,126 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_number_literal() {
+        assert_eq_unspan!(
+            number_literal(sp("0)")),
+            Ok((")", Expression::NumberLiteral("0")))
+        );
+        assert_eq_unspan!(
+            number_literal(sp("-12.5 ")),
+            Ok((" ", Expression::NumberLiteral("-12.5")))
+        );
+        assert_eq_unspan!(
+            number_literal(sp("3-->")),
+            Ok(("-->", Expression::NumberLiteral("3")))
+        );
+        assert_eq!(number("-12.5"), -12.5);
+
+        let huge = format!("{}1", "9".repeat(400));
+        assert!(matches!(
+            number_literal(sp(&huge)),
+            Err(nom::Err::Failure(e)) if e.code == nom::error::ErrorKind::Float
+        ));
+    }
+
+    #[test]
+    fn test_number_or_path() {
+        // identifiers can hold and start with digits, so those are still paths
+        for source in ["1st", "2.x", "1.2.3", "4-5", "0_0", "10:30", "1/2", "-a"] {
+            assert_eq_unspan!(
+                expression(sp(source)),
+                Ok((
+                    "",
+                    Expression::Path {
+                        span: source,
+                        path: source.split('.').map(PathPart::Part).collect(),
+                    }
+                ))
+            );
+        }
+
+        assert_eq_unspan!(
+            expression(sp("(count == 0)")),
+            Ok((
+                "",
+                Expression::Equ {
+                    span: "(count == 0)",
+                    lhs: Box::new(Expression::Path {
+                        span: "count",
+                        path: vec![PathPart::Part("count")]
+                    }),
+                    rhs: Box::new(Expression::NumberLiteral("0")),
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            expression(sp("posts.0.title")),
+            Ok((
+                "",
+                Expression::Path {
+                    span: "posts.0.title",
+                    path: vec![
+                        PathPart::Part("posts"),
+                        PathPart::Part("0"),
+                        PathPart::Part("title")
+                    ]
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_subscript() {
+        assert_eq_unspan!(
+            path(sp("users[ @key ].name[\"a b\"][0] }")),
+            Ok((
+                " }",
+                Expression::Path {
+                    span: "users[ @key ].name[\"a b\"][0]",
+                    path: vec![
+                        PathPart::Part("users"),
+                        PathPart::Index(
+                            "[ @key ]",
+                            Box::new(Expression::Keyword {
+                                span: "@key",
+                                keyword: Keyword::Key
+                            })
+                        ),
+                        PathPart::Part("name"),
+                        PathPart::Index(
+                            "[\"a b\"]",
+                            Box::new(Expression::StringLiteral("\"a b\""))
+                        ),
+                        PathPart::Index("[0]", Box::new(Expression::NumberLiteral("0"))),
+                    ]
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            path(sp("../map[./id]")),
+            Ok((
+                "",
+                Expression::Path {
+                    span: "../map[./id]",
+                    path: vec![
+                        PathPart::Part("../"),
+                        PathPart::Part("map"),
+                        PathPart::Index(
+                            "[./id]",
+                            Box::new(Expression::Path {
+                                span: "./id",
+                                path: vec![PathPart::Part("./"), PathPart::Part("id")]
+                            })
+                        ),
+                    ]
+                }
+            ))
+        );
+        // a path can't start with a subscript
+        assert!(path(sp("[0]")).is_err());
+    }
+
     #[test]
     fn test_keyword() {
         assert_eq_unspan!(
@@ -431,6 +1068,158 @@ mod test {
             ))
         );
         assert!(keyword(sp("@keyframes")).is_err());
+        assert_eq_unspan!(
+            keyword(sp("@odd ")),
+            Ok((
+                " ",
+                Expression::Keyword {
+                    span: "@odd",
+                    keyword: Keyword::Odd
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq_unspan!(
+            expression(sp("1..page.count }}}")),
+            Ok((
+                " }}}",
+                Expression::Range {
+                    span: "1..page.count",
+                    start: Box::new(Expression::NumberLiteral("1")),
+                    end: Box::new(Expression::Path {
+                        span: "page.count",
+                        path: vec![PathPart::Part("page"), PathPart::Part("count")]
+                    })
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            expression(sp("a .. 2.5")),
+            Ok((
+                "",
+                Expression::Range {
+                    span: "a .. 2.5",
+                    start: Box::new(Expression::Path {
+                        span: "a",
+                        path: vec![PathPart::Part("a")]
+                    }),
+                    end: Box::new(Expression::NumberLiteral("2.5"))
+                }
+            ))
+        );
+        // `../` is a relative path, not a range
+        assert_eq_unspan!(
+            expression(sp("a ../b")),
+            Ok((
+                " ../b",
+                Expression::Path {
+                    span: "a",
+                    path: vec![PathPart::Part("a")]
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_array() {
+        assert_eq_unspan!(
+            expression(sp("[ ]")),
+            Ok((
+                "",
+                Expression::Array {
+                    span: "[ ]",
+                    items: vec![]
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            expression(sp("[\"a\", b,1 ]")),
+            Ok((
+                "",
+                Expression::Array {
+                    span: "[\"a\", b,1 ]",
+                    items: vec![
+                        Expression::StringLiteral("\"a\""),
+                        Expression::Path {
+                            span: "b",
+                            path: vec![PathPart::Part("b")]
+                        },
+                        Expression::NumberLiteral("1"),
+                    ]
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq_unspan!(
+            expression(sp("@length")),
+            Ok((
+                "",
+                Expression::Length {
+                    span: "@length",
+                    subject: Box::new(Expression::Keyword {
+                        span: "",
+                        keyword: Keyword::Value
+                    })
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            expression(sp("a.b.@empty || !c")),
+            Ok((
+                "",
+                Expression::Or {
+                    span: "a.b.@empty || !c",
+                    lhs: Box::new(Expression::Empty {
+                        span: "a.b.@empty",
+                        subject: Box::new(Expression::Path {
+                            span: "a.b",
+                            path: vec![PathPart::Part("a"), PathPart::Part("b")]
+                        })
+                    }),
+                    rhs: Box::new(Expression::Negative {
+                        span: "!c",
+                        expr: Box::new(Expression::Path {
+                            span: "c",
+                            path: vec![PathPart::Part("c")]
+                        })
+                    })
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            expression(sp("(a).@length > 1")),
+            Ok((
+                "",
+                Expression::Gt {
+                    span: "(a).@length > 1",
+                    lhs: Box::new(Expression::Length {
+                        span: "(a).@length",
+                        subject: Box::new(Expression::Path {
+                            span: "a",
+                            path: vec![PathPart::Part("a")]
+                        })
+                    }),
+                    rhs: Box::new(Expression::NumberLiteral("1"))
+                }
+            ))
+        );
+        // only the whole keyword is special
+        assert_eq_unspan!(
+            expression(sp("a.@lengths")),
+            Ok((
+                "",
+                Expression::Path {
+                    span: "a.@lengths",
+                    path: vec![PathPart::Part("a"), PathPart::Part("@lengths")]
+                }
+            ))
+        );
     }
 
     #[test]
@@ -496,6 +1285,233 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_precedence() {
+        fn path(name: &str) -> Box<Expression<&str>> {
+            Box::new(Expression::Path {
+                span: name,
+                path: vec![PathPart::Part(name)],
+            })
+        }
+
+        assert_eq_unspan!(
+            expression(sp("a && b || c")),
+            Ok((
+                "",
+                Expression::Or {
+                    span: "a && b || c",
+                    lhs: Box::new(Expression::And {
+                        span: "a && b",
+                        lhs: path("a"),
+                        rhs: path("b"),
+                    }),
+                    rhs: path("c"),
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            expression(sp("!a || b && x == \"y\" }")),
+            Ok((
+                " }",
+                Expression::Or {
+                    span: "!a || b && x == \"y\"",
+                    lhs: Box::new(Expression::Negative {
+                        span: "!a",
+                        expr: path("a"),
+                    }),
+                    rhs: Box::new(Expression::And {
+                        span: "b && x == \"y\"",
+                        lhs: path("b"),
+                        rhs: Box::new(Expression::Equ {
+                            span: "x == \"y\"",
+                            lhs: path("x"),
+                            rhs: Box::new(Expression::StringLiteral("\"y\"")),
+                        }),
+                    }),
+                }
+            ))
+        );
+        // operators of the same precedence are left-associative
+        assert_eq_unspan!(
+            expression(sp("a || b || c")),
+            Ok((
+                "",
+                Expression::Or {
+                    span: "a || b || c",
+                    lhs: Box::new(Expression::Or {
+                        span: "a || b",
+                        lhs: path("a"),
+                        rhs: path("b"),
+                    }),
+                    rhs: path("c"),
+                }
+            ))
+        );
+        // parentheses override precedence, and are kept in the span
+        assert_eq_unspan!(
+            expression(sp("!(a || b) && c")),
+            Ok((
+                "",
+                Expression::And {
+                    span: "!(a || b) && c",
+                    lhs: Box::new(Expression::Negative {
+                        span: "!(a || b)",
+                        expr: Box::new(Expression::Or {
+                            span: "(a || b)",
+                            lhs: path("a"),
+                            rhs: path("b"),
+                        }),
+                    }),
+                    rhs: path("c"),
+                }
+            ))
+        );
+        // a dangling operator isn't part of the expression
+        assert_eq_unspan!(expression(sp("a &&")), Ok((" &&", *path("a"))));
+    }
+
+    #[test]
+    fn test_ternary() {
+        fn path(name: &str) -> Box<Expression<&str>> {
+            Box::new(Expression::Path {
+                span: name,
+                path: vec![PathPart::Part(name)],
+            })
+        }
+
+        assert_eq_unspan!(
+            expression(sp("isAdmin ? \"admin\" : \"user\" }")),
+            Ok((
+                " }",
+                Expression::Ternary {
+                    span: "isAdmin ? \"admin\" : \"user\"",
+                    condition: path("isAdmin"),
+                    then: Box::new(Expression::StringLiteral("\"admin\"")),
+                    otherwise: Box::new(Expression::StringLiteral("\"user\"")),
+                }
+            ))
+        );
+        // the condition binds loosest, and nested ternaries are right-associative
+        assert_eq_unspan!(
+            expression(sp("(a == b) ? x : y || c ? d : e")),
+            Ok((
+                "",
+                Expression::Ternary {
+                    span: "(a == b) ? x : y || c ? d : e",
+                    condition: Box::new(Expression::Equ {
+                        span: "(a == b)",
+                        lhs: path("a"),
+                        rhs: path("b"),
+                    }),
+                    then: path("x"),
+                    otherwise: Box::new(Expression::Ternary {
+                        span: "y || c ? d : e",
+                        condition: Box::new(Expression::Or {
+                            span: "y || c",
+                            lhs: path("y"),
+                            rhs: path("c"),
+                        }),
+                        then: path("d"),
+                        otherwise: path("e"),
+                    }),
+                }
+            ))
+        );
+        // `:` continues a path, so it needs a space after one
+        assert_eq_unspan!(expression(sp("a ? b:c")), Ok((" ? b:c", *path("a"))));
+    }
+
+    #[test]
+    fn test_pipes() {
+        fn path(name: &str) -> Expression<&str> {
+            Expression::Path {
+                span: name,
+                path: vec![PathPart::Part(name)],
+            }
+        }
+
+        assert_eq_unspan!(
+            expression(sp("post.timestamp | formatDate | truncate(20) }")),
+            Ok((
+                " }",
+                Expression::Helper {
+                    span: "post.timestamp | formatDate | truncate(20)",
+                    name: "truncate",
+                    args: vec![
+                        Expression::Helper {
+                            span: "post.timestamp | formatDate",
+                            name: "formatDate",
+                            args: vec![Expression::Path {
+                                span: "post.timestamp",
+                                path: vec![PathPart::Part("post"), PathPart::Part("timestamp")]
+                            }],
+                        },
+                        Expression::NumberLiteral("20"),
+                    ],
+                }
+            ))
+        );
+        // pipes apply to the whole expression, and don't split `||`
+        assert_eq_unspan!(
+            expression(sp("a || b ? c : d | f()")),
+            Ok((
+                "",
+                Expression::Helper {
+                    span: "a || b ? c : d | f()",
+                    name: "f",
+                    args: vec![Expression::Ternary {
+                        span: "a || b ? c : d",
+                        condition: Box::new(Expression::Or {
+                            span: "a || b",
+                            lhs: Box::new(path("a")),
+                            rhs: Box::new(path("b")),
+                        }),
+                        then: Box::new(path("c")),
+                        otherwise: Box::new(path("d")),
+                    }],
+                }
+            ))
+        );
+        assert_eq_unspan!(expression(sp("a |")), Ok((" |", path("a"))));
+    }
+
+    #[test]
+    fn test_relational() {
+        assert_eq_unspan!(
+            expression(sp("(count>-1)")),
+            Ok((
+                "",
+                Expression::Gt {
+                    span: "(count>-1)",
+                    lhs: Box::new(Expression::Path {
+                        span: "count",
+                        path: vec![PathPart::Part("count")]
+                    }),
+                    rhs: Box::new(Expression::NumberLiteral("-1")),
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            expression(sp("(a <= b)")),
+            Ok((
+                "",
+                Expression::Lte {
+                    span: "(a <= b)",
+                    lhs: Box::new(Expression::Path {
+                        span: "a",
+                        path: vec![PathPart::Part("a")]
+                    }),
+                    rhs: Box::new(Expression::Path {
+                        span: "b",
+                        path: vec![PathPart::Part("b")]
+                    }),
+                }
+            ))
+        );
+        // `-->` is never part of a path, so it can't become `a- ->`
+        assert!(expression(sp("(a-->b)")).is_err());
+    }
+
     #[test]
     fn test_helper() {
         assert_eq_unspan!(
@@ -0,0 +1,399 @@
+//! Templates included by other templates
+//!
+//! Includes are resolved at compile time when a [`Resolver`] is given,
+//! replacing `{{{ include "name" }}}` with the included template's tree.
+//! Otherwise they are left for the runtime to render.
+//!
+//! Every template reachable through includes is loaded up front, then the
+//! tree builder inlines them recursively, keeping the chain of includes it
+//! is inside of so a template including itself is reported instead.
+//!
+//! `{{{ extends "layout" }}}` works the same way, inlining the layout with
+//! its `{{{ block }}}` bodies replaced by the blocks of the extending
+//! template. Those replacements are kept here while the layout is built.
+
+use crate::{
+    diagnostic::{
+        Code,
+        Diagnostic,
+    },
+    parse::{
+        tokens::Token,
+        tree::Instruction,
+        Span,
+    },
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::Arc,
+};
+
+/// A function returning the source of an included template by name,
+/// or `None` if there is no such template
+pub type ResolveFn = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+/// Looks up included templates while compiling
+#[derive(Clone)]
+pub struct Resolver(Arc<ResolveFn>);
+
+impl Resolver {
+    pub fn new(resolve: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        Resolver(Arc::new(resolve))
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<String> {
+        (self.0)(name)
+    }
+}
+
+impl std::fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Resolver(..)")
+    }
+}
+
+/// resolvers are only equal if they are the same function
+impl PartialEq for Resolver {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for Resolver {}
+
+/// names of the templates included by a template
+fn included_by(source: &str, filename: &str) -> Vec<String> {
+    // diagnostics are reported when the template is parsed
+    let tokens = crate::tokenize(source, filename, false, &mut vec![]).unwrap_or_default();
+
+    tokens
+        .iter()
+        .filter_map(|tok| match tok {
+            Token::Include { name, .. }
+            | Token::LegacyInclude { name, .. }
+            | Token::Extends { name, .. } => Some(name.fragment().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Sources of included templates, loaded before parsing
+/// so the parsed tree can borrow from them
+#[derive(Debug, Default)]
+pub struct Sources {
+    enabled: bool,
+    /// name and source of each template found
+    loaded: Vec<(String, String)>,
+}
+
+impl Sources {
+    /// load the templates included by `source`, and the ones they include
+    pub fn load(source: &str, filename: &str, resolver: Option<&Resolver>) -> Self {
+        let resolver = match resolver {
+            Some(resolver) => resolver,
+            None => return Sources::default(),
+        };
+
+        let mut names = included_by(source, filename);
+        let mut loaded: Vec<(String, String)> = vec![];
+        let mut missing = vec![];
+        while let Some(name) = names.pop() {
+            if missing.contains(&name) || loaded.iter().any(|(loaded, _)| *loaded == name) {
+                continue;
+            }
+
+            match resolver.resolve(&name) {
+                Some(source) => {
+                    names.extend(included_by(&source, &name));
+                    loaded.push((name, source));
+                }
+                None => missing.push(name),
+            }
+        }
+
+        Sources {
+            enabled: true,
+            loaded,
+        }
+    }
+}
+
+/// A block of an extending template, replacing the block with the same name
+type BlockBody<'a> = (&'a str, Vec<Instruction<Span<'a>>>);
+
+/// Tokens of each included template, to be inlined in the tree
+#[derive(Debug, Default)]
+pub struct Partials<'a> {
+    enabled: bool,
+    tokens: HashMap<&'a str, Vec<Token<Span<'a>>>>,
+    /// names of the includes currently being inlined, outermost first
+    chain: RefCell<Vec<Span<'a>>>,
+    /// bodies replacing blocks of the templates being extended,
+    /// from the outermost extending template first
+    blocks: RefCell<Vec<BlockBody<'a>>>,
+}
+
+impl<'a> Partials<'a> {
+    /// tokenize the loaded templates, named by their include names
+    pub fn new(sources: &'a Sources, standalone: bool, diagnostics: &mut Vec<Diagnostic>) -> Self {
+        let tokens = sources
+            .loaded
+            .iter()
+            .filter_map(|(name, source)| {
+                Some((
+                    name.as_str(),
+                    crate::tokenize(source, name, standalone, diagnostics)?,
+                ))
+            })
+            .collect();
+
+        Partials {
+            enabled: sources.enabled,
+            tokens,
+            chain: RefCell::default(),
+            blocks: RefCell::default(),
+        }
+    }
+
+    /// whether includes are resolved at compile time
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Vec<Token<Span<'a>>>> {
+        self.tokens.get(name)
+    }
+
+    /// start inlining the template included by `name`
+    pub fn enter(&self, name: Span<'a>) {
+        self.chain.borrow_mut().push(name);
+    }
+
+    /// finish inlining the innermost included template
+    pub fn exit(&self) {
+        self.chain.borrow_mut().pop();
+    }
+
+    /// add the blocks of an extending template, returning how many were added
+    pub fn push_blocks(&self, blocks: Vec<BlockBody<'a>>) -> usize {
+        let count = blocks.len();
+        self.blocks.borrow_mut().extend(blocks);
+        count
+    }
+
+    /// remove blocks added by [`Partials::push_blocks`]
+    pub fn pop_blocks(&self, count: usize) {
+        let mut blocks = self.blocks.borrow_mut();
+        let len = blocks.len();
+        blocks.truncate(len - count);
+    }
+
+    /// body replacing the block `name`, where the outermost extending template wins
+    pub fn block(&self, name: &str) -> Option<Vec<Instruction<Span<'a>>>> {
+        self.blocks
+            .borrow()
+            .iter()
+            .find(|(block, _)| *block == name)
+            .map(|(_, body)| body.clone())
+    }
+
+    /// an error if including `name` here would include a template in itself
+    pub fn cycle(&self, name: Span<'a>) -> Option<Diagnostic> {
+        let chain = self.chain.borrow();
+        let start = chain.iter().position(|x| x.fragment() == name.fragment())?;
+
+        let filenames = std::iter::once(chain[0].extra.filename)
+            .chain(chain.iter().map(|x| *x.fragment()))
+            .chain(std::iter::once(*name.fragment()))
+            .collect::<Vec<_>>();
+        let diagnostic = chain[start..].iter().fold(
+            Diagnostic::error(Code::IncludeCycle, name, "template includes itself")
+                .primary(name, format!("`{name}` is already being included")),
+            |diagnostic, outer| diagnostic.secondary(*outer, format!("`{outer}` included here")),
+        );
+
+        Some(diagnostic.note(format!("include chain: {}", filenames.join(" -> "))))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        compile_with_diagnostics,
+        Code,
+        CompileOptions,
+    };
+
+    fn options() -> CompileOptions {
+        CompileOptions {
+            resolver: Some(Resolver::new(|name| {
+                match name {
+                "header" => Some("<h1>{title}</h1>".to_string()),
+                "partials/post" => {
+                    Some("{{{ each posts }}}<p>{./title}</p>{{{ end }}}".to_string())
+                }
+                "title" => Some("<a>{./title}</a>{{{ include \"tags\" }}}".to_string()),
+                "tags" => Some("{{{ each ./tags }}}{@value}{{{ end }}}".to_string()),
+                "a" => Some("{{{ if x }}}{{{ include \"b\" }}}{{{ end }}}".to_string()),
+                "b" => Some("<!-- IMPORT a -->".to_string()),
+                "base" => Some(
+                    "<title>{{{ block \"title\" }}}Site{{{ end }}}</title>\
+                     <main>{{{ block \"content\" }}}{{{ end }}}</main>"
+                        .to_string(),
+                ),
+                "section" => Some(
+                    "{{{ extends \"base\" }}}\
+                     {{{ block \"content\" }}}<nav/>{{{ block \"body\" }}}none{{{ end }}}{{{ end }}}"
+                        .to_string(),
+                ),
+                "loop" => Some("{{{ extends \"loop\" }}}".to_string()),
+                _ => None,
+            }
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_inline() {
+        let plain = |source| crate::compile(source, "<test>", &Default::default());
+        let inlined = |source| crate::compile(source, "<test>", &options());
+
+        assert_eq!(
+            inlined("{{{ include \"header\" }}}<!-- IMPORT partials/post -->"),
+            plain("<h1>{title}</h1>{{{ each posts }}}<p>{./title}</p>{{{ end }}}")
+        );
+        // paths are resolved as if the included template was written in place,
+        // including the templates it includes
+        assert_eq!(
+            inlined("{{{ each posts }}}{{{ include \"title\" }}}{{{ end }}}"),
+            plain(
+                "{{{ each posts }}}<a>{./title}</a>{{{ each ./tags }}}{@value}{{{ end }}}{{{ end }}}"
+            )
+        );
+        assert_eq!(
+            inlined("{{{ include \"title\" }}}"),
+            plain("<a>{./title}</a>{{{ each ./tags }}}{@value}{{{ end }}}")
+        );
+    }
+
+    #[test]
+    fn test_extends() {
+        let render = |source| {
+            let context = json::object! { title: "Post" };
+            crate::render(source, "<test>", &context, &Default::default(), &options()).unwrap()
+        };
+
+        // blocks which aren't replaced keep their body
+        assert_eq!(
+            render("{{{ extends \"base\" }}}\n{{{ block \"content\" }}}{title}{{{ end }}}\n"),
+            "<title>Site</title><main>Post</main>"
+        );
+        // through several templates, where the outermost template's blocks win
+        assert_eq!(
+            render(
+                "{{{ extends \"section\" }}}{{{ block \"body\" }}}text{{{ end }}}\
+                 {{{ block \"title\" }}}{title}{{{ end }}}"
+            ),
+            "<title>Post</title><main><nav/>text</main>"
+        );
+        // and a layout can be rendered alone
+        assert_eq!(
+            render("{{{ include \"base\" }}}"),
+            "<title>Site</title><main></main>"
+        );
+    }
+
+    #[test]
+    fn test_extends_errors() {
+        let diagnostics = |source, options: &CompileOptions| {
+            compile_with_diagnostics(source, "<test>", options)
+                .diagnostics
+                .iter()
+                .map(|x| x.code)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            diagnostics(
+                "{{{ extends \"base\" }}}<p>{a}</p>{{{ block \"title\" }}}{{{ end }}}",
+                &options()
+            ),
+            vec![
+                Code::IgnoredContent,
+                Code::IgnoredContent,
+                Code::IgnoredContent
+            ]
+        );
+        assert_eq!(
+            diagnostics("{{{ extends \"nope\" }}}", &options()),
+            vec![Code::UnresolvedInclude]
+        );
+        assert_eq!(
+            diagnostics("{{{ extends \"base\" }}}", &Default::default()),
+            vec![Code::UnresolvedInclude]
+        );
+        assert_eq!(
+            diagnostics("{{{ extends \"loop\" }}}", &options()),
+            vec![Code::IncludeCycle]
+        );
+    }
+
+    #[test]
+    fn test_cycle() {
+        let output = compile_with_diagnostics("{{{ include \"a\" }}}", "page.tpl", &options());
+        assert_eq!(output.diagnostics.len(), 1);
+
+        let diagnostic = &output.diagnostics[0];
+        assert_eq!(diagnostic.code, Code::IncludeCycle);
+        assert_eq!(
+            diagnostic.note.as_deref(),
+            Some("include chain: page.tpl -> a -> b -> a")
+        );
+        let primary = diagnostic.primary.as_ref().unwrap();
+        assert_eq!((primary.filename.as_str(), primary.column), ("b", 13));
+        let secondary = diagnostic
+            .secondary
+            .iter()
+            .map(|label| (label.filename.as_str(), label.column))
+            .collect::<Vec<_>>();
+        assert_eq!(secondary, vec![("page.tpl", 14), ("a", 26)]);
+    }
+
+    #[test]
+    fn test_unresolved() {
+        let output = compile_with_diagnostics("{{{ include \"nope\" }}}", "<test>", &options());
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].code, Code::UnresolvedInclude);
+        let label = output.diagnostics[0].primary.as_ref().unwrap();
+        assert_eq!((label.line, label.column, label.length), (1, 14, 4));
+    }
+
+    #[test]
+    fn test_runtime() {
+        let output =
+            compile_with_diagnostics("{{{ include \"header\" }}}", "<test>", &Default::default());
+        assert!(output.diagnostics.is_empty());
+        assert!(output
+            .code
+            .contains("return helper(context, helpers, '__include', [\"header\"]);"));
+        assert!(output
+            .code
+            .contains("\n  compiled.includes = [\"header\"];\n"));
+
+        // rendered with the root data, unlike an inlined include
+        let source = "{{{ each rooms }}}{{{ include \"title\" }}}{{{ end }}}";
+        let output = compile_with_diagnostics(source, "<test>", &Default::default());
+        let codes = output
+            .diagnostics
+            .iter()
+            .map(|d| d.code)
+            .collect::<Vec<_>>();
+        assert_eq!(codes, vec![Code::RuntimeInclude]);
+        assert!(!output.diagnostics[0].is_error());
+
+        let output = compile_with_diagnostics(source, "<test>", &options());
+        assert!(output.diagnostics.is_empty());
+    }
+}
@@ -1,15 +1,38 @@
-use crate::parse::Span;
+use crate::parse::{
+    expression::Expression,
+    Span,
+};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum PathPart<S> {
     Part(S),
     PartDepth(S, u32),
+    // [expr], spanning the brackets
+    Index(S, Box<Expression<S>>),
+    IndexDepth(S, Box<Expression<S>>, u32),
+}
+
+impl<S> PathPart<S> {
+    /// convert the span type, keeping the structure
+    pub fn map_spans<T>(self, f: &mut impl FnMut(S) -> T) -> PathPart<T> {
+        match self {
+            PathPart::Part(span) => PathPart::Part(f(span)),
+            PathPart::PartDepth(span, depth) => PathPart::PartDepth(f(span), depth),
+            PathPart::Index(span, expr) => PathPart::Index(f(span), Box::new(expr.map_spans(f))),
+            PathPart::IndexDepth(span, expr, depth) => {
+                PathPart::IndexDepth(f(span), Box::new(expr.map_spans(f)), depth)
+            }
+        }
+    }
 }
 
 impl<'a> PathPart<Span<'a>> {
     pub fn span(&self) -> Span<'a> {
         match self {
-            PathPart::Part(span) | PathPart::PartDepth(span, _) => *span,
+            PathPart::Part(span)
+            | PathPart::PartDepth(span, _)
+            | PathPart::Index(span, _)
+            | PathPart::IndexDepth(span, ..) => *span,
         }
     }
 
@@ -18,8 +41,22 @@ impl<'a> PathPart<Span<'a>> {
     }
 
     pub fn with_depth(&mut self, depth: u32) {
-        *self = match *self {
-            PathPart::Part(s) | PathPart::PartDepth(s, _) => PathPart::PartDepth(s, depth),
+        *self = match self {
+            PathPart::Part(s) | PathPart::PartDepth(s, _) => PathPart::PartDepth(*s, depth),
+            PathPart::Index(s, expr) | PathPart::IndexDepth(s, expr, _) => {
+                PathPart::IndexDepth(*s, expr.clone(), depth)
+            }
+        }
+    }
+
+    /// the same part, referring to the whole collection rather than
+    /// the current element of a loop over it
+    fn without_depth(&self) -> Self {
+        match self {
+            PathPart::Part(s) | PathPart::PartDepth(s, _) => PathPart::Part(*s),
+            PathPart::Index(s, expr) | PathPart::IndexDepth(s, expr, _) => {
+                PathPart::Index(*s, expr.clone())
+            }
         }
     }
 }
@@ -102,6 +139,13 @@ pub fn resolve<'a>(base: Path<'_, Span<'a>>, rel: PathBuf<Span<'a>>) -> PathBuf<
 
     if found {
         let mut output = base[0..base_end].to_vec();
+        // `{xs[i]}` inside `each xs` subscripts the array being iterated,
+        // not its current element
+        if let Some(PathPart::Index(..)) = rel.get(rel_start) {
+            if let Some(last) = output.last_mut() {
+                *last = last.without_depth();
+            }
+        }
         output.extend_from_slice(&rel[rel_start..]);
 
         output
@@ -120,6 +164,12 @@ mod test {
             match self {
                 PathPart::Part(span) => PathPart::Part(*span.fragment()),
                 PathPart::PartDepth(span, depth) => PathPart::PartDepth(*span.fragment(), depth),
+                PathPart::Index(span, expr) => {
+                    PathPart::Index(*span.fragment(), Box::new(expr.span_to_str()))
+                }
+                PathPart::IndexDepth(span, expr, depth) => {
+                    PathPart::IndexDepth(*span.fragment(), Box::new(expr.span_to_str()), depth)
+                }
             }
         }
     }
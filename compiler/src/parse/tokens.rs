@@ -1,5 +1,8 @@
 use crate::{
-    console::warn,
+    diagnostic::{
+        Code,
+        Diagnostic,
+    },
     parse::{
         expression::{
             expression,
@@ -15,18 +18,30 @@ use crate::{
 use nom::{
     branch::alt,
     bytes::complete::{
+        is_not,
         tag,
         take_until,
     },
+    character::complete::{
+        alpha1,
+        alphanumeric1,
+        char,
+        multispace1,
+    },
     combinator::{
         consumed,
         map,
+        opt,
+        peek,
         recognize,
+        verify,
     },
     error::ParseError,
+    multi::many0_count,
     sequence::{
         delimited,
         pair,
+        terminated,
     },
     IResult,
     Offset,
@@ -38,26 +53,76 @@ pub enum Token<S> {
     // Template text passed through
     Text(S),
     // `{obj.prop}`
-    InterpEscaped { span: S, expr: Expression<S> },
+    InterpEscaped {
+        span: S,
+        expr: Expression<S>,
+    },
     // `{{obj.prop}}`
-    InterpRaw { span: S, expr: Expression<S> },
+    InterpRaw {
+        span: S,
+        expr: Expression<S>,
+    },
     // `{{{ if condition }}}`
-    If { span: S, subject: Expression<S> },
-    // `{{{ each arr }}}`
-    Each { span: S, subject: Expression<S> },
+    If {
+        span: S,
+        subject: Expression<S>,
+    },
+    // `{{{ each arr }}}` or `{{{ each key, value in arr }}}`
+    Each {
+        span: S,
+        key: Option<S>,
+        value: Option<S>,
+        subject: Expression<S>,
+    },
     // `{{{ else }}}`
-    Else { span: S },
+    Else {
+        span: S,
+    },
     // `{{{ end }}}`
-    End { span: S, subject_raw: S },
+    End {
+        span: S,
+        subject_raw: S,
+    },
+    // `{{{ include "partials/post" }}}`
+    Include {
+        span: S,
+        name: S,
+    },
+    // `{{{ extends "layout" }}}`
+    Extends {
+        span: S,
+        name: S,
+    },
+    // `{{{ block "content" }}}`
+    Block {
+        span: S,
+        name: S,
+    },
     // `<!-- IF condition -->`
-    LegacyIf { span: S, subject: Expression<S> },
+    LegacyIf {
+        span: S,
+        subject: Expression<S>,
+    },
     // `<!-- BEGIN arr -->`
-    LegacyBegin { span: S, subject: Expression<S> },
+    LegacyBegin {
+        span: S,
+        subject: Expression<S>,
+    },
     // `<!-- ELSE -->`
-    LegacyElse { span: S },
+    LegacyElse {
+        span: S,
+    },
     // `<!-- END -->` or `<!-- ENDIF -->` or
     // `<!-- END subject -->` or `<!-- ENDIF subject -->`
-    LegacyEnd { span: S, subject_raw: S },
+    LegacyEnd {
+        span: S,
+        subject_raw: S,
+    },
+    // `<!-- IMPORT partials/post.tpl -->`
+    LegacyInclude {
+        span: S,
+        name: S,
+    },
 }
 
 impl<'a> Token<Span<'a>> {
@@ -70,45 +135,116 @@ impl<'a> Token<Span<'a>> {
             Token::Each { span, .. } => *span,
             Token::Else { span, .. } => *span,
             Token::End { span, .. } => *span,
+            Token::Include { span, .. } => *span,
+            Token::Extends { span, .. } => *span,
+            Token::Block { span, .. } => *span,
             Token::LegacyIf { span, .. } => *span,
             Token::LegacyBegin { span, .. } => *span,
             Token::LegacyElse { span, .. } => *span,
             Token::LegacyEnd { span, .. } => *span,
+            Token::LegacyInclude { span, .. } => *span,
         }
     }
 }
 
+/// an opening brace sequence, optionally followed by a `-` trim marker,
+/// which must be followed by whitespace to tell it apart from a path
+fn open<'a>(opener: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Span<'a>> {
+    recognize(pair(
+        tag(opener),
+        opt(terminated(char('-'), peek(multispace1))),
+    ))
+}
+
+/// whether the source right before a span is whitespace
+fn after_whitespace(input: Span) -> bool {
+    input.extra.full_source[..input.location_offset()].ends_with(char::is_whitespace)
+}
+
+/// a closing brace sequence, optionally preceded by a `-` trim marker,
+/// which must come after whitespace to tell it apart from a path
+fn close<'a>(closer: &'static str) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Span<'a>> {
+    move |input: Span<'a>| {
+        if after_whitespace(input) {
+            let trimmed: IResult<Span, Span> = recognize(pair(char('-'), tag(closer)))(input);
+            if trimmed.is_ok() {
+                return trimmed;
+            }
+        }
+
+        tag(closer)(input)
+    }
+}
+
+/// remove a trailing `-` trim marker from the subject of an end token
+fn strip_trim_marker(subject: Span) -> Span {
+    let subject = trim_end(subject);
+    let marker = subject.len().saturating_sub(1);
+    if subject.ends_with('-') && after_whitespace(subject.slice(marker..)) {
+        trim_end(subject.slice(..marker))
+    } else {
+        subject
+    }
+}
+
 fn interp_escaped(input: Span) -> IResult<Span, Token<Span>> {
     map(
-        consumed(delimited(tag("{"), ws(expression), tag("}"))),
+        consumed(delimited(open("{"), ws(expression), close("}"))),
         |(span, expr)| Token::InterpEscaped { span, expr },
     )(input)
 }
 
 fn interp_raw(input: Span) -> IResult<Span, Token<Span>> {
     map(
-        consumed(delimited(tag("{{"), ws(expression), tag("}}"))),
+        consumed(delimited(open("{{"), ws(expression), close("}}"))),
         |(span, expr)| Token::InterpRaw { span, expr },
     )(input)
 }
 
+/// a name for the key or value in `each key, value in arr`
+fn loop_variable(input: Span) -> IResult<Span, Span> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0_count(alt((alphanumeric1, tag("_")))),
+    ))(input)
+}
+
+/// `value in` or `key, value in`
+fn loop_variables(input: Span) -> IResult<Span, (Option<Span>, Span)> {
+    terminated(
+        pair(opt(terminated(loop_variable, ws(char(',')))), loop_variable),
+        delimited(multispace1, tag("in"), multispace1),
+    )(input)
+}
+
 fn new_each(input: Span) -> IResult<Span, Token<Span>> {
     map(
         consumed(delimited(
-            pair(tag("{{{"), ws(tag("each"))),
-            ws(expression),
-            tag("}}}"),
+            pair(open("{{{"), ws(tag("each"))),
+            pair(opt(loop_variables), ws(expression)),
+            close("}}}"),
         )),
-        |(span, subject)| Token::Each { span, subject },
+        |(span, (names, subject))| {
+            let (key, value) = match names {
+                Some((key, value)) => (key, Some(value)),
+                None => (None, None),
+            };
+            Token::Each {
+                span,
+                key,
+                value,
+                subject,
+            }
+        },
     )(input)
 }
 
 fn new_if(input: Span) -> IResult<Span, Token<Span>> {
     map(
         consumed(delimited(
-            pair(tag("{{{"), ws(tag("if"))),
+            pair(open("{{{"), ws(tag("if"))),
             ws(expression),
-            tag("}}}"),
+            close("}}}"),
         )),
         |(span, subject)| Token::If { span, subject },
     )(input)
@@ -116,7 +252,7 @@ fn new_if(input: Span) -> IResult<Span, Token<Span>> {
 
 fn new_else(input: Span) -> IResult<Span, Token<Span>> {
     map(
-        recognize(delimited(tag("{{{"), ws(tag("else")), tag("}}}"))),
+        recognize(delimited(open("{{{"), ws(tag("else")), close("}}}"))),
         |span| Token::Else { span },
     )(input)
 }
@@ -128,17 +264,55 @@ fn trim_end(input: Span) -> Span {
 fn new_end(input: Span) -> IResult<Span, Token<Span>> {
     map(
         consumed(delimited(
-            pair(tag("{{{"), ws(tag("end"))),
+            pair(open("{{{"), ws(tag("end"))),
             ws(take_until("}")),
-            tag("}}}"),
+            close("}}}"),
         )),
         |(span, subject)| Token::End {
             span,
-            subject_raw: trim_end(subject),
+            subject_raw: strip_trim_marker(subject),
         },
     )(input)
 }
 
+/// name of an included template or block, a string without escapes
+fn include_name(input: Span) -> IResult<Span, Span> {
+    delimited(tag("\""), is_not("\\\""), tag("\""))(input)
+}
+
+fn new_include(input: Span) -> IResult<Span, Token<Span>> {
+    map(
+        consumed(delimited(
+            pair(open("{{{"), ws(tag("include"))),
+            ws(include_name),
+            close("}}}"),
+        )),
+        |(span, name)| Token::Include { span, name },
+    )(input)
+}
+
+fn new_extends(input: Span) -> IResult<Span, Token<Span>> {
+    map(
+        consumed(delimited(
+            pair(open("{{{"), ws(tag("extends"))),
+            ws(include_name),
+            close("}}}"),
+        )),
+        |(span, name)| Token::Extends { span, name },
+    )(input)
+}
+
+fn new_block(input: Span) -> IResult<Span, Token<Span>> {
+    map(
+        consumed(delimited(
+            pair(open("{{{"), ws(tag("block"))),
+            ws(include_name),
+            close("}}}"),
+        )),
+        |(span, name)| Token::Block { span, name },
+    )(input)
+}
+
 fn legacy_begin(input: Span) -> IResult<Span, Token<Span>> {
     map(
         consumed(delimited(
@@ -207,6 +381,19 @@ fn legacy_end(input: Span) -> IResult<Span, Token<Span>> {
     )(input)
 }
 
+fn legacy_include(input: Span) -> IResult<Span, Token<Span>> {
+    map(
+        consumed(delimited(
+            pair(tag("<!--"), ws(tag("IMPORT"))),
+            verify(map(take_until("-->"), trim_end), |name: &Span| {
+                !name.is_empty()
+            }),
+            tag("-->"),
+        )),
+        |(span, name)| Token::LegacyInclude { span, name },
+    )(input)
+}
+
 fn token(input: Span) -> IResult<Span, Token<Span>> {
     alt((
         interp_escaped,
@@ -215,10 +402,14 @@ fn token(input: Span) -> IResult<Span, Token<Span>> {
         new_if,
         new_else,
         new_end,
+        new_include,
+        new_extends,
+        new_block,
         legacy_begin,
         legacy_if,
         legacy_else,
         legacy_end,
+        legacy_include,
     ))(input)
 }
 
@@ -241,8 +432,10 @@ static TOKEN_START: LazyLock<AhoCorasick> = LazyLock::new(|| {
         .unwrap()
 });
 
-#[rustfmt::skip::macros(warn)]
-pub fn tokens(mut input: Span) -> IResult<Span, Vec<Token<Span>>> {
+pub fn tokens<'a>(
+    mut input: Span<'a>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> IResult<Span<'a>, Vec<Token<Span<'a>>>> {
     let mut tokens = vec![];
     let mut index = 0;
 
@@ -258,7 +451,7 @@ pub fn tokens(mut input: Span) -> IResult<Span, Vec<Token<Span>>> {
                     // Not a match, step to the next character
                     Err(nom::Err::Error(_)) => {
                         let syntax_warning = |closer: &str| {
-                            let (line, column, padding) = slice.get_line_column_padding();
+                            let line = slice.get_line();
 
                             // restrict search to end of line
                             let consumed_line_end = line.len() - line.offset(slice.fragment());
@@ -269,37 +462,42 @@ pub fn tokens(mut input: Span) -> IResult<Span, Vec<Token<Span>>> {
                                 |end| consumed_line.slice(..(end + closer.len())),
                             );
 
-                            warn!("[benchpress] warning: probable template syntax error");
-                            warn!("     --> {}:{}:{}",
-                                span.extra.filename, span.location_line(), column);
-                            warn!("      |");
-                            warn!("{:>5} | {}", span.location_line(), line);
-                            warn!("      | {}{} this looks like a template token, but a parse error caused it to be passed through as text",
-                                padding, "^".repeat(span.len()));
-                            warn!("      | help: if this is supposed to be literal text, escape it like `\\{span}`");
-                            warn!("      | note: This will become an error in the future.\n");
+                            Diagnostic::warning(
+                                Code::ProbableSyntaxError,
+                                span,
+                                "probable template syntax error",
+                            )
+                            .primary(
+                                span,
+                                "this looks like a template token, but a parse error caused it to be passed through as text",
+                            )
+                            .help(format!(
+                                "if this is supposed to be literal text, escape it like `\\{span}`"
+                            ))
+                            .note("This will become an error in the future.")
                         };
 
                         match i.pattern().as_u32() {
                             // {{{ => }}}
-                            4 => syntax_warning("}}}"),
+                            4 => diagnostics.push(syntax_warning("}}}")),
                             // {{ => }}
-                            5 => syntax_warning("}}"),
+                            5 => diagnostics.push(syntax_warning("}}")),
                             // <!-- => -->
                             7 => {
                                 // try to make sure this looks like a template token
-                                // <!-- IF, <!-- ELSE, <!-- ENDIF, <!-- BEGIN, <!-- END
+                                // <!-- IF, <!-- ELSE, <!-- ENDIF, <!-- BEGIN, <!-- END, <!-- IMPORT
                                 let slice = slice.slice(4..).trim_start();
                                 let alike = slice
                                     .strip_prefix("IF")
                                     .or_else(|| slice.strip_prefix("ELSE"))
                                     .or_else(|| slice.strip_prefix("ENDIF"))
                                     .or_else(|| slice.strip_prefix("BEGIN"))
+                                    .or_else(|| slice.strip_prefix("IMPORT"))
                                     .is_some_and(|rest| {
                                         rest.starts_with(|c: char| c.is_whitespace())
                                     });
                                 if alike {
-                                    syntax_warning("-->")
+                                    diagnostics.push(syntax_warning("-->"))
                                 }
                             }
                             _ => (),
@@ -331,6 +529,24 @@ pub fn tokens(mut input: Span) -> IResult<Span, Vec<Token<Span>>> {
                         input = rest;
                         index = 0;
                     }
+                    // A number literal which can't be represented
+                    Err(nom::Err::Failure(e)) if e.code == nom::error::ErrorKind::Float => {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                Code::InvalidNumber,
+                                e.input,
+                                "number literal out of range",
+                            )
+                            .primary(e.input, "this is too large to be represented as a number"),
+                        );
+
+                        // step past the opener, like a token which didn't match
+                        while {
+                            // do-while
+                            index += i.end() - i.start();
+                            !input.is_char_boundary(index)
+                        } {}
+                    }
                     // Pass through other errors
                     Err(e) => return Err(e),
                 }
@@ -358,15 +574,16 @@ pub fn tokens(mut input: Span) -> IResult<Span, Vec<Token<Span>>> {
                 };
                 let span = expr.span();
 
-                let (line, column, padding) = span.get_line_column_padding();
-                warn!("[benchpress] warning: keyword outside an interpolation token is deprecated");
-                warn!("     --> {}:{}:{}",
-                    span.extra.filename, span.location_line(), column);
-                warn!("      |");
-                warn!("{:>5} | {}", span.location_line(), line);
-                warn!("      | {}{} help: wrap this in curly braces: `{{{}}}`",
-                    padding, "^".repeat(span.len()), span);
-                warn!("      | note: This will become an error in v3.0.0\n");
+                diagnostics.push(
+                    Diagnostic::warning(
+                        Code::BareKeyword,
+                        span,
+                        "keyword outside an interpolation token is deprecated",
+                    )
+                    .primary(span, "")
+                    .help(format!("wrap this in curly braces: `{{{span}}}`"))
+                    .note("This will become an error in v3.0.0"),
+                );
 
                 // Add text before the token
                 if start > 0 {
@@ -390,7 +607,155 @@ pub fn tokens(mut input: Span) -> IResult<Span, Vec<Token<Span>>> {
         tokens.push(Token::Text(input.slice(..index)));
     }
 
-    Ok((input.slice(input.len()..), tokens))
+    Ok((input.slice(input.len()..), apply_trim_markers(tokens)))
+}
+
+/// whether a token has a `-` trim marker after its opener and before its closer,
+/// like `{{{- if x -}}}` or `{- value -}`
+fn trim_markers(tok: &Token<Span>) -> (bool, bool) {
+    if let Token::Text(_) = tok {
+        return (false, false);
+    }
+
+    let span = tok.span();
+    let inner = span.trim_start_matches('{');
+    let before = inner.len() < span.len()
+        && inner
+            .strip_prefix('-')
+            .is_some_and(|rest| rest.starts_with(char::is_whitespace));
+
+    let inner = span.trim_end_matches('}');
+    let after = inner.len() < span.len()
+        && inner
+            .strip_suffix('-')
+            .is_some_and(|rest| rest.ends_with(char::is_whitespace));
+
+    (before, after)
+}
+
+fn trim_start(input: Span) -> Span {
+    input.slice((input.len() - input.trim_start().len())..)
+}
+
+/// strip whitespace from text next to tokens with trim markers,
+/// dropping text which ends up empty
+fn apply_trim_markers(mut tokens: Vec<Token<Span>>) -> Vec<Token<Span>> {
+    for index in 0..tokens.len() {
+        let (before, after) = trim_markers(&tokens[index]);
+
+        if before && index > 0 {
+            if let Token::Text(text) = &mut tokens[index - 1] {
+                *text = trim_end(*text);
+            }
+        }
+        if after {
+            if let Some(Token::Text(text)) = tokens.get_mut(index + 1) {
+                *text = trim_start(*text);
+            }
+        }
+    }
+
+    tokens.retain(|tok| !matches!(tok, Token::Text(text) if text.is_empty()));
+    tokens
+}
+
+/// whether a token opens, continues, or closes a block
+fn is_block_token<S>(tok: &Token<S>) -> bool {
+    matches!(
+        tok,
+        Token::If { .. }
+            | Token::Each { .. }
+            | Token::Else { .. }
+            | Token::End { .. }
+            | Token::Block { .. }
+            | Token::LegacyIf { .. }
+            | Token::LegacyBegin { .. }
+            | Token::LegacyElse { .. }
+            | Token::LegacyEnd { .. }
+    )
+}
+
+fn is_blank(text: &str) -> bool {
+    text.trim().is_empty()
+}
+
+/// remove lines holding only block tokens and whitespace,
+/// like Mustache standalone tags
+pub fn strip_standalone_lines(mut tokens: Vec<Token<Span>>) -> Vec<Token<Span>> {
+    // the part of each text token to keep, as offsets into the token
+    let mut keep: Vec<(usize, usize)> = tokens
+        .iter()
+        .map(|tok| match tok {
+            Token::Text(text) => (0, text.len()),
+            _ => (0, 0),
+        })
+        .collect();
+    let text = |index: usize| match tokens.get(index) {
+        Some(Token::Text(text)) => Some(*text.fragment()),
+        _ => None,
+    };
+
+    let mut index = 0;
+    while index < tokens.len() {
+        if !is_block_token(&tokens[index]) {
+            index += 1;
+            continue;
+        }
+
+        // a run of block tokens, separated only by whitespace, on one line
+        let start = index;
+        let mut end = index;
+        let mut next = index + 1;
+        while next < tokens.len() {
+            match text(next) {
+                Some(text) if is_blank(text) && !text.contains('\n') => next += 1,
+                Some(_) => break,
+                None if is_block_token(&tokens[next]) => {
+                    end = next;
+                    next += 1;
+                }
+                None => break,
+            }
+        }
+        index = end + 1;
+
+        let before = match start.checked_sub(1) {
+            None => Some(0),
+            Some(prev) => text(prev).and_then(|text| match text.rfind('\n') {
+                Some(newline) if is_blank(&text[newline..]) => Some(newline + 1),
+                None if prev == 0 && is_blank(text) => Some(0),
+                _ => None,
+            }),
+        };
+        let after = match text(end + 1) {
+            None if end + 1 == tokens.len() => Some(0),
+            None => None,
+            Some(text) => match text.find('\n') {
+                Some(newline) if is_blank(&text[..newline]) => Some(newline + 1),
+                None if end + 2 == tokens.len() && is_blank(text) => Some(text.len()),
+                _ => None,
+            },
+        };
+
+        if let (Some(before), Some(after)) = (before, after) {
+            if start > 0 {
+                keep[start - 1].1 = before;
+            }
+            keep[start..=end].fill((0, 0));
+            if end + 1 < tokens.len() {
+                keep[end + 1].0 = after;
+            }
+        }
+    }
+
+    for (tok, (start, end)) in tokens.iter_mut().zip(keep) {
+        if let Token::Text(text) = tok {
+            *text = text.slice(start..end.max(start));
+        }
+    }
+
+    tokens.retain(|tok| !matches!(tok, Token::Text(text) if text.is_empty()));
+    tokens
 }
 
 #[cfg(test)]
@@ -420,8 +785,15 @@ mod test {
                     span: *span.fragment(),
                     subject: subject.span_to_str(),
                 },
-                Token::Each { span, subject } => Token::Each {
+                Token::Each {
+                    span,
+                    key,
+                    value,
+                    subject,
+                } => Token::Each {
                     span: *span.fragment(),
+                    key: key.map(|key| *key.fragment()),
+                    value: value.map(|value| *value.fragment()),
                     subject: subject.span_to_str(),
                 },
                 Token::Else { span } => Token::Else {
@@ -446,6 +818,22 @@ mod test {
                     span: *span.fragment(),
                     subject_raw: *subject_raw.fragment(),
                 },
+                Token::Include { span, name } => Token::Include {
+                    span: *span.fragment(),
+                    name: *name.fragment(),
+                },
+                Token::LegacyInclude { span, name } => Token::LegacyInclude {
+                    span: *span.fragment(),
+                    name: *name.fragment(),
+                },
+                Token::Extends { span, name } => Token::Extends {
+                    span: *span.fragment(),
+                    name: *name.fragment(),
+                },
+                Token::Block { span, name } => Token::Block {
+                    span: *span.fragment(),
+                    name: *name.fragment(),
+                },
             }
         }
     }
@@ -467,15 +855,16 @@ mod test {
     #[test]
     fn test_comments() {
         // actual offending code from issue63
-        tokens(sp(
-            "<!--<p>⚠️ Forum Maintenance: Feb 6th, 8am - 14pm (UTC+2)</p>-->",
-        ))
+        tokens(
+            sp("<!--<p>⚠️ Forum Maintenance: Feb 6th, 8am - 14pm (UTC+2)</p>-->"),
+            &mut vec![],
+        )
         .unwrap();
 
         // some fuzzing just to make sure
         for n in 0..8 {
             let comment = format!("<!--{}⚠️⚠️⚠️⚠️⚠️⚠️⚠️⚠️⚠️⚠️⚠️-->", " ".repeat(n));
-            tokens(sp(&comment)).unwrap();
+            tokens(sp(&comment), &mut vec![]).unwrap();
 
             for sbraces in 1..=3 {
                 for ebraces in 1..=3 {
@@ -485,7 +874,7 @@ mod test {
                         " ".repeat(n),
                         "}".repeat(ebraces)
                     );
-                    tokens(sp(&input)).unwrap();
+                    tokens(sp(&input), &mut vec![]).unwrap();
                 }
             }
         }
@@ -592,6 +981,8 @@ mod test {
                 "",
                 Token::Each {
                     span: "{{{each abc.def}}}",
+                    key: None,
+                    value: None,
                     subject: Expression::Path {
                         span: "abc.def",
                         path: vec![PathPart::Part("abc"), PathPart::Part("def")]
@@ -605,6 +996,8 @@ mod test {
                 "",
                 Token::Each {
                     span: "{{{ each call() }}}",
+                    key: None,
+                    value: None,
                     subject: Expression::Helper {
                         span: "call()",
                         name: "call",
@@ -613,6 +1006,53 @@ mod test {
                 }
             ))
         );
+        assert_eq_unspan!(
+            new_each(sp("{{{ each post in posts }}}")),
+            Ok((
+                "",
+                Token::Each {
+                    span: "{{{ each post in posts }}}",
+                    key: None,
+                    value: Some("post"),
+                    subject: Expression::Path {
+                        span: "posts",
+                        path: vec![PathPart::Part("posts")]
+                    }
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            new_each(sp("{{{ each key , post_2 in index }}}")),
+            Ok((
+                "",
+                Token::Each {
+                    span: "{{{ each key , post_2 in index }}}",
+                    key: Some("key"),
+                    value: Some("post_2"),
+                    subject: Expression::Path {
+                        span: "index",
+                        path: vec![PathPart::Part("index")]
+                    }
+                }
+            ))
+        );
+        // `in` has to be its own word
+        assert_eq_unspan!(
+            new_each(sp("{{{ each inbox }}}")),
+            Ok((
+                "",
+                Token::Each {
+                    span: "{{{ each inbox }}}",
+                    key: None,
+                    value: None,
+                    subject: Expression::Path {
+                        span: "inbox",
+                        path: vec![PathPart::Part("inbox")]
+                    }
+                }
+            ))
+        );
+        assert!(new_each(sp("{{{ each post.title in posts }}}")).is_err());
     }
 
     #[test]
@@ -654,6 +1094,113 @@ mod test {
                 }
             ))
         );
+        assert_eq_unspan!(
+            new_end(sp("{{{- end abc -}}}")),
+            Ok((
+                "",
+                Token::End {
+                    span: "{{{- end abc -}}}",
+                    subject_raw: "abc"
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            new_end(sp("{{{ end abc-}}}")),
+            Ok((
+                "",
+                Token::End {
+                    span: "{{{ end abc-}}}",
+                    subject_raw: "abc-"
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_standalone_lines() {
+        fn strip(source: &str) -> String {
+            let toks = tokens(sp(source), &mut vec![]).unwrap().1;
+            strip_standalone_lines(toks)
+                .iter()
+                .map(|tok| match tok {
+                    Token::Text(text) => *text.fragment(),
+                    _ => "#",
+                })
+                .collect()
+        }
+
+        assert_eq!(
+            strip("<ul>\n  {{{ each a }}}\n  <li>{@value}</li>\n  {{{ end }}}\n</ul>"),
+            "<ul>\n#  <li>#</li>\n#</ul>"
+        );
+        // several block tokens on one line, at the start and end of the source
+        assert_eq!(
+            strip(
+                "{{{ if a }}} <!-- BEGIN b -->\r\nx\n\t<!-- END b -->{{{ else }}}\n  {{{ end }}}"
+            ),
+            "##x\n###"
+        );
+        // lines with other content are kept
+        assert_eq!(
+            strip("a {{{ if a }}}\n{{{ end }}} b\n{{{ if c }}}{c}\n{{{ end }}}"),
+            "a #\n# b\n##\n#"
+        );
+    }
+
+    #[test]
+    fn test_trim_markers() {
+        assert_eq_unspan!(
+            new_if(sp("{{{- if abc -}}}")),
+            Ok((
+                "",
+                Token::If {
+                    span: "{{{- if abc -}}}",
+                    subject: Expression::Path {
+                        span: "abc",
+                        path: vec![PathPart::Part("abc")]
+                    }
+                }
+            ))
+        );
+        assert_eq_unspan!(
+            interp_escaped(sp("{- prop -}")),
+            Ok((
+                "",
+                Token::InterpEscaped {
+                    span: "{- prop -}",
+                    expr: Expression::Path {
+                        span: "prop",
+                        path: vec![PathPart::Part("prop")]
+                    }
+                }
+            ))
+        );
+        // a marker must be separated from the expression by whitespace
+        assert_eq_unspan!(
+            interp_escaped(sp("{-prop}")),
+            Ok((
+                "",
+                Token::InterpEscaped {
+                    span: "{-prop}",
+                    expr: Expression::Path {
+                        span: "-prop",
+                        path: vec![PathPart::Part("-prop")]
+                    }
+                }
+            ))
+        );
+
+        let source =
+            "<ul>\n  {{{- each posts -}}}\n    <li>{- @value -}</li>  \n  {{{- end -}}}\n</ul>";
+        let toks = tokens(sp(source), &mut vec![]).unwrap().1;
+        let text: Vec<&str> = toks
+            .iter()
+            .filter_map(|tok| match tok {
+                Token::Text(text) => Some(*text.fragment()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, vec!["<ul>", "<li>", "</li>", "</ul>"]);
     }
 
     #[test]
@@ -712,6 +1259,24 @@ mod test {
                 }
             ))
         );
+        // relational operators don't end the legacy token early
+        assert_eq_unspan!(
+            legacy_if(sp("<!-- IF (count > 0)-->")),
+            Ok((
+                "",
+                Token::LegacyIf {
+                    span: "<!-- IF (count > 0)-->",
+                    subject: Expression::Gt {
+                        span: "(count > 0)",
+                        lhs: Box::new(Expression::Path {
+                            span: "count",
+                            path: vec![PathPart::Part("count")]
+                        }),
+                        rhs: Box::new(Expression::NumberLiteral("0")),
+                    }
+                }
+            ))
+        );
     }
 
     #[test]
@@ -767,6 +1332,67 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_new_include() {
+        assert_eq_unspan!(
+            new_include(sp("{{{ include \"partials/post\" }}}")),
+            Ok((
+                "",
+                Token::Include {
+                    span: "{{{ include \"partials/post\" }}}",
+                    name: "partials/post"
+                }
+            ))
+        );
+        assert!(new_include(sp("{{{ include partials/post }}}")).is_err());
+        assert!(new_include(sp("{{{ include \"\" }}}")).is_err());
+    }
+
+    #[test]
+    fn test_new_extends() {
+        assert_eq_unspan!(
+            new_extends(sp("{{{extends \"layout\"}}}")),
+            Ok((
+                "",
+                Token::Extends {
+                    span: "{{{extends \"layout\"}}}",
+                    name: "layout"
+                }
+            ))
+        );
+        assert!(new_extends(sp("{{{ extends layout }}}")).is_err());
+    }
+
+    #[test]
+    fn test_new_block() {
+        assert_eq_unspan!(
+            new_block(sp("{{{ block \"content\" }}}")),
+            Ok((
+                "",
+                Token::Block {
+                    span: "{{{ block \"content\" }}}",
+                    name: "content"
+                }
+            ))
+        );
+        assert!(new_block(sp("{{{ block }}}")).is_err());
+    }
+
+    #[test]
+    fn test_legacy_include() {
+        assert_eq_unspan!(
+            legacy_include(sp("<!-- IMPORT partials/topic-list.tpl -->")),
+            Ok((
+                "",
+                Token::LegacyInclude {
+                    span: "<!-- IMPORT partials/topic-list.tpl -->",
+                    name: "partials/topic-list.tpl"
+                }
+            ))
+        );
+        assert!(legacy_include(sp("<!-- IMPORT -->")).is_err());
+    }
+
     #[test]
     fn test_legacy_end() {
         assert_eq_unspan!(
@@ -831,7 +1457,7 @@ mod test {
         }
 
         assert_eq_unspan!(
-            tokens(sp("before @value other stuff")),
+            tokens(sp("before @value other stuff"), &mut vec![]),
             Ok((
                 "",
                 vec![
@@ -850,7 +1476,8 @@ mod test {
 
         assert_eq_unspan!(
             tokens(
-                sp("before {{{ if abc }}} we do one thing {{{ else }}} we do another {{{ end }}} other stuff")
+                sp("before {{{ if abc }}} we do one thing {{{ else }}} we do another {{{ end }}} other stuff"),
+                &mut vec![]
             ),
             Ok((
                 "",
@@ -875,7 +1502,7 @@ mod test {
         assert_eq_unspan!(
             tokens(sp(
                 "{{{ if abc }}} we do one thing {{{ else }}} we do another {{{ end }}} other stuff"
-            )),
+            ), &mut vec![]),
             Ok((
                 "",
                 vec![
@@ -901,13 +1528,18 @@ mod test {
         );
 
         assert_eq_unspan!(
-            tokens(sp("before {{{ each abc }}} for each thing {{{ end }}}")),
+            tokens(
+                sp("before {{{ each abc }}} for each thing {{{ end }}}"),
+                &mut vec![]
+            ),
             Ok((
                 "",
                 vec![
                     Token::Text("before "),
                     Token::Each {
                         span: "{{{ each abc }}}",
+                        key: None,
+                        value: None,
                         subject: Expression::Path {
                             span: "abc",
                             path: vec![PathPart::Part("abc")]
@@ -923,12 +1555,17 @@ mod test {
         );
 
         assert_eq_unspan!(
-            tokens(sp("{{{ each abc }}} for each thing {{{ end }}}")),
+            tokens(
+                sp("{{{ each abc }}} for each thing {{{ end }}}"),
+                &mut vec![]
+            ),
             Ok((
                 "",
                 vec![
                     Token::Each {
                         span: "{{{ each abc }}}",
+                        key: None,
+                        value: None,
                         subject: Expression::Path {
                             span: "abc",
                             path: vec![PathPart::Part("abc")]
@@ -944,7 +1581,10 @@ mod test {
         );
 
         assert_eq_unspan!(
-            tokens(sp("{{{ each *abc }}} for each thing {{{ end }}}")),
+            tokens(
+                sp("{{{ each *abc }}} for each thing {{{ end }}}"),
+                &mut vec![]
+            ),
             Ok((
                 "",
                 vec![
@@ -959,7 +1599,7 @@ mod test {
 
         let program = "before \\{{{ each abc }}} for each thing \\{{{ end }}}";
         assert_eq_unspan!(
-            tokens(sp(program)),
+            tokens(sp(program), &mut vec![]),
             Ok((
                 "",
                 vec![
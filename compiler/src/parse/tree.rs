@@ -1,7 +1,14 @@
 use crate::{
-    console::warn,
+    diagnostic::{
+        Code,
+        Diagnostic,
+    },
     parse::{
-        expression::Expression,
+        expression::{
+            Expression,
+            Keyword,
+        },
+        include::Partials,
         path::{
             resolve,
             Path,
@@ -10,7 +17,6 @@ use crate::{
         },
         tokens::Token,
         Span,
-        SpanExt,
     },
 };
 use std::collections::HashSet;
@@ -34,13 +40,75 @@ pub enum Instruction<S> {
         body: Vec<Instruction<S>>,
         alt: Vec<Instruction<S>>,
     },
+    // `{{{ include "name" }}}`, rendered at runtime
+    Include {
+        name: S,
+    },
+    // `{{{ block "name" }}}`, which templates extending this one can replace
+    Block {
+        name: S,
+        body: Vec<Instruction<S>>,
+    },
+}
+
+impl<S> Instruction<S> {
+    /// convert the span type, keeping the structure
+    pub fn map_spans<T>(self, f: &mut impl FnMut(S) -> T) -> Instruction<T> {
+        let map_body = |body: Vec<Instruction<S>>, f: &mut _| {
+            body.into_iter()
+                .map(|instruction| instruction.map_spans(f))
+                .collect()
+        };
+
+        match self {
+            Instruction::Text(span) => Instruction::Text(f(span)),
+            Instruction::InterpEscaped(expr) => Instruction::InterpEscaped(expr.map_spans(f)),
+            Instruction::InterpRaw(expr) => Instruction::InterpRaw(expr.map_spans(f)),
+            Instruction::If { subject, body, alt } => Instruction::If {
+                subject: subject.map_spans(f),
+                body: map_body(body, f),
+                alt: map_body(alt, f),
+            },
+            Instruction::Iter {
+                depth,
+                subject,
+                body,
+                alt,
+            } => Instruction::Iter {
+                depth,
+                subject: subject.map_spans(f),
+                body: map_body(body, f),
+                alt: map_body(alt, f),
+            },
+            Instruction::Include { name } => Instruction::Include { name: f(name) },
+            Instruction::Block { name, body } => Instruction::Block {
+                name: f(name),
+                body: map_body(body, f),
+            },
+        }
+    }
+}
+
+impl<'a> Instruction<Span<'a>> {
+    /// where the instruction starts in the template, for diagnostics
+    fn span(&self) -> Span<'a> {
+        match self {
+            Instruction::Text(span)
+            | Instruction::Include { name: span }
+            | Instruction::Block { name: span, .. } => *span,
+            Instruction::InterpEscaped(expr) | Instruction::InterpRaw(expr) => expr.span(),
+            Instruction::If { subject, .. } | Instruction::Iter { subject, .. } => subject.span(),
+        }
+    }
 }
 
 /// in a case where there are extra End tokens
 /// try to match them to Ifs or Iters
 /// and remove the extra ones
-#[rustfmt::skip::macros(warn)]
-pub fn fix_extra_tokens<'a>(input: Vec<Token<Span<'a>>>) -> Vec<Token<Span<'a>>> {
+pub fn fix_extra_tokens<'a>(
+    input: Vec<Token<Span<'a>>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Token<Span<'a>>> {
     let mut remove: HashSet<Token<Span<'a>>> = HashSet::new();
     let mut expected_subjects: Vec<&str> = Vec::new();
 
@@ -62,6 +130,10 @@ pub fn fix_extra_tokens<'a>(input: Vec<Token<Span<'a>>>) -> Vec<Token<Span<'a>>>
                 expected_subjects.push(subject_raw);
                 starts_count += 1;
             }
+            Token::Block { name, .. } => {
+                expected_subjects.push(name.fragment());
+                starts_count += 1;
+            }
             Token::LegacyEnd { subject_raw, .. } | Token::End { subject_raw, .. } => {
                 ends_count += 1;
 
@@ -80,18 +152,19 @@ pub fn fix_extra_tokens<'a>(input: Vec<Token<Span<'a>>>) -> Vec<Token<Span<'a>>>
                                 Token::LegacyIf { .. }
                                 | Token::LegacyBegin { .. }
                                 | Token::If { .. }
-                                | Token::Each { .. } => {
+                                | Token::Each { .. }
+                                | Token::Block { .. } => {
                                     break;
                                 }
                                 Token::LegacyEnd { subject_raw, .. }
-                                | Token::End { subject_raw, .. } => {
-                                    if subject_raw.fragment() == &expected_subject {
-                                        // found one ahead, so remove the current one
-                                        remove.insert(elem.clone());
-                                        expected_subjects.push(expected_subject);
-
-                                        break;
-                                    }
+                                | Token::End { subject_raw, .. }
+                                    if subject_raw.fragment() == &expected_subject =>
+                                {
+                                    // found one ahead, so remove the current one
+                                    remove.insert(elem.clone());
+                                    expected_subjects.push(expected_subject);
+
+                                    break;
                                 }
                                 _ => (),
                             }
@@ -110,20 +183,21 @@ pub fn fix_extra_tokens<'a>(input: Vec<Token<Span<'a>>>) -> Vec<Token<Span<'a>>>
     if ends_count > starts_count {
         let mut diff = ends_count - starts_count;
 
-        warn!("[benchpress] warning: found extra tokens");
-
         let output: Vec<Token<Span>> = input
             .into_iter()
             .map(|tok| {
                 if diff > 0 && remove.contains(&tok) {
                     let span = tok.span();
-                    let (line, column, padding) = span.get_line_column_padding();
-                    warn!("     --> {}:{}:{}",
-                        span.extra.filename, span.location_line(), column);
-                    warn!("      |");
-                    warn!("{:>5} | {}", span.location_line(), line);
-                    warn!("      | {}{} help: remove the token, make it an unambiguous comment, or escape it like `\\{}`",
-                        padding, "^".repeat(span.len()), span);
+                    diagnostics.push(
+                        Diagnostic::warning(Code::ExtraTokens, span, "found extra tokens")
+                            .primary(span, "")
+                            .help(format!(
+                                "remove the token, make it an unambiguous comment, or escape it like `\\{span}`"
+                            ))
+                            .note(
+                                "These tokens will be passed through as text, but this will become an error in the future.",
+                            ),
+                    );
 
                     diff -= 1;
                     // replace removed instructions with their source Text
@@ -134,35 +208,123 @@ pub fn fix_extra_tokens<'a>(input: Vec<Token<Span<'a>>>) -> Vec<Token<Span<'a>>>
             })
             .collect();
 
-        warn!("      = note: These tokens will be passed through as text, but this will become an error in the future.\n");
-
         output
     } else {
         input
     }
 }
 
+/// a name bound by `{{{ each key, value in arr }}}`
+#[derive(Debug, Clone)]
+pub enum Alias<'a> {
+    // the key of the loop at `depth`
+    Key {
+        name: &'a str,
+        depth: u32,
+    },
+    // the item of the loop at `depth`,
+    // with the path to it if the loop subject was a path
+    Value {
+        name: &'a str,
+        depth: u32,
+        path: Option<PathBuf<Span<'a>>>,
+    },
+}
+
+impl<'a> Alias<'a> {
+    fn name(&self) -> &'a str {
+        match self {
+            Alias::Key { name, .. } | Alias::Value { name, .. } => name,
+        }
+    }
+}
+
 fn resolve_expression_paths<'a>(
     base: Path<'_, Span<'a>>,
+    aliases: &[Alias<'a>],
     expr: Expression<Span<'a>>,
 ) -> Expression<Span<'a>> {
     match expr {
         s @ Expression::StringLiteral(_) => s,
+        n @ Expression::NumberLiteral(_) => n,
         k @ Expression::Keyword { .. } => k,
-        Expression::Path { span, path } => Expression::Path {
+        k @ Expression::KeywordDepth { .. } => k,
+        Expression::Path { span, path } => {
+            // subscripts are resolved where they're written,
+            // before the path is joined to the parts from `base`
+            let path: PathBuf<Span> = path
+                .into_iter()
+                .map(|part| match part {
+                    PathPart::Index(span, expr) => PathPart::Index(
+                        span,
+                        Box::new(resolve_expression_paths(base, aliases, *expr)),
+                    ),
+                    part => part,
+                })
+                .collect();
+
+            // the innermost loop variable with the name shadows the others,
+            // and any property of the same name in the data
+            let alias = aliases
+                .iter()
+                .rev()
+                .find(|alias| alias.name() == path[0].inner());
+            match alias {
+                Some(Alias::Key { depth, .. }) if path.len() == 1 => Expression::KeywordDepth {
+                    span,
+                    keyword: Keyword::Key,
+                    depth: *depth,
+                },
+                Some(Alias::Value {
+                    path: Some(item), ..
+                }) => {
+                    let mut item = item.clone();
+                    item.extend(path.into_iter().skip(1));
+                    Expression::Path { span, path: item }
+                }
+                Some(Alias::Value {
+                    depth, path: None, ..
+                }) if path.len() == 1 => Expression::KeywordDepth {
+                    span,
+                    keyword: Keyword::Value,
+                    depth: *depth,
+                },
+                _ => Expression::Path {
+                    span,
+                    path: resolve(base, path),
+                },
+            }
+        }
+        Expression::Negative { span, expr } => Expression::Negative {
             span,
-            path: resolve(base, path),
+            expr: Box::new(resolve_expression_paths(base, aliases, *expr)),
         },
-        Expression::Negative { span, expr } => Expression::Negative {
+        Expression::Array { span, items } => Expression::Array {
+            span,
+            items: items
+                .into_iter()
+                .map(|x| resolve_expression_paths(base, aliases, x))
+                .collect(),
+        },
+        Expression::Range { span, start, end } => Expression::Range {
+            span,
+            start: Box::new(resolve_expression_paths(base, aliases, *start)),
+            end: Box::new(resolve_expression_paths(base, aliases, *end)),
+        },
+        Expression::Length { span, subject } => Expression::Length {
             span,
-            expr: Box::new(resolve_expression_paths(base, *expr)),
+            subject: Box::new(resolve_expression_paths(base, aliases, *subject)),
+        },
+        Expression::Empty { span, subject } => Expression::Empty {
+            span,
+            subject: Box::new(resolve_expression_paths(base, aliases, *subject)),
         },
         Expression::Helper { span, name, args } => Expression::Helper {
             span,
             name,
             args: args
                 .into_iter()
-                .map(|x| resolve_expression_paths(base, x))
+                .map(|x| resolve_expression_paths(base, aliases, x))
                 .collect(),
         },
         Expression::LegacyHelper { span, name, args } => Expression::LegacyHelper {
@@ -170,42 +332,78 @@ fn resolve_expression_paths<'a>(
             name,
             args: args
                 .into_iter()
-                .map(|x| resolve_expression_paths(base, x))
+                .map(|x| resolve_expression_paths(base, aliases, x))
                 .collect(),
         },
         Expression::Equ { span, lhs, rhs } => Expression::Equ {
             span,
-            lhs: Box::new(resolve_expression_paths(base, *lhs)),
-            rhs: Box::new(resolve_expression_paths(base, *rhs)),
+            lhs: Box::new(resolve_expression_paths(base, aliases, *lhs)),
+            rhs: Box::new(resolve_expression_paths(base, aliases, *rhs)),
         },
         Expression::Neq { span, lhs, rhs } => Expression::Neq {
             span,
-            lhs: Box::new(resolve_expression_paths(base, *lhs)),
-            rhs: Box::new(resolve_expression_paths(base, *rhs)),
+            lhs: Box::new(resolve_expression_paths(base, aliases, *lhs)),
+            rhs: Box::new(resolve_expression_paths(base, aliases, *rhs)),
+        },
+        Expression::Lt { span, lhs, rhs } => Expression::Lt {
+            span,
+            lhs: Box::new(resolve_expression_paths(base, aliases, *lhs)),
+            rhs: Box::new(resolve_expression_paths(base, aliases, *rhs)),
+        },
+        Expression::Lte { span, lhs, rhs } => Expression::Lte {
+            span,
+            lhs: Box::new(resolve_expression_paths(base, aliases, *lhs)),
+            rhs: Box::new(resolve_expression_paths(base, aliases, *rhs)),
+        },
+        Expression::Gt { span, lhs, rhs } => Expression::Gt {
+            span,
+            lhs: Box::new(resolve_expression_paths(base, aliases, *lhs)),
+            rhs: Box::new(resolve_expression_paths(base, aliases, *rhs)),
+        },
+        Expression::Gte { span, lhs, rhs } => Expression::Gte {
+            span,
+            lhs: Box::new(resolve_expression_paths(base, aliases, *lhs)),
+            rhs: Box::new(resolve_expression_paths(base, aliases, *rhs)),
         },
         Expression::And { span, lhs, rhs } => Expression::And {
             span,
-            lhs: Box::new(resolve_expression_paths(base, *lhs)),
-            rhs: Box::new(resolve_expression_paths(base, *rhs)),
+            lhs: Box::new(resolve_expression_paths(base, aliases, *lhs)),
+            rhs: Box::new(resolve_expression_paths(base, aliases, *rhs)),
         },
         Expression::Or { span, lhs, rhs } => Expression::Or {
             span,
-            lhs: Box::new(resolve_expression_paths(base, *lhs)),
-            rhs: Box::new(resolve_expression_paths(base, *rhs)),
+            lhs: Box::new(resolve_expression_paths(base, aliases, *lhs)),
+            rhs: Box::new(resolve_expression_paths(base, aliases, *rhs)),
+        },
+        Expression::Ternary {
+            span,
+            condition,
+            then,
+            otherwise,
+        } => Expression::Ternary {
+            span,
+            condition: Box::new(resolve_expression_paths(base, aliases, *condition)),
+            then: Box::new(resolve_expression_paths(base, aliases, *then)),
+            otherwise: Box::new(resolve_expression_paths(base, aliases, *otherwise)),
         },
     }
 }
 
+/// A token was found where it can't be used, like a second `else`
 #[derive(Debug)]
-pub struct TreeError;
+pub struct TreeError<'a> {
+    pub span: Span<'a>,
+}
 
-#[rustfmt::skip::macros(warn)]
 pub fn tree<'a, 'b, I>(
     depth: u32,
     base: Path<'b, Span<'a>>,
+    aliases: &[Alias<'a>],
     input: &mut I,
     output: &mut Vec<Instruction<Span<'a>>>,
-) -> Result<Option<Token<Span<'a>>>, TreeError>
+    diagnostics: &mut Vec<Diagnostic>,
+    partials: &Partials<'a>,
+) -> Result<Option<Token<Span<'a>>>, TreeError<'a>>
 where
     I: Iterator<Item = Token<Span<'a>>> + Clone,
 {
@@ -227,76 +425,84 @@ where
             _ => unreachable!(),
         };
 
-        let (open_line, open_column, open_padding) = open_span.get_line_column_padding();
-        let (close_line, close_column, close_padding) = close_span.get_line_column_padding();
-        warn!("[benchpress] warning: mixing token types is deprecated");
-        warn!("     --> {}:{}:{}",
-            open_span.extra.filename, open_span.location_line(), open_column);
-        warn!("      |");
-        warn!("{:>5} | {}", open_span.location_line(), open_line);
-        warn!("      | {}{} `{}` started with {} syntax",
-            open_padding, "^".repeat(open_span.len()),
-            open_token, open_syntax);
-        warn!("     ::: {}:{}:{}",
-            open_span.extra.filename, close_span.location_line(), close_column);
-        warn!("      |");
-        warn!("{:>5} | {}", close_span.location_line(), close_line);
-        warn!("      | {}{} but {} syntax used for `{}`",
-            close_padding, "^".repeat(close_span.len()), close_syntax, close_token);
-        warn!("      | note: Migrate all to modern syntax. This will become an error in v3.0.0\n");
+        Diagnostic::warning(
+            Code::MixedTokens,
+            open_span,
+            "mixing token types is deprecated",
+        )
+        .primary(
+            open_span,
+            format!("`{open_token}` started with {open_syntax} syntax"),
+        )
+        .secondary(
+            close_span,
+            format!("but {close_syntax} syntax used for `{close_token}`"),
+        )
+        .note("Migrate all to modern syntax. This will become an error in v3.0.0")
     };
 
     let missing_warning = |open_span: Span, closer: &str| {
-        let (open_line, open_column, open_padding) = open_span.get_line_column_padding();
-        warn!("[benchpress] warning: block not terminated");
-        warn!("     --> {}:{}:{}",
-            open_span.extra.filename, open_span.location_line(), open_column);
-        warn!("      |");
-        warn!("{:>5} | {}", open_span.location_line(), open_line);
-        warn!("      | {}{} block started here, but was not terminated before EOF",
-            open_padding, "^".repeat(open_span.len()));
-        warn!("      | note: Add `{closer}` to terminate the block. This will become an error in v3.0.0\n");
+        Diagnostic::warning(Code::UnterminatedBlock, open_span, "block not terminated")
+            .primary(
+                open_span,
+                "block started here, but was not terminated before EOF",
+            )
+            .note(format!(
+                "Add `{closer}` to terminate the block. This will become an error in v3.0.0"
+            ))
     };
 
     while let Some(tok) = input.next() {
-        output.push(match tok {
+        let instruction = match tok {
             // convert a text token to a text instruction
             Token::Text(value) => Instruction::Text(value),
             // convert token to expression
             // generate expression
             Token::InterpEscaped { expr, .. } => {
-                Instruction::InterpEscaped(resolve_expression_paths(base, expr))
+                Instruction::InterpEscaped(resolve_expression_paths(base, aliases, expr))
             }
             Token::InterpRaw { expr, .. } => {
-                Instruction::InterpRaw(resolve_expression_paths(base, expr))
+                Instruction::InterpRaw(resolve_expression_paths(base, aliases, expr))
             }
             // create an if-then-else instruction
             Token::If { span, subject, .. } => {
                 let mut body = vec![];
                 let mut alt = vec![];
 
-                match tree(depth, base, input, &mut body)? {
+                match tree(
+                    depth,
+                    base,
+                    aliases,
+                    input,
+                    &mut body,
+                    diagnostics,
+                    partials,
+                )? {
                     Some(els @ Token::Else { .. }) | Some(els @ Token::LegacyElse { .. }) => {
                         if let Token::LegacyElse { .. } = els {
-                            mixed_warning("if", span, els)
+                            diagnostics.push(mixed_warning("if", span, els))
                         }
 
                         // consume the end after the else
-                        match tree(depth, base, input, &mut alt)? {
+                        match tree(depth, base, aliases, input, &mut alt, diagnostics, partials)? {
                             Some(Token::End { .. }) => {}
-                            Some(end @ Token::LegacyEnd { .. }) => mixed_warning("if", span, end),
-                            None => missing_warning(span, "{{{ end }}}"),
-                            _ => return Err(TreeError),
+                            Some(end @ Token::LegacyEnd { .. }) => {
+                                diagnostics.push(mixed_warning("if", span, end))
+                            }
+                            None => diagnostics.push(missing_warning(span, "{{{ end }}}")),
+                            Some(tok) => return Err(TreeError { span: tok.span() }),
                         }
                     }
                     Some(Token::End { .. }) => {}
-                    Some(end @ Token::LegacyEnd { .. }) => mixed_warning("if", span, end),
-                    None => missing_warning(span, "{{{ end }}}"),
-                    _ => return Err(TreeError),
+                    Some(end @ Token::LegacyEnd { .. }) => {
+                        diagnostics.push(mixed_warning("if", span, end))
+                    }
+                    None => diagnostics.push(missing_warning(span, "{{{ end }}}")),
+                    Some(tok) => return Err(TreeError { span: tok.span() }),
                 }
 
                 Instruction::If {
-                    subject: resolve_expression_paths(base, subject),
+                    subject: resolve_expression_paths(base, aliases, subject),
                     body,
                     alt,
                 }
@@ -304,40 +510,80 @@ where
             // create an iteration intruction
             Token::Each {
                 span,
+                key,
+                value,
                 subject,
             } => {
                 let mut body = vec![];
                 let mut alt = vec![];
 
-                let subject = resolve_expression_paths(base, subject);
-                let base: PathBuf<Span> = if let Expression::Path { path: base, .. } = &subject {
-                    let mut base = base.clone();
-                    if let Some(last) = base.last_mut() {
-                        last.with_depth(depth)
-                    }
-                    base
-                } else {
-                    base.to_vec()
-                };
+                let subject = resolve_expression_paths(base, aliases, subject);
+                let item: Option<PathBuf<Span>> =
+                    if let Expression::Path { path: base, .. } = &subject {
+                        let mut base = base.clone();
+                        if let Some(last) = base.last_mut() {
+                            last.with_depth(depth)
+                        }
+                        Some(base)
+                    } else {
+                        None
+                    };
+
+                // names from `each key, value in arr` are only bound in the body
+                let mut body_aliases = aliases.to_vec();
+                if let Some(key) = key {
+                    body_aliases.push(Alias::Key {
+                        name: key.fragment(),
+                        depth,
+                    });
+                }
+                if let Some(value) = value {
+                    body_aliases.push(Alias::Value {
+                        name: value.fragment(),
+                        depth,
+                        path: item.clone(),
+                    });
+                }
+                let base = item.unwrap_or_else(|| base.to_vec());
 
-                match tree(depth + 1, &base, input, &mut body)? {
+                match tree(
+                    depth + 1,
+                    &base,
+                    &body_aliases,
+                    input,
+                    &mut body,
+                    diagnostics,
+                    partials,
+                )? {
                     Some(els @ Token::Else { .. }) | Some(els @ Token::LegacyElse { .. }) => {
                         if let Token::LegacyElse { .. } = els {
-                            mixed_warning("each", span, els)
+                            diagnostics.push(mixed_warning("each", span, els))
                         }
 
                         // consume the end after the else
-                        match tree(depth, &base, input, &mut alt)? {
+                        match tree(
+                            depth,
+                            &base,
+                            aliases,
+                            input,
+                            &mut alt,
+                            diagnostics,
+                            partials,
+                        )? {
                             Some(Token::End { .. }) => {}
-                            Some(end @ Token::LegacyEnd { .. }) => mixed_warning("each", span, end),
-                            None => missing_warning(span, "{{{ end }}}"),
-                            _ => return Err(TreeError),
+                            Some(end @ Token::LegacyEnd { .. }) => {
+                                diagnostics.push(mixed_warning("each", span, end))
+                            }
+                            None => diagnostics.push(missing_warning(span, "{{{ end }}}")),
+                            Some(tok) => return Err(TreeError { span: tok.span() }),
                         }
                     }
                     Some(Token::End { .. }) => {}
-                    Some(end @ Token::LegacyEnd { .. }) => mixed_warning("each", span, end),
-                    None => missing_warning(span, "{{{ end }}}"),
-                    _ => return Err(TreeError),
+                    Some(end @ Token::LegacyEnd { .. }) => {
+                        diagnostics.push(mixed_warning("each", span, end))
+                    }
+                    None => diagnostics.push(missing_warning(span, "{{{ end }}}")),
+                    Some(tok) => return Err(TreeError { span: tok.span() }),
                 }
 
                 Instruction::Iter {
@@ -351,43 +597,53 @@ where
             Token::LegacyIf { span, subject, .. } => {
                 let mut body = vec![];
                 let mut alt = vec![];
-                match tree(depth, base, input, &mut body)? {
+                match tree(
+                    depth,
+                    base,
+                    aliases,
+                    input,
+                    &mut body,
+                    diagnostics,
+                    partials,
+                )? {
                     Some(els @ Token::LegacyElse { .. }) | Some(els @ Token::Else { .. }) => {
                         if let Token::Else { .. } = els {
-                            mixed_warning("IF", span, els)
+                            diagnostics.push(mixed_warning("IF", span, els))
                         }
 
                         // consume the end after the else
-                        match tree(depth, base, input, &mut alt)? {
+                        match tree(depth, base, aliases, input, &mut alt, diagnostics, partials)? {
                             Some(Token::LegacyEnd { .. }) => {}
-                            Some(end @ Token::End { .. }) => mixed_warning("IF", span, end),
-                            None => missing_warning(span, "<!-- END -->"),
-                            _ => return Err(TreeError),
+                            Some(end @ Token::End { .. }) => {
+                                diagnostics.push(mixed_warning("IF", span, end))
+                            }
+                            None => diagnostics.push(missing_warning(span, "<!-- END -->")),
+                            Some(tok) => return Err(TreeError { span: tok.span() }),
                         }
                     }
                     Some(Token::LegacyEnd { .. }) => {}
-                    Some(end @ Token::End { .. }) => mixed_warning("IF", span, end),
-                    None => missing_warning(span, "<!-- END -->"),
-                    _ => return Err(TreeError),
+                    Some(end @ Token::End { .. }) => {
+                        diagnostics.push(mixed_warning("IF", span, end))
+                    }
+                    None => diagnostics.push(missing_warning(span, "<!-- END -->")),
+                    Some(tok) => return Err(TreeError { span: tok.span() }),
                 }
 
                 Instruction::If {
-                    subject: resolve_expression_paths(base, subject),
+                    subject: resolve_expression_paths(base, aliases, subject),
                     body,
                     alt,
                 }
             }
             // create an iteration intruction
-            Token::LegacyBegin {
-                span,
-                subject,
-            } => {
-                let normal = |input: &mut I, subject| {
+            Token::LegacyBegin { span, subject } => {
+                let normal = |input: &mut I, subject, diagnostics: &mut Vec<Diagnostic>| {
                     let mut body = vec![];
                     let mut alt = vec![];
 
-                    let subject = resolve_expression_paths(base, subject);
-                    let base: PathBuf<Span> = if let Expression::Path { path: base, .. } = &subject {
+                    let subject = resolve_expression_paths(base, aliases, subject);
+                    let base: PathBuf<Span> = if let Expression::Path { path: base, .. } = &subject
+                    {
                         let mut base = base.clone();
                         if let Some(last) = base.last_mut() {
                             last.with_depth(depth)
@@ -397,24 +653,44 @@ where
                         base.to_vec()
                     };
 
-                    match tree(depth + 1, &base, input, &mut body)? {
+                    match tree(
+                        depth + 1,
+                        &base,
+                        aliases,
+                        input,
+                        &mut body,
+                        diagnostics,
+                        partials,
+                    )? {
                         Some(els @ Token::LegacyElse { .. }) | Some(els @ Token::Else { .. }) => {
                             if let Token::Else { .. } = els {
-                                mixed_warning("BEGIN", span, els)
+                                diagnostics.push(mixed_warning("BEGIN", span, els))
                             }
 
                             // consume the end after the else
-                            match tree(depth, &base, input, &mut alt)? {
+                            match tree(
+                                depth,
+                                &base,
+                                aliases,
+                                input,
+                                &mut alt,
+                                diagnostics,
+                                partials,
+                            )? {
                                 Some(Token::LegacyEnd { .. }) => {}
-                                Some(end @ Token::End { .. }) => mixed_warning("BEGIN", span, end),
-                                None => missing_warning(span, "<!-- END -->"),
-                                _ => return Err(TreeError),
+                                Some(end @ Token::End { .. }) => {
+                                    diagnostics.push(mixed_warning("BEGIN", span, end))
+                                }
+                                None => diagnostics.push(missing_warning(span, "<!-- END -->")),
+                                Some(tok) => return Err(TreeError { span: tok.span() }),
                             }
                         }
                         Some(Token::LegacyEnd { .. }) => {}
-                        Some(end @ Token::End { .. }) => mixed_warning("BEGIN", span, end),
-                        None => missing_warning(span, "<!-- END -->"),
-                        _ => return Err(TreeError),
+                        Some(end @ Token::End { .. }) => {
+                            diagnostics.push(mixed_warning("BEGIN", span, end))
+                        }
+                        None => diagnostics.push(missing_warning(span, "<!-- END -->")),
+                        Some(tok) => return Err(TreeError { span: tok.span() }),
                     }
 
                     Ok(Instruction::Iter {
@@ -428,44 +704,280 @@ where
                 // Handle legacy `<!-- BEGIN stuff -->` working for top-level `stuff` and implicitly `./stuff`
                 match &subject {
                     Expression::Path { path, span }
-                        if depth > 0 && path.first().is_some_and(|s| {
-                            // Not a relative path or keyword
-                            !s.inner().starts_with(&['.', '@'] as &[char])
-                        }) =>
+                        if depth > 0
+                            && path.first().is_some_and(|s| {
+                                // Not a relative path or keyword
+                                !s.inner().starts_with(&['.', '@'] as &[char])
+                            }) =>
                     {
-                        let (line, column, padding) = span.get_line_column_padding();
-                        warn!("[benchpress] warning: output bloat due to ambiguous inner BEGIN");
-                        warn!("     --> {}:{}:{}",
-                            span.extra.filename, span.location_line(), column);
-                        warn!("      |");
-                        warn!("{:>5} | {}",
-                            span.location_line(), line);
-                        warn!("      | {}{} `{subject}` could refer to the top-level value `{subject}` or the `.{subject}` property of the current element, so compiler must emit code for both cases",
-                            padding, "^".repeat(span.len()), subject = subject.span());
-                        warn!("      | note: Migrate to modern syntax to avoid the ambiguity. This will become an error in the future.\n");
+                        diagnostics.push(
+                            Diagnostic::warning(
+                                Code::AmbiguousBegin,
+                                *span,
+                                "output bloat due to ambiguous inner BEGIN",
+                            )
+                            .primary(
+                                *span,
+                                format!(
+                                    "`{subject}` could refer to the top-level value `{subject}` or the `.{subject}` property of the current element, so compiler must emit code for both cases",
+                                    subject = subject.span()
+                                ),
+                            )
+                            .note(
+                                "Migrate to modern syntax to avoid the ambiguity. This will become an error in the future.",
+                            ),
+                        );
 
                         // Path is absolute, so create a branch for both `./subject` and `subject`
                         let mut relative_path =
                             vec![PathPart::Part(Span::new_extra("./", span.extra))];
                         relative_path.extend_from_slice(path);
-                        let relative_subject = Expression::Path { path: relative_path, span: *span };
+                        let relative_subject = Expression::Path {
+                            path: relative_path,
+                            span: *span,
+                        };
 
                         Instruction::If {
-                            subject: resolve_expression_paths(base, relative_subject.clone()),
-                            body: vec![normal(&mut input.clone(), relative_subject)?],
-                            alt: vec![normal(input, subject)?],
+                            subject: resolve_expression_paths(
+                                base,
+                                aliases,
+                                relative_subject.clone(),
+                            ),
+                            // both branches parse the same tokens, so only report diagnostics once
+                            body: vec![normal(&mut input.clone(), relative_subject, &mut vec![])?],
+                            alt: vec![normal(input, subject, diagnostics)?],
+                        }
+                    }
+                    _ => normal(input, subject, diagnostics)?,
+                }
+            }
+            Token::Include { name, .. } | Token::LegacyInclude { name, .. } => {
+                match partials.get(name.fragment()) {
+                    Some(tokens) => {
+                        if let Some(cycle) = partials.cycle(name) {
+                            diagnostics.push(cycle);
+                            continue;
+                        }
+
+                        // paths in the included template are resolved
+                        // as if it was written in place of the include
+                        partials.enter(name);
+                        let mut input = tokens.clone().into_iter();
+                        let rest = tree(
+                            depth,
+                            base,
+                            aliases,
+                            &mut input,
+                            output,
+                            diagnostics,
+                            partials,
+                        )?;
+                        partials.exit();
+
+                        if let Some(tok) = rest {
+                            return Err(TreeError { span: tok.span() });
                         }
+
+                        continue;
+                    }
+                    None => {
+                        if partials.is_enabled() {
+                            diagnostics.push(
+                                Diagnostic::error(
+                                    Code::UnresolvedInclude,
+                                    name,
+                                    "could not resolve included template",
+                                )
+                                .primary(name, format!("no template named `{name}` was found")),
+                            );
+                        } else if depth > 0 {
+                            diagnostics.push(
+                                Diagnostic::warning(
+                                    Code::RuntimeInclude,
+                                    name,
+                                    "template included at runtime inside a loop",
+                                )
+                                .primary(
+                                    name,
+                                    "this is rendered with the root data, not the current item",
+                                )
+                                .help(
+                                    "compile with the `benchpress` CLI, a `Resolver` in Rust, \
+                                     or the `partials` option in JS to read the current item",
+                                )
+                                .note(
+                                    "Relative paths like `{./title}` in the included template \
+                                     read from the root of the data.",
+                                ),
+                            );
+                        }
+
+                        Instruction::Include { name }
                     }
-                    _ => normal(input, subject)?,
                 }
             }
+            Token::Block { span, name } => {
+                let mut body = vec![];
+                match tree(
+                    depth,
+                    base,
+                    aliases,
+                    input,
+                    &mut body,
+                    diagnostics,
+                    partials,
+                )? {
+                    Some(Token::End { .. }) => {}
+                    Some(end @ Token::LegacyEnd { .. }) => {
+                        diagnostics.push(mixed_warning("block", span, end))
+                    }
+                    None => diagnostics.push(missing_warning(span, "{{{ end }}}")),
+                    Some(tok) => return Err(TreeError { span: tok.span() }),
+                }
+
+                Instruction::Block {
+                    name,
+                    body: partials.block(name.fragment()).unwrap_or(body),
+                }
+            }
+            Token::Extends { span, name } => {
+                // the rest of the template only provides blocks for the extended one
+                let mut child = vec![];
+                let rest = tree(
+                    depth,
+                    base,
+                    aliases,
+                    input,
+                    &mut child,
+                    diagnostics,
+                    partials,
+                )?;
+
+                let mut blocks = vec![];
+                for instruction in child {
+                    match instruction {
+                        Instruction::Block { name, body } => blocks.push((*name.fragment(), body)),
+                        Instruction::Text(text) if text.trim().is_empty() => {}
+                        _ => {
+                            let content = instruction.span();
+                            diagnostics.push(
+                                Diagnostic::warning(
+                                    Code::IgnoredContent,
+                                    content,
+                                    "content outside of a block is ignored",
+                                )
+                                .primary(content, "this is never rendered")
+                                .secondary(span, format!("because this template extends `{name}`"))
+                                .help("move it into a `{{{ block }}}`, or remove it"),
+                            );
+                        }
+                    }
+                }
+
+                match partials.get(name.fragment()) {
+                    Some(tokens) => {
+                        if let Some(cycle) = partials.cycle(name) {
+                            diagnostics.push(cycle);
+                            return Ok(rest);
+                        }
+
+                        partials.enter(name);
+                        let count = partials.push_blocks(blocks);
+                        let mut input = tokens.clone().into_iter();
+                        let parent = tree(
+                            depth,
+                            base,
+                            aliases,
+                            &mut input,
+                            output,
+                            diagnostics,
+                            partials,
+                        )?;
+                        partials.pop_blocks(count);
+                        partials.exit();
+
+                        if let Some(tok) = parent {
+                            return Err(TreeError { span: tok.span() });
+                        }
+                    }
+                    None => {
+                        let diagnostic = Diagnostic::error(
+                            Code::UnresolvedInclude,
+                            name,
+                            "could not resolve extended template",
+                        );
+                        diagnostics.push(if partials.is_enabled() {
+                            diagnostic
+                                .primary(name, format!("no template named `{name}` was found"))
+                        } else {
+                            diagnostic
+                                .primary(name, "templates can only be extended at compile time")
+                                .help(
+                                    "compile with the `benchpress` CLI, a `Resolver` in Rust, \
+                                     or the `partials` option in JS",
+                                )
+                        });
+                    }
+                }
+
+                return Ok(rest);
+            }
             tok => return Ok(Some(tok)),
-        });
+        };
+
+        output.push(instruction);
     }
 
     Ok(None)
 }
 
+/// report named blocks sharing a name with another block or a top-level
+/// iteration, since only the first is generated as a block method
+pub fn check_block_names<'a>(tree: &[Instruction<Span<'a>>], diagnostics: &mut Vec<Diagnostic>) {
+    // the same instructions the generator pulls out into block methods
+    fn walk<'a>(
+        body: &[Instruction<Span<'a>>],
+        iters: &mut Vec<Span<'a>>,
+        blocks: &mut Vec<Span<'a>>,
+    ) {
+        for instruction in body {
+            match instruction {
+                Instruction::If { body, alt, .. } => {
+                    walk(body, iters, blocks);
+                    walk(alt, iters, blocks);
+                }
+                Instruction::Iter { subject, .. } => iters.push(subject.span()),
+                Instruction::Block { name, body } => {
+                    blocks.push(*name);
+                    walk(body, iters, blocks);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut iters = vec![];
+    let mut blocks = vec![];
+    walk(tree, &mut iters, &mut blocks);
+
+    for (i, name) in blocks.iter().enumerate() {
+        let same_name = |other: &&Span| other.fragment() == name.fragment();
+        let diagnostic = if let Some(block) = blocks[..i].iter().find(same_name) {
+            Diagnostic::error(Code::DuplicateBlock, *name, "duplicate block name")
+                .primary(*name, format!("a block named `{name}` is already defined"))
+                .secondary(*block, "first defined here")
+        } else if let Some(iter) = iters.iter().find(same_name) {
+            Diagnostic::error(Code::DuplicateBlock, *name, "duplicate block name")
+                .primary(*name, "this block has the same name as an iteration")
+                .secondary(*iter, format!("rendered alone as the `{name}` block"))
+        } else {
+            continue;
+        };
+
+        diagnostics.push(diagnostic.help("rename the block"));
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -499,25 +1011,35 @@ mod test {
                     body: body.into_iter().map(|i| i.span_to_str()).collect(),
                     alt: alt.into_iter().map(|i| i.span_to_str()).collect(),
                 },
+                Instruction::Include { name } => Instruction::Include {
+                    name: *name.fragment(),
+                },
+                Instruction::Block { name, body } => Instruction::Block {
+                    name: *name.fragment(),
+                    body: body.into_iter().map(|i| i.span_to_str()).collect(),
+                },
             }
         }
     }
 
     #[test]
     fn test_fix_extra_tokens() {
-        fn span_to_str(tokens: Vec<Token<Span>>) -> Vec<Token<&str>> {
+        fn span_to_str(tokens: Vec<Token<Span<'_>>>) -> Vec<Token<&str>> {
             tokens.into_iter().map(|t| t.span_to_str()).collect()
         }
 
         let program = "{{{ each abc }}} for each thing <!-- END foo -->{{{ end }}}";
         let source = sp(program);
-        let (_, tokens) = crate::parse::tokens::tokens(source).unwrap();
+        let mut diagnostics = vec![];
+        let (_, tokens) = crate::parse::tokens::tokens(source, &mut diagnostics).unwrap();
 
         assert_eq_unspan!(
-            fix_extra_tokens(tokens),
+            fix_extra_tokens(tokens, &mut diagnostics),
             vec![
                 Token::Each {
                     span: "{{{ each abc }}}",
+                    key: None,
+                    value: None,
                     subject: Expression::Path {
                         span: "abc",
                         path: vec![PathPart::Part("abc")]
@@ -531,9 +1053,13 @@ mod test {
                 },
             ]
         );
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::ExtraTokens);
+        assert_eq!(diagnostics[0].primary.as_ref().unwrap().column, 33);
     }
 
-    fn span_to_str(tree: Vec<Instruction<Span>>) -> Vec<Instruction<&str>> {
+    fn span_to_str(tree: Vec<Instruction<Span<'_>>>) -> Vec<Instruction<&str>> {
         tree.into_iter().map(|i| i.span_to_str()).collect()
     }
 
@@ -542,6 +1068,8 @@ mod test {
         let mut input = vec![
             Token::Each {
                 span: sp("{{{ each abc }}}"),
+                key: None,
+                value: None,
                 subject: Expression::Path {
                     span: sp("abc"),
                     path: vec![PathPart::Part(sp("abc"))],
@@ -557,7 +1085,16 @@ mod test {
 
         let mut output = vec![];
 
-        assert!(tree(0, &[], &mut input, &mut output).is_ok());
+        assert!(tree(
+            0,
+            &[],
+            &[],
+            &mut input,
+            &mut output,
+            &mut vec![],
+            &Partials::default()
+        )
+        .is_ok());
 
         assert_eq_unspan!(
             output,
@@ -578,6 +1115,8 @@ mod test {
         let mut input = vec![
             Token::Each {
                 span: sp("{{{ each abc }}}"),
+                key: None,
+                value: None,
                 subject: Expression::Path {
                     span: sp("abc"),
                     path: vec![PathPart::Part(sp("abc"))],
@@ -586,6 +1125,8 @@ mod test {
             Token::Text(sp(" before inner ")),
             Token::Each {
                 span: sp("{{{ each ./inner }}}"),
+                key: None,
+                value: None,
                 subject: Expression::Path {
                     span: sp("./inner"),
                     path: vec![PathPart::Part(sp("./")), PathPart::Part(sp("inner"))],
@@ -616,7 +1157,16 @@ mod test {
 
         let mut output = vec![];
 
-        assert!(tree(0, &[], &mut input, &mut output).is_ok());
+        assert!(tree(
+            0,
+            &[],
+            &[],
+            &mut input,
+            &mut output,
+            &mut vec![],
+            &Partials::default()
+        )
+        .is_ok());
 
         assert_eq_unspan!(
             output,
@@ -656,6 +1206,8 @@ mod test {
         let mut input = vec![
             Token::Each {
                 span: sp("{{{ each abc }}}"),
+                key: None,
+                value: None,
                 subject: Expression::Path {
                     span: sp("abc"),
                     path: vec![PathPart::Part(sp("abc"))],
@@ -694,7 +1246,16 @@ mod test {
 
         let mut output = vec![];
 
-        assert!(tree(0, &[], &mut input, &mut output).is_ok());
+        assert!(tree(
+            0,
+            &[],
+            &[],
+            &mut input,
+            &mut output,
+            &mut vec![],
+            &Partials::default()
+        )
+        .is_ok());
 
         assert_eq_unspan!(
             output,
@@ -0,0 +1,979 @@
+//! Render parsed templates directly, without generating JS
+//!
+//! Output matches what the compiled template would produce when run
+//! through `lib/runtime.js`, including its `guard`, `iter`, and `helper`
+//! semantics and JS type coercion.
+
+mod value;
+
+use crate::{
+    generate::templates::{
+        ESCAPE,
+        INCLUDE,
+        MAX_RANGE,
+    },
+    parse::{
+        expression::{
+            number,
+            unescape,
+            Expression,
+            Keyword,
+        },
+        path::PathPart,
+        tree::Instruction,
+        Fragment,
+    },
+};
+use json::JsonValue;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+};
+use value::Value;
+
+/// A helper function, called with the root context and its arguments
+pub type HelperFn = dyn Fn(&JsonValue, &[JsonValue]) -> JsonValue + Send + Sync;
+
+/// Helper functions available to templates while rendering
+#[derive(Clone)]
+pub struct Helpers {
+    map: HashMap<String, Arc<HelperFn>>,
+}
+
+impl Helpers {
+    /// helpers with only the default `__escape`
+    pub fn new() -> Self {
+        let mut helpers = Helpers {
+            map: HashMap::new(),
+        };
+        helpers.register(ESCAPE, escape);
+
+        helpers
+    }
+
+    /// register a helper, replacing any existing helper with the same name
+    ///
+    /// Like the JS runtime, falsy return values are output as empty strings.
+    pub fn register<F>(&mut self, name: &str, helper: F) -> &mut Self
+    where
+        F: Fn(&JsonValue, &[JsonValue]) -> JsonValue + Send + Sync + 'static,
+    {
+        self.map.insert(name.to_string(), Arc::new(helper));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&HelperFn> {
+        self.map.get(name).map(|helper| &**helper)
+    }
+}
+
+impl Default for Helpers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Helpers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.map.keys()).finish()
+    }
+}
+
+/// default `__escape` helper, escaping HTML entities
+fn escape(_: &JsonValue, args: &[JsonValue]) -> JsonValue {
+    match args.first() {
+        None | Some(JsonValue::Null) => "".into(),
+        Some(value) => {
+            let text = value::to_string(value);
+            let mut out = String::with_capacity(text.len());
+            for c in text.chars() {
+                match c {
+                    '&' => out.push_str("&amp;"),
+                    '<' => out.push_str("&lt;"),
+                    '>' => out.push_str("&gt;"),
+                    '"' => out.push_str("&quot;"),
+                    '\'' => out.push_str("&#x27;"),
+                    '`' => out.push_str("&#x60;"),
+                    '=' => out.push_str("&#x3D;"),
+                    _ => out.push(c),
+                }
+            }
+
+            out.into()
+        }
+    }
+}
+
+/// state of the `iter` callback currently running
+#[derive(Debug, Clone, Copy)]
+struct Iteration<'a> {
+    depth: u32,
+    key: &'a str,
+    index: usize,
+    length: usize,
+}
+
+/// variables in scope at a given point in the template
+struct Scope<'a> {
+    context: &'a JsonValue,
+    value: &'a JsonValue,
+    iteration: Option<Iteration<'a>>,
+    parent: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    /// `key{depth}`, from the innermost iteration at that depth
+    fn key_at(&self, depth: u32) -> Option<&'a str> {
+        match self.iteration {
+            Some(iteration) if iteration.depth == depth => Some(iteration.key),
+            _ => self.parent?.key_at(depth),
+        }
+    }
+
+    /// `value{depth}`, from the innermost iteration at that depth
+    fn value_at(&self, depth: u32) -> Option<&'a JsonValue> {
+        match self.iteration {
+            Some(iteration) if iteration.depth == depth => Some(self.value),
+            _ => self.parent?.value_at(depth),
+        }
+    }
+}
+
+struct Renderer<'h> {
+    helpers: &'h Helpers,
+}
+
+impl Renderer<'_> {
+    /// fold a body like the generated `a + b + c`
+    fn body<'a, S: Fragment>(&self, body: &[Instruction<S>], scope: &Scope<'a>) -> Value<'a> {
+        body.iter()
+            .map(|instruction| self.instruction(instruction, scope))
+            .reduce(value::add)
+            .unwrap_or_else(|| Cow::Owned("".into()))
+    }
+
+    fn instruction<'a, S: Fragment>(
+        &self,
+        instruction: &Instruction<S>,
+        scope: &Scope<'a>,
+    ) -> Value<'a> {
+        match instruction {
+            Instruction::Text(text) => Cow::Owned(text.text().into()),
+            Instruction::InterpEscaped(subject) => {
+                let subject = self.expression(subject, scope).into_owned();
+                Cow::Owned(
+                    self.helpers
+                        .get(ESCAPE)
+                        .map_or(JsonValue::Null, |escape| escape(scope.context, &[subject])),
+                )
+            }
+            Instruction::InterpRaw(subject) => self.expression(subject, scope),
+            Instruction::If { subject, body, alt } => {
+                if value::truthy(&self.expression(subject, scope)) {
+                    self.body(body, scope)
+                } else {
+                    self.body(alt, scope)
+                }
+            }
+            // like the runtime `iter`, `alt` is never used
+            Instruction::Iter {
+                depth,
+                subject,
+                body,
+                ..
+            } => {
+                let subject = self.expression(subject, scope);
+                let keys = value::keys(&subject);
+                let length = keys.len();
+
+                let mut output = String::new();
+                for (index, key) in keys.iter().enumerate() {
+                    let value = if subject.is_array() {
+                        &subject[index]
+                    } else {
+                        &subject[&**key]
+                    };
+                    let scope = Scope {
+                        context: scope.context,
+                        value,
+                        iteration: Some(Iteration {
+                            depth: *depth,
+                            key,
+                            index,
+                            length,
+                        }),
+                        parent: Some(scope),
+                    };
+
+                    output.push_str(&value::to_string(&self.body(body, &scope)));
+                }
+
+                Cow::Owned(output.into())
+            }
+            Instruction::Block { body, .. } => self.body(body, scope),
+            // rendered by the `__include` helper, if one was registered
+            Instruction::Include { name } => {
+                Cow::Owned(self.helper(INCLUDE, scope.context, &[name.text().into()]))
+            }
+        }
+    }
+
+    fn expression<'a, S: Fragment>(&self, expr: &Expression<S>, scope: &Scope<'a>) -> Value<'a> {
+        let iteration = scope.iteration;
+
+        match expr {
+            Expression::StringLiteral(value) => Cow::Owned(unescape(value.text()).into()),
+            Expression::NumberLiteral(value) => Cow::Owned(number(value.text()).into()),
+            Expression::KeywordDepth {
+                keyword: Keyword::Key,
+                depth,
+                ..
+            } => Cow::Owned(scope.key_at(*depth).into()),
+            Expression::KeywordDepth {
+                keyword: Keyword::Value,
+                depth,
+                ..
+            } => value::guard(
+                scope
+                    .value_at(*depth)
+                    .map_or(Cow::Owned(JsonValue::Null), Cow::Borrowed),
+            ),
+            // only keys and values are kept for each depth
+            Expression::Keyword { keyword, .. } | Expression::KeywordDepth { keyword, .. } => {
+                match keyword {
+                    Keyword::Root => Cow::Borrowed(scope.context),
+                    Keyword::Key => Cow::Owned(iteration.map(|x| x.key).into()),
+                    Keyword::Index => Cow::Owned(iteration.map(|x| x.index).into()),
+                    Keyword::Value => value::guard(Cow::Borrowed(scope.value)),
+                    Keyword::First => Cow::Owned(iteration.is_some_and(|x| x.index == 0).into()),
+                    Keyword::Last => {
+                        Cow::Owned(iteration.is_some_and(|x| x.index + 1 == x.length).into())
+                    }
+                    Keyword::Odd => Cow::Owned(iteration.is_some_and(|x| x.index % 2 == 1).into()),
+                    Keyword::Even => Cow::Owned(iteration.is_some_and(|x| x.index % 2 == 0).into()),
+                    Keyword::True => Cow::Owned(true.into()),
+                    Keyword::False => Cow::Owned(false.into()),
+                }
+            }
+            Expression::Path { path, .. } => value::guard(self.path(path, scope)),
+            Expression::Helper { name, args, .. } | Expression::LegacyHelper { name, args, .. } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.expression(arg, scope).into_owned())
+                    .collect::<Vec<_>>();
+
+                Cow::Owned(self.helper(name.text(), scope.context, &args))
+            }
+            Expression::Negative { expr, .. } => {
+                Cow::Owned((!value::truthy(&self.expression(expr, scope))).into())
+            }
+            Expression::Array { items, .. } => Cow::Owned(JsonValue::Array(
+                items
+                    .iter()
+                    .map(|item| self.expression(item, scope).into_owned())
+                    .collect(),
+            )),
+            Expression::Range { start, end, .. } => {
+                let start = value::to_number(&self.expression(start, scope));
+                let end = value::to_number(&self.expression(end, scope));
+
+                // counted up front, so huge bounds can't loop forever
+                let length = (end - start).floor() + 1.0;
+                let items = if length.is_finite() && length <= MAX_RANGE as f64 {
+                    // negative lengths saturate to zero
+                    (0..length as usize)
+                        .map(|i| (start + i as f64).into())
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+
+                Cow::Owned(JsonValue::Array(items))
+            }
+            Expression::Length { subject, .. } => {
+                Cow::Owned(value::count(&self.expression(subject, scope)).into())
+            }
+            Expression::Empty { subject, .. } => {
+                Cow::Owned((value::count(&self.expression(subject, scope)) == 0).into())
+            }
+            Expression::Equ { lhs, rhs, .. } => Cow::Owned(
+                value::loose_eq(&self.expression(lhs, scope), &self.expression(rhs, scope)).into(),
+            ),
+            Expression::Neq { lhs, rhs, .. } => Cow::Owned(
+                (!value::loose_eq(&self.expression(lhs, scope), &self.expression(rhs, scope)))
+                    .into(),
+            ),
+            Expression::Lt { lhs, rhs, .. } => {
+                let lhs = self.expression(lhs, scope);
+                let rhs = self.expression(rhs, scope);
+                Cow::Owned((value::less_than(&lhs, &rhs) == Some(true)).into())
+            }
+            Expression::Lte { lhs, rhs, .. } => {
+                let lhs = self.expression(lhs, scope);
+                let rhs = self.expression(rhs, scope);
+                Cow::Owned((value::less_than(&rhs, &lhs) == Some(false)).into())
+            }
+            Expression::Gt { lhs, rhs, .. } => {
+                let lhs = self.expression(lhs, scope);
+                let rhs = self.expression(rhs, scope);
+                Cow::Owned((value::less_than(&rhs, &lhs) == Some(true)).into())
+            }
+            Expression::Gte { lhs, rhs, .. } => {
+                let lhs = self.expression(lhs, scope);
+                let rhs = self.expression(rhs, scope);
+                Cow::Owned((value::less_than(&lhs, &rhs) == Some(false)).into())
+            }
+            Expression::And { lhs, rhs, .. } => {
+                let lhs = self.expression(lhs, scope);
+                if value::truthy(&lhs) {
+                    self.expression(rhs, scope)
+                } else {
+                    lhs
+                }
+            }
+            Expression::Or { lhs, rhs, .. } => {
+                let lhs = self.expression(lhs, scope);
+                if value::truthy(&lhs) {
+                    lhs
+                } else {
+                    self.expression(rhs, scope)
+                }
+            }
+            Expression::Ternary {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                if value::truthy(&self.expression(condition, scope)) {
+                    self.expression(then, scope)
+                } else {
+                    self.expression(otherwise, scope)
+                }
+            }
+        }
+    }
+
+    /// unguarded property access from the root context,
+    /// `null` if any part of the path is missing
+    fn path<'a, S: Fragment>(&self, path: &[PathPart<S>], scope: &Scope<'a>) -> Value<'a> {
+        let mut current = Cow::Borrowed(scope.context);
+
+        for part in path {
+            // `obj[expr]` converts the value of `expr` to a string key
+            let subscript =
+                |expr| Cow::Owned(value::to_string(&self.expression(expr, scope)).into_owned());
+            let (key, depth): (Cow<str>, _) = match part {
+                PathPart::Part(name) => (Cow::Borrowed(name.text()), None),
+                PathPart::PartDepth(name, depth) => (Cow::Borrowed(name.text()), Some(*depth)),
+                PathPart::Index(_, expr) => (subscript(expr), None),
+                PathPart::IndexDepth(_, expr, depth) => (subscript(expr), Some(*depth)),
+            };
+
+            current = match value::get(current, &key) {
+                Some(next) => next,
+                None => return Cow::Owned(JsonValue::Null),
+            };
+            if let Some(depth) = depth {
+                // `obj[undefined]` outside of the loop
+                let key = scope.key_at(depth).unwrap_or("undefined");
+                current = match value::get(current, key) {
+                    Some(next) => next,
+                    None => return Cow::Owned(JsonValue::Null),
+                };
+            }
+        }
+
+        current
+    }
+
+    /// call a helper like the runtime `helper`
+    fn helper(&self, name: &str, context: &JsonValue, args: &[JsonValue]) -> JsonValue {
+        match self.helpers.get(name) {
+            Some(helper) => {
+                let out = helper(context, args);
+                if value::truthy(&out) {
+                    out
+                } else {
+                    "".into()
+                }
+            }
+            None => "".into(),
+        }
+    }
+}
+
+/// render parser output with the given context
+pub fn render<S: Fragment>(
+    input: &[Instruction<S>],
+    context: &JsonValue,
+    helpers: &Helpers,
+) -> String {
+    let scope = Scope {
+        context,
+        value: context,
+        iteration: None,
+        parent: None,
+    };
+    let output = Renderer { helpers }.body(input, &scope);
+
+    value::to_string(&value::guard(output)).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        render as render_source,
+        CompileOptions,
+    };
+    use pretty_assertions::assert_eq;
+    use std::{
+        fs,
+        path::Path,
+    };
+
+    fn render(source: &str, context: JsonValue) -> String {
+        render_source(
+            source,
+            "<test>",
+            &context,
+            &Helpers::new(),
+            &CompileOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_interp() {
+        let context = json::object! { a: { b: "<b>" }, n: 4, list: [], nil: null };
+
+        assert_eq!(render("{a.b} {{a.b}}", context.clone()), "&lt;b&gt; <b>");
+        assert_eq!(render("{n}{list}{nil}{missing.deep}", context.clone()), "4");
+        assert_eq!(render("{a}", context), "[object Object]");
+    }
+
+    #[test]
+    fn test_numbers() {
+        let context = json::object! { count: 0, price: "2.50" };
+
+        assert_eq!(
+            render(
+                "{{{ if (count == 0) }}}none{{{ end }}} {{{ if (price == 2.5) }}}{-1.0}{{{ end }}}",
+                context
+            ),
+            "none -1"
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        let context = json::object! { a: true, b: false, x: "y" };
+
+        assert_eq!(
+            render(
+                "{{{ if b && a || x == \"y\" }}}1{{{ end }}}{{{ if !a || b }}}2{{{ end }}}{a && x}",
+                context
+            ),
+            "1y"
+        );
+    }
+
+    #[test]
+    fn test_subscripts() {
+        let context = json::object! {
+            users: { a: { name: "Ann" }, b: { name: "Bob" } },
+            ids: ["b", "a"],
+            map: { "key with spaces": 1, "2": "two" },
+            list: [[1, 2], [3]],
+        };
+
+        assert_eq!(
+            render(
+                "{{{ each ids }}}{users[@value].name}{users[@value][\"name\"]}{{{ end }}} {map[\"key with spaces\"]} {map[2]} {missing[0]}",
+                context.clone()
+            ),
+            "BobBobAnnAnn 1 two "
+        );
+        assert_eq!(
+            render(
+                "{{{ each list[0] }}}{@value}{{{ end }}}|{{{ each list[1] }}}{@index}:{list[0][@index]}{{{ end }}}",
+                context.clone()
+            ),
+            "12|0:1"
+        );
+        // subscripting the array being iterated, not its current item
+        assert_eq!(
+            render(
+                "{{{ each ids }}}{ids[@index]}{{{ end }}}|{{{ each list }}}{list[@index][0]}{{{ end }}}",
+                context
+            ),
+            "ba|13"
+        );
+    }
+
+    #[test]
+    fn test_ternary() {
+        let context = json::object! { admin: true, posts: [{ pinned: false, title: "<b>" }] };
+
+        assert_eq!(
+            render(
+                "{ admin ? \"admin\" : \"user\" }{{{ each posts }}} {./pinned ? \"!\" : ./title}{{{ end }}}",
+                context
+            ),
+            "admin &lt;b&gt;"
+        );
+    }
+
+    #[test]
+    fn test_pipes() {
+        let mut helpers = Helpers::new();
+        helpers
+            .register("caps", |_, args| {
+                JsonValue::from(value::to_string(&args[0]).to_uppercase())
+            })
+            .register("truncate", |_, args| {
+                let length = value::to_number(&args[1]) as usize;
+                JsonValue::from(
+                    value::to_string(&args[0])
+                        .chars()
+                        .take(length)
+                        .collect::<String>(),
+                )
+            });
+
+        let output = render_source(
+            "{ title | caps | truncate(5) }|{ (title | truncate(2)) == \"he\" }",
+            "<test>",
+            &json::object! { title: "hello world" },
+            &helpers,
+            &CompileOptions::default(),
+        );
+        assert_eq!(output.unwrap(), "HELLO|true");
+    }
+
+    #[test]
+    fn test_relational() {
+        let context = json::object! { count: 3, name: "b", missing: null };
+        let cases = [
+            ("(count > 0)", true),
+            ("(count >= 3)", true),
+            ("(count < 3)", false),
+            ("(count <= -1)", false),
+            ("(name < \"c\")", true),
+            ("(name > count)", false),
+            ("(name <= count)", false),
+            ("(missing < 1)", true),
+        ];
+
+        for (condition, expected) in cases {
+            let source =
+                format!("{{{{{{ if {condition} }}}}}}yes{{{{{{ else }}}}}}no{{{{{{ end }}}}}}");
+            let expected = if expected { "yes" } else { "no" };
+            assert_eq!(render(&source, context.clone()), expected, "{}", condition);
+        }
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let context = json::object! { yes: true, no: false, n: 1, s: "1", list: [] };
+
+        assert_eq!(
+            render(
+                "{{{ if yes }}}a{{{ else }}}b{{{ end }}}{{{ if !yes }}}c{{{ else }}}d{{{ end }}}",
+                context.clone()
+            ),
+            "ad"
+        );
+        assert_eq!(
+            render("{{{ if (n == s) }}}eq{{{ end }}}", context.clone()),
+            "eq"
+        );
+        assert_eq!(
+            render(
+                "{{{ if (no || list) }}}yes{{{ else }}}empty arrays are falsy{{{ end }}}",
+                context
+            ),
+            "empty arrays are falsy"
+        );
+    }
+
+    #[test]
+    fn test_iteration() {
+        let context = json::object! {
+            rows: [{ cells: ["a", "b"] }, { cells: ["c"] }],
+            obj: { x: 1, y: 2 },
+        };
+
+        assert_eq!(
+            render(
+                "{{{ each rows }}}[{{{ each rows.cells }}}{@index}{@value}{{{ if @last }}}.{{{ end }}}{{{ end }}}]{{{ end }}}",
+                context.clone()
+            ),
+            "[0a1b.][0c.]"
+        );
+        assert_eq!(
+            render(
+                "{{{ each obj }}}{@key}={@value}{{{ if !@last }}},{{{ end }}}{{{ end }}}",
+                context
+            ),
+            "x=1,y=2"
+        );
+    }
+
+    #[test]
+    fn test_measures() {
+        let context = json::object! {
+            posts: ["a", "b", "c"],
+            tags: {},
+            name: "née",
+        };
+
+        assert_eq!(
+            render(
+                "{posts.@length} {tags.@length} {name.@length} {missing.@length}",
+                context.clone()
+            ),
+            "3 0 3 0"
+        );
+        assert_eq!(
+            render(
+                "{{{ if tags.@empty }}}none{{{ end }}}{{{ if !posts.@empty }}}{posts.@length > 2}{{{ end }}}",
+                context.clone()
+            ),
+            "nonetrue"
+        );
+        assert_eq!(
+            render(
+                "{{{ each posts }}}{{{ if @odd }}}-{{{ else }}}+{{{ end }}}{@length}{{{ end }}}{@odd}{@even}",
+                context
+            ),
+            "+1-1+1falsefalse"
+        );
+    }
+
+    #[test]
+    fn test_loop_variables() {
+        let context = json::object! {
+            posts: [
+                { title: "a", tags: ["x", "y"] },
+                { title: "b", tags: [] },
+            ],
+            post: { title: "data" },
+            names: { one: 1, two: 2 },
+        };
+
+        assert_eq!(
+            render(
+                "{{{ each post in posts }}}[{post.title}:{{{ each tag in post.tags }}}{post.title}{tag}{{{ end }}}]{{{ end }}}{post.title}",
+                context.clone()
+            ),
+            "[a:axay][b:]data"
+        );
+        assert_eq!(
+            render(
+                "{{{ each i, post in posts }}}{{{ each j, tag in post.tags }}}{i}{j}{tag} {{{ end }}}{{{ end }}}",
+                context.clone()
+            ),
+            "00x 01y "
+        );
+        assert_eq!(
+            render(
+                "{{{ each name, n in names }}}{{{ each posts }}}{name}={n}{posts.title} {{{ end }}}{{{ end }}}",
+                context
+            ),
+            "one=1a one=1b two=2a two=2b "
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let context = json::object! {
+            count: 3,
+            text: "2",
+            missing_end: null,
+            infinite: "Infinity",
+            huge: 1e300,
+        };
+
+        assert_eq!(
+            render(
+                "{{{ each page in 1..count }}}{page}{{{ if !@last }}},{{{ end }}}{{{ end }}}",
+                context.clone()
+            ),
+            "1,2,3"
+        );
+        assert_eq!(
+            render(
+                "{0.5..text} {3..1} {1..missing_end} {1..x}",
+                context.clone()
+            ),
+            "0.5,1.5   "
+        );
+        // infinite or too long, so empty rather than looping forever
+        assert_eq!(
+            render(
+                "[{1..infinite}] [{1..huge}] [{huge..huge}] [{1..1000000000}] [{1..10001}]",
+                context.clone()
+            ),
+            "[] [] [1e+300] [] []"
+        );
+        assert!(render("{1..10000}", context.clone()).ends_with(",9999,10000"));
+        assert_eq!(
+            render(
+                "{{{ each [\"a\", count, 1..2] }}}{@index}={@value} {{{ end }}}{[]}",
+                context
+            ),
+            "0=a 1=3 2=1,2 "
+        );
+    }
+
+    #[test]
+    fn test_standalone() {
+        let source = "<ul>\n  {{{ each list }}}\n  <li>{@value}</li>\n  {{{ end }}}\n</ul>";
+        let context = json::object! { list: [1, 2] };
+        let options = CompileOptions {
+            standalone: true,
+            ..Default::default()
+        };
+
+        let output = render_source(source, "<test>", &context, &Helpers::new(), &options);
+        assert_eq!(output.unwrap(), "<ul>\n  <li>1</li>\n  <li>2</li>\n</ul>");
+        assert_eq!(
+            render(source, context),
+            "<ul>\n  \n  <li>1</li>\n  \n  <li>2</li>\n  \n</ul>"
+        );
+    }
+
+    #[test]
+    fn test_trim_markers() {
+        let context = json::object! { list: [1, 2], name: "x" };
+
+        assert_eq!(
+            render(
+                "<ul>\n  {{{- each list -}}}\n  <li>{- @value -}</li>\n  {{{- end -}}}\n</ul>",
+                context.clone()
+            ),
+            "<ul><li>1</li><li>2</li></ul>"
+        );
+        assert_eq!(
+            render("a  {{{- if name }}}  b  {{{ end -}}}  c", context),
+            "a  b  c"
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let mut helpers = Helpers::new();
+        helpers.register("__include", |context, args| {
+            format!("<{}:{}>", args[0], context["a"]).into()
+        });
+
+        let output = render_source(
+            "{{{ each list }}}{{{ include \"row\" }}}{{{ end }}}<!-- IMPORT footer.tpl -->",
+            "<test>",
+            &json::object! { a: 1, list: [1, 2] },
+            &helpers,
+            &CompileOptions::default(),
+        );
+        assert_eq!(output.unwrap(), "<row:1><row:1><footer.tpl:1>");
+
+        // without the helper, includes are empty
+        assert_eq!(render("a{{{ include \"b\" }}}c", json::object! {}), "ac");
+    }
+
+    #[test]
+    fn test_helpers() {
+        let mut helpers = Helpers::new();
+        helpers
+            .register("double", |_, args| {
+                JsonValue::from(value::to_number(&args[0]) * 2.0)
+            })
+            .register("root", |context, _| context["name"].clone())
+            .register("nothing", |_, _| JsonValue::Null);
+
+        let output = render_source(
+            "{double(n)} {root()} [{nothing()}] [{missing()}] {double(\"4\")} [{double(\"x\")}]",
+            "<test>",
+            &json::object! { n: 21, name: "ctx" },
+            &helpers,
+            &CompileOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output, "42 ctx [] [] 8 []");
+    }
+
+    fn collapse_whitespace(input: &str) -> String {
+        let mut lines = Vec::new();
+        for line in input.lines() {
+            let line = line
+                .split([' ', '\t'])
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>();
+            if !line.is_empty() {
+                lines.push(line.join(" "));
+            }
+        }
+
+        lines.join("\n").replace(" <", "<").replace("> ", ">")
+    }
+
+    /// Rust versions of the helpers registered in `tests/templates.spec.js`
+    fn spec_helpers() -> Helpers {
+        let mut helpers = Helpers::new();
+        let get = |value: &JsonValue, key: &str| {
+            value::property(value, key).map_or(JsonValue::Null, Cow::into_owned)
+        };
+
+        helpers
+            .register("canspeak", move |_, args| {
+                let data = &args[0];
+                if value::truthy(&get(data, "isHuman")) && get(data, "name") == "Human" {
+                    "Can speak".into()
+                } else {
+                    "Cannot speak".into()
+                }
+            })
+            .register("test", move |_, args| {
+                let forum = get(&args[0], "forum");
+                if value::truthy(&forum) {
+                    (!value::truthy(&get(&args[0], "double"))).into()
+                } else {
+                    forum
+                }
+            })
+            .register("isHuman", move |_, args| {
+                let animal = get(&get(&args[0], "animals"), &value::to_string(&args[1]));
+                get(&animal, "isHuman")
+            })
+            .register("wordpressHome", |_, _| "wordpress-home".into())
+            .register("stylesheetLocation", |_, _| "stylesheet-location".into())
+            .register("buildAvatar", move |context, args| {
+                let arg = |i: usize| args.get(i).cloned().unwrap_or(JsonValue::Null);
+                let user = if value::truthy(&arg(0)) {
+                    arg(0)
+                } else {
+                    context.clone()
+                };
+                let field = |key: &str| value::to_string(&get(&user, key)).into_owned();
+                let size = arg(1);
+                let size_str = value::to_string(&size);
+
+                let mut attributes = vec![
+                    format!("alt=\"{}\"", field("username")),
+                    format!("title=\"{}\"", field("username")),
+                    format!("data-uid=\"{}\"", field("uid")),
+                    "loading=\"lazy\"".to_string(),
+                ];
+                let mut styles = vec![];
+                let mut class_names = if value::truthy(&arg(3)) {
+                    value::to_string(&arg(3)).into_owned()
+                } else {
+                    String::new()
+                };
+                if ["xs", "sm", "sm2x", "md", "lg", "xl"].contains(&&*size_str) {
+                    class_names += &format!(" avatar-{size_str}");
+                } else if let Ok(px) = size_str.parse::<i64>() {
+                    styles.push(format!("width: {px}px;"));
+                    styles.push(format!("height: {px}px;"));
+                    styles.push(format!("line-height: {px}px;"));
+                    styles.push(format!("font-size: {}rem;", px as f64 / 16.0));
+                } else {
+                    class_names += " avatar-sm";
+                }
+                let rounded = if value::truthy(&arg(2)) {
+                    " avatar-rounded"
+                } else {
+                    ""
+                };
+                attributes.insert(0, format!("class=\"avatar {class_names}{rounded}\""));
+
+                let picture = get(&user, "picture");
+                if value::truthy(&arg(4)) {
+                    attributes.push(format!("component=\"{}\"", value::to_string(&arg(4))));
+                } else if value::truthy(&picture) {
+                    attributes.push("component=\"avatar/picture\"".to_string());
+                } else {
+                    attributes.push("component=\"avatar/icon\"".to_string());
+                }
+
+                if value::truthy(&picture) {
+                    return format!(
+                        "<img {} src=\"{}\" style=\"{}\" />",
+                        attributes.join(" "),
+                        value::to_string(&picture),
+                        styles.join(" ")
+                    )
+                    .into();
+                }
+
+                styles.push(format!("background-color: {};", field("icon:bgColor")));
+                format!(
+                    "<span {} style=\"{}\">{}</span>",
+                    attributes.join(" "),
+                    styles.join(" "),
+                    field("icon:text")
+                )
+                .into()
+            })
+            .register("caps", |_, args| {
+                value::to_string(args.first().unwrap_or(&JsonValue::Null))
+                    .to_uppercase()
+                    .into()
+            })
+            .register("isEven", |_, args| {
+                (value::to_number(args.first().unwrap_or(&JsonValue::Null)) % 2.0 == 0.0).into()
+            })
+            .register("isEvenLegacy", |_, args| {
+                (value::to_number(args.get(1).unwrap_or(&JsonValue::Null)) % 2.0 == 0.0).into()
+            })
+            .register("join", |_, args| match args.split_first() {
+                Some((joiner, rest)) => rest
+                    .iter()
+                    .map(|x| {
+                        if x.is_null() {
+                            "".into()
+                        } else {
+                            value::to_string(x)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(&value::to_string(joiner))
+                    .into(),
+                None => "".into(),
+            });
+
+        helpers
+    }
+
+    /// render every template in `tests/templates` and compare with the expected output
+    #[test]
+    fn test_templates() {
+        let tests_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests");
+        let data = json::parse(&fs::read_to_string(tests_dir.join("data.json")).unwrap()).unwrap();
+        let helpers = spec_helpers();
+
+        let mut failed = vec![];
+        for entry in fs::read_dir(tests_dir.join("templates/source")).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let expected = tests_dir.join(format!("templates/expected/{name}.html"));
+            let Ok(expected) = fs::read_to_string(expected) else {
+                continue;
+            };
+
+            let source = fs::read_to_string(&path).unwrap();
+            let output =
+                render_source(&source, &name, &data, &helpers, &CompileOptions::default()).unwrap();
+
+            if collapse_whitespace(&output) != collapse_whitespace(&expected) {
+                failed.push(name);
+            }
+        }
+
+        failed.sort();
+        assert_eq!(failed, Vec::<String>::new());
+    }
+}
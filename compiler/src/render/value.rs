@@ -0,0 +1,376 @@
+//! JavaScript semantics for JSON values, matching what compiled templates
+//! do when run by `lib/runtime.js`
+
+use itertools::Itertools;
+use json::JsonValue;
+use std::borrow::Cow;
+
+pub type Value<'a> = Cow<'a, JsonValue>;
+
+fn is_string(value: &JsonValue) -> bool {
+    matches!(value, JsonValue::Short(_) | JsonValue::String(_))
+}
+
+/// values which convert to strings when used with `+` or `==`
+fn is_stringy(value: &JsonValue) -> bool {
+    matches!(
+        value,
+        JsonValue::Short(_) | JsonValue::String(_) | JsonValue::Array(_) | JsonValue::Object(_)
+    )
+}
+
+/// `guard` from the runtime: `null` and empty arrays become empty strings
+pub fn guard(value: Value) -> Value {
+    match &*value {
+        JsonValue::Null => Cow::Owned(JsonValue::from("")),
+        JsonValue::Array(arr) if arr.is_empty() => Cow::Owned(JsonValue::from("")),
+        _ => value,
+    }
+}
+
+/// `!!value`
+pub fn truthy(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => false,
+        JsonValue::Boolean(b) => *b,
+        JsonValue::Number(_) => {
+            let n = to_number(value);
+            n != 0.0 && !n.is_nan()
+        }
+        JsonValue::Short(_) | JsonValue::String(_) => !value.as_str().unwrap_or("").is_empty(),
+        JsonValue::Object(_) | JsonValue::Array(_) => true,
+    }
+}
+
+/// `Number.prototype.toString`
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == 0.0 {
+        // includes -0
+        "0".to_string()
+    } else if n.abs() >= 1e21 || n.abs() < 1e-6 {
+        // JS always includes the sign of the exponent
+        let out = format!("{n:e}");
+        match out.split_once('e') {
+            Some((mantissa, exp)) if !exp.starts_with('-') => format!("{mantissa}e+{exp}"),
+            _ => out,
+        }
+    } else {
+        format!("{n}")
+    }
+}
+
+/// `String(value)`
+pub fn to_string(value: &JsonValue) -> Cow<'_, str> {
+    match value {
+        JsonValue::Null => "null".into(),
+        JsonValue::Boolean(b) => if *b { "true" } else { "false" }.into(),
+        JsonValue::Number(_) => number_to_string(to_number(value)).into(),
+        JsonValue::Short(_) | JsonValue::String(_) => value.as_str().unwrap_or("").into(),
+        // `null` elements are left empty by `Array.prototype.join`
+        JsonValue::Array(arr) => arr
+            .iter()
+            .map(|x| if x.is_null() { "".into() } else { to_string(x) })
+            .join(",")
+            .into(),
+        JsonValue::Object(_) => "[object Object]".into(),
+    }
+}
+
+/// `Number(value)`
+pub fn to_number(value: &JsonValue) -> f64 {
+    match value {
+        JsonValue::Null => 0.0,
+        JsonValue::Boolean(b) => f64::from(u8::from(*b)),
+        JsonValue::Number(n) => f64::from(*n),
+        JsonValue::Short(_) | JsonValue::String(_) => {
+            let s = value.as_str().unwrap_or("").trim();
+            let radix = |prefix: [&str; 2], radix| {
+                prefix
+                    .iter()
+                    .find_map(|p| s.strip_prefix(p))
+                    .map(|digits| u64::from_str_radix(digits, radix).map_or(f64::NAN, |x| x as f64))
+            };
+
+            if s.is_empty() {
+                0.0
+            } else if let Some(n) = radix(["0x", "0X"], 16)
+                .or_else(|| radix(["0o", "0O"], 8))
+                .or_else(|| radix(["0b", "0B"], 2))
+            {
+                n
+            } else {
+                match s.strip_prefix(['+', '-']).unwrap_or(s) {
+                    "Infinity" if s.starts_with('-') => f64::NEG_INFINITY,
+                    "Infinity" => f64::INFINITY,
+                    // reject the words Rust accepts but JS doesn't, like `inf` and `nan`
+                    rest if rest
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || ".eE+-".contains(c)) =>
+                    {
+                        s.parse().unwrap_or(f64::NAN)
+                    }
+                    _ => f64::NAN,
+                }
+            }
+        }
+        JsonValue::Array(_) | JsonValue::Object(_) => {
+            to_number(&JsonValue::from(&*to_string(value)))
+        }
+    }
+}
+
+/// `lhs + rhs`
+pub fn add<'a>(lhs: Value<'a>, rhs: Value<'a>) -> Value<'a> {
+    if is_stringy(&lhs) || is_stringy(&rhs) {
+        // reuse the buffer when concatenating onto a string we already own
+        let mut out = match lhs {
+            Cow::Owned(JsonValue::String(s)) => s,
+            lhs => to_string(&lhs).into_owned(),
+        };
+        out.push_str(&to_string(&rhs));
+
+        Cow::Owned(JsonValue::String(out))
+    } else {
+        Cow::Owned(JsonValue::from(to_number(&lhs) + to_number(&rhs)))
+    }
+}
+
+/// `lhs == rhs`
+///
+/// Objects and arrays are only equal if they are the same value,
+/// which is only possible if both are borrowed from the same context.
+pub fn loose_eq(lhs: &JsonValue, rhs: &JsonValue) -> bool {
+    use JsonValue::*;
+
+    match (lhs, rhs) {
+        (Null, Null) => true,
+        (Null, _) | (_, Null) => false,
+        (Boolean(a), Boolean(b)) => a == b,
+        (Number(_), Number(_)) => to_number(lhs) == to_number(rhs),
+        (a, b) if is_string(a) && is_string(b) => a.as_str() == b.as_str(),
+        (Object(_) | Array(_), Object(_) | Array(_)) => std::ptr::eq(lhs, rhs),
+        (Boolean(_), _) => loose_eq(&JsonValue::from(to_number(lhs)), rhs),
+        (_, Boolean(_)) => loose_eq(lhs, &JsonValue::from(to_number(rhs))),
+        (Object(_) | Array(_), _) => loose_eq(&JsonValue::from(&*to_string(lhs)), rhs),
+        (_, Object(_) | Array(_)) => loose_eq(lhs, &JsonValue::from(&*to_string(rhs))),
+        // one number and one string
+        _ => to_number(lhs) == to_number(rhs),
+    }
+}
+
+/// `lhs < rhs`, or `None` if either side is `NaN`,
+/// in which case every comparison is false
+///
+/// Strings are compared by UTF-16 code units, and everything else as numbers.
+pub fn less_than(lhs: &JsonValue, rhs: &JsonValue) -> Option<bool> {
+    fn primitive(value: &JsonValue) -> Value<'_> {
+        match value {
+            JsonValue::Object(_) | JsonValue::Array(_) => {
+                Cow::Owned(JsonValue::from(&*to_string(value)))
+            }
+            _ => Cow::Borrowed(value),
+        }
+    }
+    let (lhs, rhs) = (primitive(lhs), primitive(rhs));
+
+    if is_string(&lhs) && is_string(&rhs) {
+        let utf16 = |value: &JsonValue| {
+            value
+                .as_str()
+                .unwrap_or("")
+                .encode_utf16()
+                .collect::<Vec<_>>()
+        };
+        return Some(utf16(&lhs) < utf16(&rhs));
+    }
+
+    let (lhs, rhs) = (to_number(&lhs), to_number(&rhs));
+    if lhs.is_nan() || rhs.is_nan() {
+        None
+    } else {
+        Some(lhs < rhs)
+    }
+}
+
+/// parse a canonical array index like `3`, but not `03` or `+3`
+fn array_index(key: &str) -> Option<usize> {
+    key.parse::<usize>().ok().filter(|i| i.to_string() == key)
+}
+
+/// `value[key]`, or `None` if the property doesn't exist
+pub fn property<'a>(value: &'a JsonValue, key: &str) -> Option<Value<'a>> {
+    match value {
+        JsonValue::Object(obj) => obj.get(key).map(Cow::Borrowed),
+        JsonValue::Array(arr) => {
+            if key == "length" {
+                Some(Cow::Owned(JsonValue::from(arr.len())))
+            } else {
+                array_index(key).and_then(|i| arr.get(i)).map(Cow::Borrowed)
+            }
+        }
+        JsonValue::Short(_) | JsonValue::String(_) => {
+            // JS strings are indexed by UTF-16 code unit
+            let mut units = value.as_str().unwrap_or("").encode_utf16();
+            if key == "length" {
+                Some(Cow::Owned(JsonValue::from(units.count())))
+            } else {
+                let unit = units.nth(array_index(key)?)?;
+                Some(Cow::Owned(JsonValue::from(String::from_utf16_lossy(&[
+                    unit,
+                ]))))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// `value[key]` on a value which may be borrowed or owned
+pub fn get<'a>(value: Value<'a>, key: &str) -> Option<Value<'a>> {
+    match value {
+        Cow::Borrowed(value) => property(value, key),
+        Cow::Owned(value) => property(&value, key).map(|x| Cow::Owned(x.into_owned())),
+    }
+}
+
+/// `Object.keys(Object(value)).length`: items in arrays and objects,
+/// UTF-16 code units in strings, and nothing in anything else
+pub fn count(value: &JsonValue) -> usize {
+    match value {
+        JsonValue::Array(arr) => arr.len(),
+        JsonValue::Object(obj) => obj.len(),
+        JsonValue::Short(_) | JsonValue::String(_) => {
+            value.as_str().unwrap_or("").encode_utf16().count()
+        }
+        _ => 0,
+    }
+}
+
+/// `Object.keys(value)`, in the same order JS uses:
+/// integer keys ascending, followed by other keys in insertion order
+pub fn keys(value: &JsonValue) -> Vec<Cow<'_, str>> {
+    match value {
+        JsonValue::Array(arr) => (0..arr.len()).map(|i| i.to_string().into()).collect(),
+        JsonValue::Object(obj) => {
+            let (mut indices, others): (Vec<_>, Vec<_>) = obj
+                .iter()
+                .map(|(key, _)| key)
+                .partition(|key| array_index(key).is_some());
+            indices.sort_by_key(|key| array_index(key));
+
+            indices
+                .into_iter()
+                .chain(others)
+                .map(Cow::Borrowed)
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_to_string() {
+        assert_eq!(to_string(&JsonValue::from(5.0)), "5");
+        assert_eq!(to_string(&JsonValue::from(0.1)), "0.1");
+        assert_eq!(to_string(&JsonValue::from(-1.5e21)), "-1.5e+21");
+        assert_eq!(to_string(&JsonValue::from(1e-7)), "1e-7");
+        assert_eq!(to_string(&json::array![1, null, "a", [2, 3]]), "1,,a,2,3");
+        assert_eq!(to_string(&json::object! { a: 1 }), "[object Object]");
+    }
+
+    #[test]
+    fn test_to_number() {
+        assert_eq!(to_number(&JsonValue::from(" 12 ")), 12.0);
+        assert_eq!(to_number(&JsonValue::from("")), 0.0);
+        assert_eq!(to_number(&JsonValue::from("0x1A")), 26.0);
+        assert_eq!(to_number(&JsonValue::from("-Infinity")), f64::NEG_INFINITY);
+        assert!(to_number(&JsonValue::from("inf")).is_nan());
+        assert!(to_number(&JsonValue::from("12px")).is_nan());
+        assert_eq!(to_number(&json::array![7]), 7.0);
+    }
+
+    #[test]
+    fn test_add() {
+        let add = |a: JsonValue, b: JsonValue| add(Cow::Owned(a), Cow::Owned(b)).into_owned();
+
+        assert_eq!(add(1.into(), 2.into()), JsonValue::from(3));
+        assert_eq!(add(true.into(), JsonValue::Null), JsonValue::from(1));
+        assert_eq!(add(1.into(), "2".into()), JsonValue::from("12"));
+        assert_eq!(add("a".into(), JsonValue::Null), JsonValue::from("anull"));
+        assert_eq!(add(json::array![1, 2], 3.into()), JsonValue::from("1,23"));
+    }
+
+    #[test]
+    fn test_loose_eq() {
+        assert!(loose_eq(&JsonValue::from(1), &JsonValue::from("1")));
+        assert!(loose_eq(&JsonValue::from(true), &JsonValue::from("1")));
+        assert!(loose_eq(&JsonValue::from(false), &JsonValue::from("")));
+        assert!(loose_eq(&json::array![1, 2], &JsonValue::from("1,2")));
+        assert!(!loose_eq(&JsonValue::Null, &JsonValue::from(false)));
+        assert!(!loose_eq(&json::array![1], &json::array![1]));
+
+        let arr = json::array![1];
+        assert!(loose_eq(&arr, &arr));
+    }
+
+    #[test]
+    fn test_less_than() {
+        assert_eq!(
+            less_than(&JsonValue::from(2), &JsonValue::from("10")),
+            Some(true)
+        );
+        // strings compare by code units, not as numbers
+        assert_eq!(
+            less_than(&JsonValue::from("2"), &JsonValue::from("10")),
+            Some(false)
+        );
+        assert_eq!(less_than(&JsonValue::Null, &JsonValue::from(1)), Some(true));
+        assert_eq!(less_than(&json::array![1], &JsonValue::from(2)), Some(true));
+        assert_eq!(less_than(&JsonValue::from("a"), &JsonValue::from(1)), None);
+    }
+
+    #[test]
+    fn test_property() {
+        let value = json::object! { list: ["a", "b"], text: "héllo" };
+
+        assert_eq!(
+            property(&value["list"], "1").as_deref(),
+            Some(&JsonValue::from("b"))
+        );
+        assert_eq!(
+            property(&value["list"], "length").as_deref(),
+            Some(&JsonValue::from(2))
+        );
+        assert_eq!(property(&value["list"], "01"), None);
+        assert_eq!(
+            property(&value["text"], "1").as_deref(),
+            Some(&JsonValue::from("é"))
+        );
+        assert_eq!(property(&value, "missing"), None);
+    }
+
+    #[test]
+    fn test_keys() {
+        let value = json::object! { b: 1, "10": 2, a: 3, "2": 4 };
+        assert_eq!(keys(&value), vec!["2", "10", "b", "a"]);
+        assert_eq!(keys(&json::array![5, 6]), vec!["0", "1"]);
+        assert!(keys(&JsonValue::from("str")).is_empty());
+    }
+
+    #[test]
+    fn test_count() {
+        assert_eq!(count(&json::array![5, 6]), 2);
+        assert_eq!(count(&json::object! { a: 1 }), 1);
+        assert_eq!(count(&JsonValue::from("a😀")), 3);
+        assert_eq!(count(&JsonValue::from(12)), 0);
+        assert_eq!(count(&JsonValue::Null), 0);
+    }
+}
@@ -0,0 +1,604 @@
+//! Inferring what data a template reads from its context
+//!
+//! Paths in the tree are already resolved against the iterations they appear
+//! in, so `{./title}` inside `{{{ each posts }}}` is `posts[key0].title`,
+//! which is recorded as a `title` field on the elements of `posts`.
+
+use crate::parse::{
+    expression::{
+        number,
+        unescape,
+        Expression,
+    },
+    path::{
+        Path,
+        PathPart,
+    },
+    tree::Instruction,
+    Span,
+};
+use std::collections::BTreeMap;
+
+const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+/// non-standard keyword listing helper calls, ignored by validators
+const HELPERS_KEYWORD: &str = "x-benchpress-helpers";
+
+/// How a template uses a value in its context
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Field {
+    /// output, passed to a helper, or otherwise used as a value
+    pub read: bool,
+    /// tested by an `if`, or compared in one
+    pub conditional: bool,
+    /// properties read from the value
+    pub fields: BTreeMap<String, Field>,
+    /// the elements, if the value is iterated over
+    pub element: Option<Box<Field>>,
+    /// read or iterated, itself or through its properties,
+    /// somewhere an enclosing `if` doesn't test it first
+    pub required: bool,
+}
+
+impl Field {
+    pub fn is_iterated(&self) -> bool {
+        self.element.is_some()
+    }
+
+    /// only tested by conditionals, or used inside `if`s which test it,
+    /// so the template works without it
+    pub fn is_conditional_only(&self) -> bool {
+        !self.required
+    }
+
+    /// JSON Schema for values matching the field
+    ///
+    /// Fields which are only used in conditionals are optional,
+    /// and iterated values can be arrays or objects. Reading properties
+    /// doesn't constrain the type, since strings and arrays have them too.
+    pub fn to_json_schema(&self) -> json::JsonValue {
+        let mut schema = json::JsonValue::new_object();
+
+        if let Some(element) = &self.element {
+            let element = element.to_json_schema();
+            schema["type"] = json::array!["array", "object"];
+            schema["items"] = element.clone();
+            schema["additionalProperties"] = element;
+        }
+
+        if !self.fields.is_empty() {
+            schema["properties"] = self
+                .fields
+                .iter()
+                .map(|(name, field)| (name.as_str(), field.to_json_schema()))
+                .collect::<json::object::Object>()
+                .into();
+            schema["required"] = self
+                .fields
+                .iter()
+                .filter(|(_, field)| !field.is_conditional_only())
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .into();
+        }
+
+        schema
+    }
+}
+
+/// A context path read by a template, like `posts[].title`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DataPath {
+    pub path: String,
+    pub iterated: bool,
+    pub conditional_only: bool,
+}
+
+/// A call to a helper, with the source of each argument.
+/// Paths are given resolved, like `posts[].title`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HelperCall {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// Everything a template reads from its context, and the helpers it calls
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Schema {
+    /// the context itself
+    pub context: Field,
+    /// each distinct call, in the order they appear
+    pub helpers: Vec<HelperCall>,
+    /// paths tested by the `if`s around the instruction being inferred
+    guards: Vec<String>,
+}
+
+/// the property a path part reads, or `None` for a computed subscript
+/// like `[@key]`, which reads an element like iteration does
+fn property(part: &PathPart<Span>) -> Option<String> {
+    match part {
+        PathPart::Part(_) | PathPart::PartDepth(..) => Some(part.inner().to_string()),
+        PathPart::Index(_, expr) | PathPart::IndexDepth(_, expr, _) => match &**expr {
+            Expression::StringLiteral(value) => Some(unescape(value.fragment())),
+            Expression::NumberLiteral(value) => Some(json::stringify(number(value.fragment()))),
+            _ => None,
+        },
+    }
+}
+
+/// each field a path passes through, as the property it reads
+/// (or `None` for an element) and the path to it in dotted form,
+/// with `[]` for iteration and computed subscripts
+fn steps(path: Path<Span>) -> Vec<(Option<String>, String)> {
+    let mut out = vec![];
+    let mut prefix = String::new();
+    for part in path {
+        match property(part) {
+            Some(name) => {
+                if !prefix.is_empty() {
+                    prefix.push('.');
+                }
+                prefix.push_str(&name);
+                out.push((Some(name), prefix.clone()));
+            }
+            None => {
+                prefix.push_str("[]");
+                out.push((None, prefix.clone()));
+            }
+        }
+        if let PathPart::PartDepth(..) | PathPart::IndexDepth(..) = part {
+            prefix.push_str("[]");
+            out.push((None, prefix.clone()));
+        }
+    }
+
+    out
+}
+
+/// resolved path in dotted form, with `[]` for iteration and computed subscripts
+fn path_string(path: Path<Span>) -> String {
+    steps(path).pop().map(|(_, path)| path).unwrap_or_default()
+}
+
+/// whether a path reads from the context, rather than being
+/// a special `@` path or escaping above the root with `../`
+fn is_context_path(path: Path<Span>) -> bool {
+    path.first().is_some_and(|first| {
+        !first.inner().starts_with('@') && path.iter().all(|part| !part.inner().ends_with('/'))
+    })
+}
+
+impl Schema {
+    /// infer the schema of a parsed template
+    pub(crate) fn from_tree(input: &[Instruction<Span>]) -> Self {
+        let mut schema = Schema::default();
+        schema.body(input);
+        schema
+    }
+
+    /// record a path used from the context, returning the field at its end
+    ///
+    /// With `required`, the fields along the path are required, except
+    /// one tested by an enclosing `if` and those leading up to it.
+    fn insert(&mut self, path: Path<Span>, required: bool) -> &mut Field {
+        let steps = steps(path);
+        let guarded = steps
+            .iter()
+            .rposition(|(_, path)| self.guards.contains(path))
+            .map_or(0, |i| i + 1);
+
+        let mut field = &mut self.context;
+        for (i, (name, _)) in steps.into_iter().enumerate() {
+            field = match name {
+                Some(name) => field.fields.entry(name).or_default(),
+                None => field.element.get_or_insert_with(Default::default),
+            };
+            if required && i >= guarded {
+                field.required = true;
+            }
+        }
+
+        field
+    }
+
+    /// record the paths an `if` tests, which guard the reads in its branches
+    fn tested(expr: &Expression<Span>, out: &mut Vec<String>) {
+        match expr {
+            Expression::Path { path, .. } if is_context_path(path) => out.push(path_string(path)),
+            Expression::Negative { expr: subject, .. }
+            | Expression::Length { subject, .. }
+            | Expression::Empty { subject, .. } => Self::tested(subject, out),
+            Expression::And { lhs, rhs, .. } | Expression::Or { lhs, rhs, .. } => {
+                Self::tested(lhs, out);
+                Self::tested(rhs, out);
+            }
+            _ => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression<Span>, conditional: bool) {
+        match expr {
+            Expression::StringLiteral(_)
+            | Expression::NumberLiteral(_)
+            | Expression::Keyword { .. }
+            | Expression::KeywordDepth { .. } => {}
+            Expression::Path { path, .. } => {
+                if is_context_path(path) {
+                    let field = self.insert(path, !conditional);
+                    if conditional {
+                        field.conditional = true;
+                    } else {
+                        field.read = true;
+                    }
+                }
+                self.subscripts(path);
+            }
+            Expression::Negative { expr, .. } => self.expression(expr, conditional),
+            Expression::Array { items, .. } => {
+                for item in items {
+                    self.expression(item, false);
+                }
+            }
+            Expression::Range { start, end, .. } => {
+                self.expression(start, false);
+                self.expression(end, false);
+            }
+            // a missing value is just empty
+            Expression::Length { subject, .. } | Expression::Empty { subject, .. } => {
+                self.expression(subject, true)
+            }
+            Expression::Helper { name, args, .. } | Expression::LegacyHelper { name, args, .. } => {
+                let call = HelperCall {
+                    name: name.fragment().to_string(),
+                    args: args
+                        .iter()
+                        .map(|arg| match arg {
+                            Expression::Path { path, .. } => path_string(path),
+                            _ => arg.span().fragment().to_string(),
+                        })
+                        .collect(),
+                };
+                if !self.helpers.contains(&call) {
+                    self.helpers.push(call);
+                }
+
+                // arguments are passed as values, even in a conditional
+                for arg in args {
+                    self.expression(arg, false);
+                }
+            }
+            Expression::Equ { lhs, rhs, .. }
+            | Expression::Neq { lhs, rhs, .. }
+            | Expression::Lt { lhs, rhs, .. }
+            | Expression::Lte { lhs, rhs, .. }
+            | Expression::Gt { lhs, rhs, .. }
+            | Expression::Gte { lhs, rhs, .. }
+            | Expression::And { lhs, rhs, .. }
+            | Expression::Or { lhs, rhs, .. } => {
+                self.expression(lhs, conditional);
+                self.expression(rhs, conditional);
+            }
+            Expression::Ternary {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                self.expression(condition, true);
+                self.expression(then, conditional);
+                self.expression(otherwise, conditional);
+            }
+        }
+    }
+
+    /// record the values used as computed subscripts in a path
+    fn subscripts(&mut self, path: Path<Span>) {
+        for part in path {
+            if let PathPart::Index(_, expr) | PathPart::IndexDepth(_, expr, _) = part {
+                self.expression(expr, false);
+            }
+        }
+    }
+
+    fn body(&mut self, body: &[Instruction<Span>]) {
+        for instruction in body {
+            match instruction {
+                Instruction::Text(_) => {}
+                Instruction::InterpEscaped(expr) | Instruction::InterpRaw(expr) => {
+                    self.expression(expr, false)
+                }
+                Instruction::If { subject, body, alt } => {
+                    self.expression(subject, true);
+
+                    let count = self.guards.len();
+                    Self::tested(subject, &mut self.guards);
+                    self.body(body);
+                    self.body(alt);
+                    self.guards.truncate(count);
+                }
+                Instruction::Iter {
+                    subject, body, alt, ..
+                } => {
+                    match subject {
+                        Expression::Path { path, .. } if is_context_path(path) => {
+                            self.insert(path, true)
+                                .element
+                                .get_or_insert_with(Default::default);
+                            self.subscripts(path);
+                        }
+                        _ => self.expression(subject, false),
+                    }
+                    self.body(body);
+                    self.body(alt);
+                }
+                Instruction::Block { body, .. } => self.body(body),
+                // rendered with the same context, but its template isn't known here
+                Instruction::Include { .. } => {}
+            }
+        }
+    }
+
+    /// every path read from the context, sorted
+    pub fn paths(&self) -> Vec<DataPath> {
+        fn walk(field: &Field, prefix: &str, out: &mut Vec<DataPath>) {
+            for (name, field) in &field.fields {
+                let mut path = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{prefix}.{name}")
+                };
+                out.push(DataPath {
+                    path: path.clone(),
+                    iterated: field.is_iterated(),
+                    conditional_only: field.is_conditional_only(),
+                });
+
+                walk(field, &path, out);
+                if let Some(element) = &field.element {
+                    path.push_str("[]");
+                    walk(element, &path, out);
+                }
+            }
+        }
+
+        let mut out = vec![];
+        walk(&self.context, "", &mut out);
+        out
+    }
+
+    /// JSON Schema for contexts the template can render,
+    /// with helper calls listed under `x-benchpress-helpers`
+    pub fn to_json_schema(&self) -> json::JsonValue {
+        let mut schema = json::object! {
+            "$schema": JSON_SCHEMA_DIALECT,
+            type: "object",
+        };
+        let mut context = self.context.to_json_schema();
+        for key in ["properties", "required"] {
+            if context.has_key(key) {
+                schema[key] = context.remove(key);
+            }
+        }
+
+        if !self.helpers.is_empty() {
+            let mut helpers = json::JsonValue::new_object();
+            for call in &self.helpers {
+                if !helpers.has_key(&call.name) {
+                    helpers[&call.name] = json::JsonValue::new_array();
+                }
+                helpers[&call.name].push(call.args.clone()).unwrap();
+            }
+            schema[HELPERS_KEYWORD] = helpers;
+        }
+
+        schema
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CompileOptions;
+    use pretty_assertions::assert_eq;
+
+    const SOURCE: &str = "<h1>{title}</h1>
+{{{ if !user.loggedIn }}}<a>log in</a>{{{ end }}}
+{{{ if (user.role == \"admin\") }}}{user.name}{{{ end }}}
+{{{ each posts }}}
+  {./title} {caps(./title, \"é\")}
+  {{{ each ./tags }}}{@value}{{{ end }}}
+{{{ end }}}
+{function.join, list, \", \"}";
+
+    fn schema() -> Schema {
+        crate::infer_schema(SOURCE, "<test>", &CompileOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_paths() {
+        let path = |path: &str, iterated, conditional_only| DataPath {
+            path: path.to_string(),
+            iterated,
+            conditional_only,
+        };
+
+        assert_eq!(
+            schema().paths(),
+            vec![
+                path("list", false, false),
+                path("posts", true, false),
+                path("posts[].tags", true, false),
+                path("posts[].title", false, false),
+                path("title", false, false),
+                path("user", false, false),
+                path("user.loggedIn", false, true),
+                path("user.name", false, false),
+                path("user.role", false, true),
+            ]
+        );
+
+        let source =
+            "{users[post.author].name} {map[\"a b\"]} {{{ each rows[0] }}}{@value}{{{ end }}}";
+        let schema = crate::infer_schema(source, "<test>", &CompileOptions::default());
+        assert_eq!(
+            schema.unwrap().paths(),
+            vec![
+                path("map", false, false),
+                path("map.a b", false, false),
+                path("post", false, false),
+                path("post.author", false, false),
+                path("rows", false, false),
+                path("rows.0", true, false),
+                path("users", true, false),
+                path("users[].name", false, false),
+            ]
+        );
+
+        // only the condition of a ternary is conditional
+        let source = "{ post.pinned ? post.label : \"none\" }";
+        let schema = crate::infer_schema(source, "<test>", &CompileOptions::default());
+        assert_eq!(
+            schema.unwrap().paths(),
+            vec![
+                path("post", false, false),
+                path("post.label", false, false),
+                path("post.pinned", false, true),
+            ]
+        );
+
+        // loop variables name the items of the subject
+        let source = "{{{ each post in posts }}}{post.title}{{{ each tag in post.tags }}}{tag.name}{{{ end }}}{{{ end }}}";
+        let schema = crate::infer_schema(source, "<test>", &CompileOptions::default());
+        assert_eq!(
+            schema.unwrap().paths(),
+            vec![
+                path("posts", true, false),
+                path("posts[].tags", true, false),
+                path("posts[].tags[].name", false, false),
+                path("posts[].title", false, false),
+            ]
+        );
+
+        // parents of fields only tested by conditionals are optional too
+        let source = "{{{ if !user.admin }}}{{{ if user.profile.public }}}{user.name}{{{ end }}}{{{ end }}}{{{ if site.flags.beta }}}beta{{{ end }}}";
+        let schema = crate::infer_schema(source, "<test>", &CompileOptions::default());
+        assert_eq!(
+            schema.unwrap().paths(),
+            vec![
+                path("site", false, true),
+                path("site.flags", false, true),
+                path("site.flags.beta", false, true),
+                path("user", false, false),
+                path("user.admin", false, true),
+                path("user.name", false, false),
+                path("user.profile", false, true),
+                path("user.profile.public", false, true),
+            ]
+        );
+
+        // reads inside an `if` don't require what it tests, or anything above it
+        let source = "{{{ each posts }}}{{{ end }}}{{{ if user }}}{user.name}{{{ end }}}{{{ if a.b }}}{{{ each a.b.c }}}{{{ end }}}{{{ else }}}{a.d}{{{ end }}}";
+        let schema = crate::infer_schema(source, "<test>", &CompileOptions::default());
+        assert_eq!(
+            schema.unwrap().paths(),
+            vec![
+                path("a", false, false),
+                path("a.b", false, true),
+                path("a.b.c", true, false),
+                path("a.d", false, false),
+                path("posts", true, false),
+                path("user", false, true),
+                path("user.name", false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_helpers() {
+        let call = |name: &str, args: &[&str]| HelperCall {
+            name: name.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+
+        assert_eq!(
+            schema().helpers,
+            vec![
+                call("caps", &["posts[].title", "\"é\""]),
+                call("join", &["list", "\", \""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let any = json::object! {};
+        let expected = json::object! {
+            "$schema": JSON_SCHEMA_DIALECT,
+            type: "object",
+            properties: {
+                list: any.clone(),
+                posts: {
+                    type: ["array", "object"],
+                    items: {
+                        properties: {
+                            tags: {
+                                type: ["array", "object"],
+                                items: any.clone(),
+                                additionalProperties: any.clone(),
+                            },
+                            title: any.clone(),
+                        },
+                        required: ["tags", "title"],
+                    },
+                    additionalProperties: {
+                        properties: {
+                            tags: {
+                                type: ["array", "object"],
+                                items: any.clone(),
+                                additionalProperties: any.clone(),
+                            },
+                            title: any.clone(),
+                        },
+                        required: ["tags", "title"],
+                    },
+                },
+                title: any.clone(),
+                user: {
+                    properties: {
+                        loggedIn: any.clone(),
+                        name: any.clone(),
+                        role: any.clone(),
+                    },
+                    required: ["name"],
+                },
+            },
+            required: ["list", "posts", "title", "user"],
+            "x-benchpress-helpers": {
+                caps: [["posts[].title", "\"é\""]],
+                join: [["list", "\", \""]],
+            },
+        };
+
+        assert_eq!(schema().to_json_schema(), expected);
+
+        // `.length` and numeric parts read strings and arrays, not just objects
+        let source = "{title.length} {posts.0.title}";
+        let schema = crate::infer_schema(source, "<test>", &CompileOptions::default());
+        let schema = schema.unwrap().to_json_schema();
+        assert!(!schema["properties"]["title"].has_key("type"));
+        assert!(!schema["properties"]["posts"].has_key("type"));
+        assert_eq!(
+            schema["properties"]["posts"]["properties"]["0"]["required"],
+            json::array!["title"]
+        );
+
+        // an empty context is valid when the template tests `user` first
+        let source = "{{{ each posts }}}{{{ end }}}{{{ if user }}}{user.name}{{{ end }}}";
+        let schema = crate::infer_schema(source, "<test>", &CompileOptions::default());
+        let schema = schema.unwrap().to_json_schema();
+        assert_eq!(schema["required"], json::array!["posts"]);
+        assert_eq!(
+            schema["properties"]["user"]["required"],
+            json::array!["name"]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Templates parsed once and rendered many times
+
+mod binary;
+
+pub use binary::DecodeError;
+
+use crate::{
+    parse::{
+        tree::Instruction,
+        Fragment,
+        Span,
+    },
+    render::{
+        self,
+        Helpers,
+    },
+    CompileError,
+    CompileOptions,
+};
+use std::sync::Arc;
+
+/// A fragment of template source, sharing ownership of the whole source
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SourceSpan {
+    source: Arc<str>,
+    start: usize,
+    end: usize,
+}
+
+impl SourceSpan {
+    fn new(source: &Arc<str>, span: Span) -> Self {
+        SourceSpan {
+            source: source.clone(),
+            start: span.location_offset(),
+            end: span.location_offset() + span.len(),
+        }
+    }
+}
+
+impl Fragment for SourceSpan {
+    fn text(&self) -> &str {
+        &self.source[self.start..self.end]
+    }
+}
+
+/// A parsed template which owns its source
+///
+/// Parsing happens once, in [`Template::new`], after which the template
+/// can be rendered any number of times, shared between threads, or
+/// cached with [`Template::to_bytes`] and [`Template::from_bytes`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Template {
+    filename: String,
+    source: Arc<str>,
+    tree: Vec<Instruction<SourceSpan>>,
+}
+
+impl Template {
+    /// Parse a template, failing if any errors were found
+    ///
+    /// Includes are left to the `__include` helper, even with a
+    /// [`CompileOptions::resolver`], since a template only owns its own source.
+    pub fn new(
+        source: &str,
+        filename: &str,
+        options: &CompileOptions,
+    ) -> Result<Self, CompileError> {
+        let source: Arc<str> = source.into();
+        let tree = crate::try_parse(&source, filename, &Default::default(), options)?
+            .into_iter()
+            .map(|instruction| instruction.map_spans(&mut |span| SourceSpan::new(&source, span)))
+            .collect();
+
+        Ok(Template {
+            filename: filename.to_string(),
+            source,
+            tree,
+        })
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Render the template with the given context
+    pub fn render(&self, context: &json::JsonValue, helpers: &Helpers) -> String {
+        render::render(&self.tree, context, helpers)
+    }
+
+    /// Serialize to a compact binary form, see [`Template::from_bytes`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        binary::encode(self)
+    }
+
+    /// Load a template serialized by [`Template::to_bytes`],
+    /// without parsing it again
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        binary::decode(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const SOURCE: &str = "<h1>{title}</h1>
+{{{ each posts }}}
+  {{{ if ((./author == \"you\") && !@first) }}}{{caps(./title, \"é\")}}{{{ else }}}{@index}{{{ end }}}
+  <!-- BEGIN tags -->{function.join, @value}<!-- END tags -->
+{{{ end }}}";
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Template>();
+    }
+
+    #[test]
+    fn test_render() {
+        let template = Template::new(SOURCE, "<test>", &CompileOptions::default()).unwrap();
+        let context = json::object! {
+            title: "a < b",
+            posts: [{ author: "you", title: "first" }, { author: "you", title: "second" }],
+        };
+        let mut helpers = Helpers::new();
+        helpers.register("caps", |_, args| {
+            json::JsonValue::from(args[0].as_str().unwrap_or("").to_uppercase())
+        });
+
+        let expected = "<h1>a &lt; b</h1>\n\n  0\n  \n\n  SECOND\n  \n";
+        assert_eq!(template.render(&context, &helpers), expected);
+        // rendering doesn't consume or change the template
+        assert_eq!(template.render(&context, &helpers), expected);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = Template::new("{{{ else }}}", "<test>", &CompileOptions::default());
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let template = Template::new(SOURCE, "posts.tpl", &CompileOptions::default()).unwrap();
+        let bytes = template.to_bytes();
+        let decoded = Template::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded, template);
+        assert_eq!(decoded.filename(), "posts.tpl");
+        // spans are stored as offsets, so the source is only included once
+        assert!(bytes.len() < SOURCE.len() * 2);
+
+        assert_eq!(
+            Template::from_bytes(b"nope"),
+            Err(DecodeError::InvalidHeader)
+        );
+        assert_eq!(
+            Template::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+
+        let template = Template::new(
+            "{{{ if (count == 1.5) }}}{-2}{{{ end }}}{ a ? b : c }{{{ each a[@root.b] }}}{a[\"x\"][@key]}{{{ end }}}{a.@length}{@empty}{@even}{{{ each k, v in b() }}}{k}{v}{{{ end }}}{{{ each [1, c..d] }}}{{{ end }}}",
+            "<test>",
+            &Default::default(),
+        );
+        let template = template.unwrap();
+        assert_eq!(Template::from_bytes(&template.to_bytes()), Ok(template));
+
+        let template = Template::new("{{{ include \"footer\" }}}", "<test>", &Default::default());
+        let template = template.unwrap();
+        assert_eq!(Template::from_bytes(&template.to_bytes()), Ok(template));
+
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(
+            Template::from_bytes(&extra),
+            Err(DecodeError::TrailingBytes)
+        );
+
+        // header, version, empty filename and source, then one interpolation
+        // of a million nested negations, which must not overflow the stack
+        let mut nested = b"BPT\x01\x00\x00\x01\x01".to_vec();
+        for _ in 0..1_000_000 {
+            // negative, with an empty span
+            nested.extend_from_slice(&[3, 0, 0]);
+        }
+        assert_eq!(Template::from_bytes(&nested), Err(DecodeError::TooDeep));
+    }
+}
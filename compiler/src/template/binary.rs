@@ -0,0 +1,605 @@
+//! Compact binary encoding of parsed templates
+//!
+//! ```text
+//! magic     b"BPT"
+//! version   u8
+//! filename  string
+//! source    string
+//! tree      list of instructions
+//! ```
+//!
+//! Integers are LEB128 varints, strings are a byte length followed by UTF-8,
+//! and lists are a length followed by their items. Nodes start with a tag
+//! byte, and spans are stored as an offset and length into the source.
+
+use super::{
+    SourceSpan,
+    Template,
+};
+use crate::parse::{
+    expression::{
+        Expression,
+        Keyword,
+    },
+    path::PathPart,
+    tree::Instruction,
+};
+use std::{
+    convert::TryFrom,
+    fmt,
+    sync::Arc,
+};
+
+const MAGIC: &[u8] = b"BPT";
+const VERSION: u8 = 1;
+/// most instructions and expressions that can be nested in each other,
+/// so untrusted input can't overflow the stack while decoding
+const MAX_NESTING: usize = 256;
+
+/// Reasons bytes can't be loaded as a [`Template`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DecodeError {
+    /// not a serialized template
+    InvalidHeader,
+    /// serialized by an incompatible version of benchpress
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    InvalidTag(u8),
+    InvalidInteger,
+    InvalidUtf8,
+    /// a span outside of the source
+    InvalidSpan,
+    TrailingBytes,
+    /// instructions or expressions nested more than the decoder allows
+    TooDeep,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidHeader => write!(f, "not a serialized template"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported format version {v}"),
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::InvalidTag(tag) => write!(f, "invalid tag {tag}"),
+            DecodeError::InvalidInteger => write!(f, "invalid integer"),
+            DecodeError::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            DecodeError::InvalidSpan => write!(f, "span out of bounds"),
+            DecodeError::TrailingBytes => write!(f, "unexpected bytes after end of template"),
+            DecodeError::TooDeep => write!(f, "template nested too deeply"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn keyword_tag(keyword: &Keyword) -> u8 {
+    match keyword {
+        Keyword::Root => 0,
+        Keyword::Key => 1,
+        Keyword::Index => 2,
+        Keyword::Value => 3,
+        Keyword::First => 4,
+        Keyword::Last => 5,
+        Keyword::True => 6,
+        Keyword::False => 7,
+        Keyword::Odd => 8,
+        Keyword::Even => 9,
+    }
+}
+
+fn tag_keyword(tag: u8) -> Option<Keyword> {
+    Some(match tag {
+        0 => Keyword::Root,
+        1 => Keyword::Key,
+        2 => Keyword::Index,
+        3 => Keyword::Value,
+        4 => Keyword::First,
+        5 => Keyword::Last,
+        6 => Keyword::True,
+        7 => Keyword::False,
+        8 => Keyword::Odd,
+        9 => Keyword::Even,
+        _ => return None,
+    })
+}
+
+struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    fn uint(&mut self, mut n: usize) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.out.push(byte);
+                return;
+            }
+            self.out.push(byte | 0x80);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.uint(s.len());
+        self.out.extend_from_slice(s.as_bytes());
+    }
+
+    fn span(&mut self, span: &SourceSpan) {
+        self.uint(span.start);
+        self.uint(span.end - span.start);
+    }
+
+    fn body(&mut self, body: &[Instruction<SourceSpan>]) {
+        self.uint(body.len());
+        for instruction in body {
+            self.instruction(instruction);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction<SourceSpan>) {
+        match instruction {
+            Instruction::Text(span) => {
+                self.out.push(0);
+                self.span(span);
+            }
+            Instruction::InterpEscaped(expr) => {
+                self.out.push(1);
+                self.expression(expr);
+            }
+            Instruction::InterpRaw(expr) => {
+                self.out.push(2);
+                self.expression(expr);
+            }
+            Instruction::If { subject, body, alt } => {
+                self.out.push(3);
+                self.expression(subject);
+                self.body(body);
+                self.body(alt);
+            }
+            Instruction::Iter {
+                depth,
+                subject,
+                body,
+                alt,
+            } => {
+                self.out.push(4);
+                self.uint(*depth as usize);
+                self.expression(subject);
+                self.body(body);
+                self.body(alt);
+            }
+            Instruction::Include { name } => {
+                self.out.push(5);
+                self.span(name);
+            }
+            Instruction::Block { name, body } => {
+                self.out.push(6);
+                self.span(name);
+                self.body(body);
+            }
+        }
+    }
+
+    fn expressions(&mut self, exprs: &[Expression<SourceSpan>]) {
+        self.uint(exprs.len());
+        for expr in exprs {
+            self.expression(expr);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression<SourceSpan>) {
+        match expr {
+            Expression::StringLiteral(span) => {
+                self.out.push(0);
+                self.span(span);
+            }
+            Expression::NumberLiteral(span) => {
+                self.out.push(10);
+                self.span(span);
+            }
+            Expression::Ternary {
+                span,
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.out.push(15);
+                self.span(span);
+                self.expression(condition);
+                self.expression(then);
+                self.expression(otherwise);
+            }
+            Expression::Keyword { span, keyword } => {
+                self.out.push(1);
+                self.span(span);
+                self.out.push(keyword_tag(keyword));
+            }
+            Expression::KeywordDepth {
+                span,
+                keyword,
+                depth,
+            } => {
+                self.out.push(18);
+                self.span(span);
+                self.out.push(keyword_tag(keyword));
+                self.uint(*depth as usize);
+            }
+            Expression::Path { span, path } => {
+                self.out.push(2);
+                self.span(span);
+                self.uint(path.len());
+                for part in path {
+                    match part {
+                        PathPart::Part(span) => {
+                            self.out.push(0);
+                            self.span(span);
+                        }
+                        PathPart::PartDepth(span, depth) => {
+                            self.out.push(1);
+                            self.span(span);
+                            self.uint(*depth as usize);
+                        }
+                        PathPart::Index(span, expr) => {
+                            self.out.push(2);
+                            self.span(span);
+                            self.expression(expr);
+                        }
+                        PathPart::IndexDepth(span, expr, depth) => {
+                            self.out.push(3);
+                            self.span(span);
+                            self.expression(expr);
+                            self.uint(*depth as usize);
+                        }
+                    }
+                }
+            }
+            Expression::Negative { span, expr } => {
+                self.out.push(3);
+                self.span(span);
+                self.expression(expr);
+            }
+            Expression::Array { span, items } => {
+                self.out.push(19);
+                self.span(span);
+                self.expressions(items);
+            }
+            Expression::Range { span, start, end } => {
+                self.out.push(20);
+                self.span(span);
+                self.expression(start);
+                self.expression(end);
+            }
+            Expression::Length { span, subject } => {
+                self.out.push(16);
+                self.span(span);
+                self.expression(subject);
+            }
+            Expression::Empty { span, subject } => {
+                self.out.push(17);
+                self.span(span);
+                self.expression(subject);
+            }
+            Expression::Helper { span, name, args } => {
+                self.out.push(4);
+                self.span(span);
+                self.span(name);
+                self.expressions(args);
+            }
+            Expression::LegacyHelper { span, name, args } => {
+                self.out.push(5);
+                self.span(span);
+                self.span(name);
+                self.expressions(args);
+            }
+            Expression::Equ { span, lhs, rhs }
+            | Expression::Neq { span, lhs, rhs }
+            | Expression::And { span, lhs, rhs }
+            | Expression::Or { span, lhs, rhs }
+            | Expression::Lt { span, lhs, rhs }
+            | Expression::Lte { span, lhs, rhs }
+            | Expression::Gt { span, lhs, rhs }
+            | Expression::Gte { span, lhs, rhs } => {
+                self.out.push(match expr {
+                    Expression::Equ { .. } => 6,
+                    Expression::Neq { .. } => 7,
+                    Expression::And { .. } => 8,
+                    Expression::Or { .. } => 9,
+                    Expression::Lt { .. } => 11,
+                    Expression::Lte { .. } => 12,
+                    Expression::Gt { .. } => 13,
+                    _ => 14,
+                });
+                self.span(span);
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+        }
+    }
+}
+
+pub fn encode(template: &Template) -> Vec<u8> {
+    let mut writer = Writer {
+        out: Vec::with_capacity(template.source.len() * 2),
+    };
+    writer.out.extend_from_slice(MAGIC);
+    writer.out.push(VERSION);
+    writer.string(&template.filename);
+    writer.string(&template.source);
+    writer.body(&template.tree);
+
+    writer.out
+}
+
+struct Reader<'b> {
+    input: &'b [u8],
+    source: Arc<str>,
+    /// instructions and expressions currently being read
+    nesting: usize,
+}
+
+type Result<T> = std::result::Result<T, DecodeError>;
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8> {
+        let (&first, rest) = self.input.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+        self.input = rest;
+        Ok(first)
+    }
+
+    fn uint(&mut self) -> Result<usize> {
+        let mut n: usize = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            let bits = usize::from(byte & 0x7f);
+            if shift >= usize::BITS || (bits << shift) >> shift != bits {
+                return Err(DecodeError::InvalidInteger);
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    fn depth(&mut self) -> Result<u32> {
+        u32::try_from(self.uint()?).map_err(|_| DecodeError::InvalidInteger)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.uint()?;
+        if len > self.input.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    fn span(&mut self) -> Result<SourceSpan> {
+        let start = self.uint()?;
+        let end = start
+            .checked_add(self.uint()?)
+            .ok_or(DecodeError::InvalidSpan)?;
+        if self.source.get(start..end).is_none() {
+            return Err(DecodeError::InvalidSpan);
+        }
+
+        Ok(SourceSpan {
+            source: self.source.clone(),
+            start,
+            end,
+        })
+    }
+
+    /// read a length followed by that many items
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let len = self.uint()?;
+        // every item takes at least one byte, so don't trust a larger length
+        let mut out = Vec::with_capacity(len.min(self.input.len()));
+        for _ in 0..len {
+            out.push(item(self)?);
+        }
+
+        Ok(out)
+    }
+
+    /// read something nested one level deeper, up to [`MAX_NESTING`] levels
+    fn nested<T>(&mut self, item: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.nesting >= MAX_NESTING {
+            return Err(DecodeError::TooDeep);
+        }
+
+        self.nesting += 1;
+        let out = item(self);
+        self.nesting -= 1;
+        out
+    }
+
+    fn body(&mut self) -> Result<Vec<Instruction<SourceSpan>>> {
+        self.list(Self::instruction)
+    }
+
+    fn instruction(&mut self) -> Result<Instruction<SourceSpan>> {
+        self.nested(Self::instruction_inner)
+    }
+
+    fn instruction_inner(&mut self) -> Result<Instruction<SourceSpan>> {
+        Ok(match self.byte()? {
+            0 => Instruction::Text(self.span()?),
+            1 => Instruction::InterpEscaped(self.expression()?),
+            2 => Instruction::InterpRaw(self.expression()?),
+            3 => Instruction::If {
+                subject: self.expression()?,
+                body: self.body()?,
+                alt: self.body()?,
+            },
+            4 => Instruction::Iter {
+                depth: self.depth()?,
+                subject: self.expression()?,
+                body: self.body()?,
+                alt: self.body()?,
+            },
+            5 => Instruction::Include { name: self.span()? },
+            6 => Instruction::Block {
+                name: self.span()?,
+                body: self.body()?,
+            },
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        })
+    }
+
+    fn boxed(&mut self) -> Result<Box<Expression<SourceSpan>>> {
+        self.expression().map(Box::new)
+    }
+
+    fn path_part(&mut self) -> Result<PathPart<SourceSpan>> {
+        Ok(match self.byte()? {
+            0 => PathPart::Part(self.span()?),
+            1 => PathPart::PartDepth(self.span()?, self.depth()?),
+            2 => PathPart::Index(self.span()?, self.boxed()?),
+            3 => PathPart::IndexDepth(self.span()?, self.boxed()?, self.depth()?),
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        })
+    }
+
+    fn expression(&mut self) -> Result<Expression<SourceSpan>> {
+        self.nested(Self::expression_inner)
+    }
+
+    fn expression_inner(&mut self) -> Result<Expression<SourceSpan>> {
+        Ok(match self.byte()? {
+            0 => Expression::StringLiteral(self.span()?),
+            1 => Expression::Keyword {
+                span: self.span()?,
+                keyword: {
+                    let tag = self.byte()?;
+                    tag_keyword(tag).ok_or(DecodeError::InvalidTag(tag))?
+                },
+            },
+            2 => Expression::Path {
+                span: self.span()?,
+                path: self.list(Self::path_part)?,
+            },
+            3 => Expression::Negative {
+                span: self.span()?,
+                expr: self.boxed()?,
+            },
+            4 => Expression::Helper {
+                span: self.span()?,
+                name: self.span()?,
+                args: self.list(Self::expression)?,
+            },
+            5 => Expression::LegacyHelper {
+                span: self.span()?,
+                name: self.span()?,
+                args: self.list(Self::expression)?,
+            },
+            6 => Expression::Equ {
+                span: self.span()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            7 => Expression::Neq {
+                span: self.span()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            8 => Expression::And {
+                span: self.span()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            9 => Expression::Or {
+                span: self.span()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            10 => Expression::NumberLiteral(self.span()?),
+            11 => Expression::Lt {
+                span: self.span()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            12 => Expression::Lte {
+                span: self.span()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            13 => Expression::Gt {
+                span: self.span()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            14 => Expression::Gte {
+                span: self.span()?,
+                lhs: self.boxed()?,
+                rhs: self.boxed()?,
+            },
+            15 => Expression::Ternary {
+                span: self.span()?,
+                condition: self.boxed()?,
+                then: self.boxed()?,
+                otherwise: self.boxed()?,
+            },
+            16 => Expression::Length {
+                span: self.span()?,
+                subject: self.boxed()?,
+            },
+            17 => Expression::Empty {
+                span: self.span()?,
+                subject: self.boxed()?,
+            },
+            18 => Expression::KeywordDepth {
+                span: self.span()?,
+                keyword: {
+                    let tag = self.byte()?;
+                    tag_keyword(tag).ok_or(DecodeError::InvalidTag(tag))?
+                },
+                depth: self.depth()?,
+            },
+            19 => Expression::Array {
+                span: self.span()?,
+                items: self.list(Self::expression)?,
+            },
+            20 => Expression::Range {
+                span: self.span()?,
+                start: self.boxed()?,
+                end: self.boxed()?,
+            },
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        })
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Template> {
+    let rest = bytes
+        .strip_prefix(MAGIC)
+        .ok_or(DecodeError::InvalidHeader)?;
+    let mut reader = Reader {
+        input: rest,
+        source: "".into(),
+        nesting: 0,
+    };
+
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let filename = reader.string()?;
+    reader.source = reader.string()?.into();
+    let tree = reader.body()?;
+
+    if !reader.input.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+
+    Ok(Template {
+        filename,
+        source: reader.source,
+        tree,
+    })
+}
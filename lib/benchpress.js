@@ -7,11 +7,13 @@ const Benchpress = (typeof module === 'object' && module.exports) ? module.expor
 
 const runtime = require('./runtime');
 const precompile = require('./precompile');
+const schema = require('./schema');
 const __express = require('./express');
 const evaluate = require('./evaluate');
 const { compileRender, compileParse } = require('./compile-render');
 
 Benchpress.precompile = precompile;
+Benchpress.schema = schema;
 Benchpress.__express = __express;
 Benchpress.evaluate = evaluate;
 Benchpress.compileRender = compileRender;
@@ -58,6 +60,21 @@ Benchpress.registerHelper('__escape', (str) => {
 
 Benchpress.cache = {};
 
+// loaded template functions, by name, for `{{{ include }}}`
+Benchpress.partials = {};
+
+Benchpress.registerHelper('__include', function include(name) {
+  const partial = Benchpress.partials[name];
+  if (!partial) {
+    return '';
+  }
+
+  // helpers are called with the context as `this`, so the partial
+  // always renders from the root data, even inside a loop,
+  // unlike templates inlined at compile time
+  return runtime(Benchpress.helpers, this, partial);
+});
+
 Benchpress.globals = {};
 
 /**
@@ -83,6 +100,7 @@ Benchpress.addGlobals = function addGlobals(data) {
  */
 Benchpress.flush = function flush() {
   Benchpress.cache = {};
+  Benchpress.partials = {};
 };
 
 // necessary to support both promises and callbacks
@@ -99,6 +117,33 @@ function load(template) {
   });
 }
 
+/**
+ * Load the templates included by a template function, and the ones they include,
+ * so they can be rendered synchronously
+ * @private
+ * @param {function} templateFunction - Compiled template function
+ * @param {Set<string>} [seen] - Names already being loaded
+ * @returns {Promise<function>} - The template function, once its includes are loaded
+ */
+Benchpress.loadIncludes = function loadIncludes(templateFunction, seen) {
+  seen = seen || new Set();
+  const names = ((templateFunction && templateFunction.includes) || []).filter((name) => {
+    if (seen.has(name)) {
+      return false;
+    }
+    seen.add(name);
+    return true;
+  });
+
+  return Promise.all(names.map((name) => {
+    Benchpress.cache[name] = Benchpress.cache[name] || load(name);
+    return Benchpress.cache[name].then((partial) => {
+      Benchpress.partials[name] = partial;
+      return partial && Benchpress.loadIncludes(partial, seen);
+    });
+  })).then(() => templateFunction);
+};
+
 /**
  * Fetch and run the given template
  * @param {string} template - Name of template to fetch
@@ -112,7 +157,7 @@ function render(template, data, block) {
   return Promise.try(() => {
     Benchpress.cache[template] = Benchpress.cache[template] || load(template);
     return Benchpress.cache[template];
-  }).then((templateFunction) => {
+  }).then(templateFunction => Benchpress.loadIncludes(templateFunction)).then((templateFunction) => {
     if (block) {
       templateFunction = templateFunction.blocks && templateFunction.blocks[block];
     }
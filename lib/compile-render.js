@@ -33,7 +33,7 @@ function compileRender(templateSource, data, block) {
 
     compileRenderCache.set(templateSource, templateFunction);
     return templateFunction;
-  }).then((templateFunction) => {
+  }).then(templateFunction => Benchpress.loadIncludes(templateFunction)).then((templateFunction) => {
     if (block) {
       templateFunction = templateFunction.blocks && templateFunction.blocks[block];
     }
@@ -9,8 +9,22 @@ const { compile } = require('../build/compiler');
  * - `precompile(source, {}, callback) => callback(err, output)`
  * - `precompile({ source }, callback) => callback(err, output)`
  *
+ * Rejects with an `Error` if the template fails to compile. The error has
+ * `filename`, `line`, and `column` of the first problem, and `diagnostics`
+ * listing every warning and error found.
+ *
  * @param {string} source - Template source
  * @param {string} options.filename - Template file name for diagnostics
+ * @param {boolean} [options.strict] - Treat deprecated syntax as an error
+ * @param {string} [options.format] - Module format of the output code:
+ * `'umd'` (default), `'esm'` for an ES module, or `'cjs'` for plain CommonJS.
+ * Any other format rejects with an `Error`
+ * @param {boolean} [options.sourceMap] - Append an inline source map,
+ * mapping the output code back to the template
+ * @param {boolean} [options.standalone] - Remove lines holding only
+ * block tags like `{{{ if x }}}` and whitespace
+ * @param {Object<string, string>} [options.partials] - Sources of templates by name,
+ * inlined where they are included or extended
  * @param {function} [callback] - (err, output)
  * @returns {Promise<string>} - output code
  */
@@ -24,13 +38,20 @@ function precompile(source, options, callback) {
   }
 
   const filename = (options && options.filename) || '<unknown>';
+  const strict = Boolean(options && options.strict);
+  const format = (options && options.format) || 'umd';
+  const sourceMap = Boolean(options && options.sourceMap);
+  const standalone = Boolean(options && options.standalone);
+  const partials = (options && options.partials) || undefined;
 
   const promise = Promise.try(() => {
     if (typeof source !== 'string') {
       throw Error('source must be a string');
     }
 
-    return compile(source, filename);
+    return compile(source, filename, {
+      strict, format, sourceMap, standalone, partials,
+    });
   });
 
   if (callback) {
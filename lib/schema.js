@@ -0,0 +1,28 @@
+'use strict';
+
+// eslint-disable-next-line import/no-unresolved, import/extensions
+const { inferSchema } = require('../build/compiler');
+
+/**
+ * Infer what data a template reads, as a JSON Schema for contexts it can render
+ *
+ * Fields only used in conditionals are optional, and iterated fields can be
+ * arrays or objects. Helper calls are listed under `x-benchpress-helpers`,
+ * with the source of each argument.
+ *
+ * Throws an `Error` if the template fails to compile, like `precompile`.
+ *
+ * @param {string} source - Template source
+ * @param {object} [options]
+ * @param {string} [options.filename] - Template file name for diagnostics
+ * @param {boolean} [options.strict] - Treat deprecated syntax as an error
+ * @returns {object} - JSON Schema
+ */
+function schema(source, options) {
+  const filename = (options && options.filename) || '<unknown>';
+  const strict = Boolean(options && options.strict);
+
+  return inferSchema(source, filename, { strict });
+}
+
+module.exports = schema;
@@ -50,3 +50,47 @@ describe('compileParse', () => {
     });
   });
 });
+
+describe('subscripts', () => {
+  let calls = 0;
+
+  before(() => {
+    Benchpress.flush();
+    Benchpress.registerHelper('nextKey', () => {
+      calls += 1;
+      return 'b';
+    });
+  });
+
+  it('should evaluate each subscript once', () => {
+    calls = 0;
+    return Benchpress.compileRender('{users[nextKey()].name}', { users: { b: { name: 'Bob' } } })
+      .then((output) => {
+        assert.strictEqual(output, 'Bob');
+        assert.strictEqual(calls, 1);
+      });
+  });
+
+  it('should evaluate nested subscripts once', () => {
+    calls = 0;
+    const data = {
+      a: { z: { x: 'ok' } },
+      b: { y: 'z' },
+      c: { b: 'y' },
+    };
+    return Benchpress.compileRender('{a[b[c[nextKey()]]].x}', data)
+      .then((output) => {
+        assert.strictEqual(output, 'ok');
+        assert.strictEqual(calls, 1);
+      });
+  });
+
+  it('should not evaluate subscripts of missing values', () => {
+    calls = 0;
+    return Benchpress.compileRender('{missing[nextKey()].name}', {})
+      .then((output) => {
+        assert.strictEqual(output, '');
+        assert.strictEqual(calls, 0);
+      });
+  });
+});
@@ -40,4 +40,108 @@ describe('precompile', () => {
       done();
     });
   });
+
+  it('should reject with location info on errors', () =>
+    Benchpress.precompile('{{{ if a }}}x{{{ else }}}y{{{ else }}}z{{{ end }}}', { filename: 'bad.tpl' })
+      .then(
+        () => assert.fail('expected precompile to fail'),
+        (err) => {
+          assert(err instanceof Error);
+          assert.strictEqual(err.filename, 'bad.tpl');
+          assert.strictEqual(err.line, 1);
+          assert.strictEqual(err.column, 27);
+          assert.strictEqual(err.diagnostics[0].code, 'unexpected-token');
+        },
+      )
+  );
+
+  it('should output ES modules and CommonJS', () =>
+    Promise.all([
+      Benchpress.precompile(template, { format: 'esm' }),
+      Benchpress.precompile(template, { format: 'cjs' }),
+    ]).then(([esm, cjs]) => {
+      assert(esm.includes('export default compiled;'));
+      assert(!esm.includes('module.exports'));
+      assert(cjs.includes('module.exports = compiled;'));
+      assert(!cjs.includes('define('));
+    })
+  );
+
+  it('should reject an unknown format', () =>
+    Benchpress.precompile(template, { format: 'iife' })
+      .then(
+        () => assert.fail('expected precompile to fail'),
+        (err) => {
+          assert(err instanceof Error);
+          assert(err.message.includes('unknown output format `iife`'));
+        },
+      )
+  );
+
+  it('should inline a source map', () =>
+    Benchpress.precompile(template, { filename: 'page.tpl', sourceMap: true }).then((code) => {
+      const prefix = '//# sourceMappingURL=data:application/json;charset=utf-8;base64,';
+      const comment = code.trim().split('\n').pop();
+      assert(comment.startsWith(prefix));
+
+      const map = JSON.parse(Buffer.from(comment.slice(prefix.length), 'base64').toString());
+      assert.strictEqual(map.version, 3);
+      assert.deepStrictEqual(map.sources, ['page.tpl']);
+      assert.deepStrictEqual(map.sourcesContent, [template]);
+      assert(map.mappings.length > 0);
+    })
+  );
+
+  it('should extend templates given as partials', () => {
+    const partials = {
+      layout: '<main>{{{ block "content" }}}empty{{{ end }}}</main>{{{ include "footer" }}}',
+      footer: '<footer>{year}</footer>',
+    };
+
+    return Benchpress.precompile('{{{ extends "layout" }}}{{{ block "content" }}}{title}{{{ end }}}', { partials })
+      .then((code) => {
+        const templateFunction = Benchpress.evaluate(code);
+        const output = Benchpress.runtime(Benchpress.helpers, { title: 'hi', year: 2020 }, templateFunction);
+        assert.strictEqual(output, '<main>hi</main><footer>2020</footer>');
+        assert.strictEqual(templateFunction.includes, undefined);
+        assert(templateFunction.blocks.content);
+      });
+  });
+
+  it('should remove standalone block lines', () => {
+    const source = '<ul>\n  {{{ each items }}}\n  <li>{@value}</li>\n  {{{ end }}}\n</ul>\n';
+
+    return Benchpress.precompile(source, { standalone: true })
+      .then((code) => {
+        const templateFunction = Benchpress.evaluate(code);
+        const output = Benchpress.runtime(Benchpress.helpers, { items: [1, 2] }, templateFunction);
+        assert.strictEqual(output, '<ul>\n  <li>1</li>\n  <li>2</li>\n</ul>\n');
+      });
+  });
+
+  it('should reject deprecated syntax in strict mode', () =>
+    Benchpress.precompile('{{{ if a }}}x<!-- ENDIF a -->', { strict: true })
+      .then(
+        () => assert.fail('expected precompile to fail'),
+        (err) => {
+          assert.strictEqual(err.diagnostics[0].code, 'mixed-tokens');
+          assert.strictEqual(err.diagnostics[0].severity, 'error');
+        },
+      )
+  );
+});
+
+describe('schema', () => {
+  it('should infer the data a template reads', () => {
+    const source = '{{{ if !user.admin }}}{{{ each posts }}}{./title}{{{ end }}}{{{ end }}}';
+    const schema = Benchpress.schema(source);
+
+    assert.deepStrictEqual(schema.required, ['posts']);
+    assert.deepStrictEqual(schema.properties.user.required, []);
+    assert.deepStrictEqual(schema.properties.posts.items.required, ['title']);
+  });
+
+  it('should throw if the template fails to compile', () => {
+    assert.throws(() => Benchpress.schema('{{{ else }}}'), /unexpected token/);
+  });
 });
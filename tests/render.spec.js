@@ -1,5 +1,6 @@
 'use strict';
 
+const assert = require('assert');
 const fs = require('fs');
 const path = require('path');
 
@@ -11,13 +12,23 @@ const name = 'loop-inside-if-else';
 const source = fs.readFileSync(path.join(__dirname, 'templates/source/loop-inside-if-else.tpl')).toString();
 const expected = fs.readFileSync(path.join(__dirname, 'templates/expected/loop-inside-if-else.html')).toString();
 
+const partials = {
+  'include-page': '<ul>{{{ each rooms }}}{{{ include "partials/room" }}}{{{ end }}}</ul>',
+  'partials/room': '<li>{title}<!-- IMPORT partials/footer --></li>',
+  'partials/footer': '<b>{name}</b>',
+};
+
 describe('', () => {
   before(() => {
     Benchpress.flush();
 
     const cache = {};
 
-    return Benchpress.precompile(source)
+    return Promise.all(Object.keys(partials).map(key => Benchpress.precompile(partials[key])
+      .then((code) => {
+        cache[key] = Benchpress.evaluate(code);
+      })))
+      .then(() => Benchpress.precompile(source))
       .then((code) => {
         cache[name] = Benchpress.evaluate(code);
         return Benchpress.registerLoader(n => Promise.resolve(cache[n]));
@@ -34,6 +45,11 @@ describe('', () => {
       Benchpress.render(name, mainData, 'rooms')
         .then(output => equalsIgnoreWhitespace(output, expected))
     );
+
+    it('should render included templates with the same data', () =>
+      Benchpress.render('include-page', { rooms: [{}, {}], title: 'hi', name: 'x' })
+        .then(output => assert.strictEqual(output, '<ul><li>hi<b>x</b></li><li>hi<b>x</b></li></ul>'))
+    );
   });
 
   describe('parse', () => {
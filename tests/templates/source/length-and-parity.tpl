@@ -0,0 +1,6 @@
+{arr.@length} {usernames.@length} {name.@length} {missing.@length}
+{{{ if emptyarray.@empty }}}nothing here{{{ end }}}
+{{{ if !arr.@empty }}}<ul>
+{{{ each arr }}}<li class="{{{ if @odd }}}odd{{{ else }}}even{{{ end }}}">{@value}{{{ if !@even }}}!{{{ end }}}</li>
+{{{ end }}}</ul>{{{ end }}}
+{{{ each people }}}{./name.@length} {{{ end }}}
@@ -0,0 +1,5 @@
+<ul>
+{{{ each person in people }}}<li>{person.name} ({person.age}){{{ each letter in arr }}}{{{ if @first }}}: {person.name}{{{ end }}}{letter}{{{ end }}}</li>
+{{{ end }}}</ul>
+{{{ each slug, admin in admins }}}{slug}={admin.name}{{{ each username in usernames }}}{{{ if (username == admin.name) }}} ({slug}){{{ end }}}{{{ end }}}
+{{{ end }}}
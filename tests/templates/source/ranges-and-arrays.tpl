@@ -0,0 +1,5 @@
+<nav>{{{ each page in 1..5 }}}<a href="?page={page}"{{{ if (page == 3) }}} class="active"{{{ end }}}>{page}</a>{{{ end }}}</nav>
+<p>{{{ each n in 1..ten }}}{{{ if @even }}}{n} {{{ end }}}{{{ end }}}</p>
+<p>{{{ each 3..1 }}}never{{{ end }}}{{{ each count..0 }}}{@value}{{{ end }}}</p>
+<ul>{{{ each label in ["one", name, eleven, 1..2] }}}<li>{@index}: {label}</li>{{{ end }}}</ul>
+<p>[{1..1000000000}] [{1..missing}]</p>
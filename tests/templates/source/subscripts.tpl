@@ -0,0 +1,8 @@
+{people[1].name}
+{myobject["two"]}
+{{{ each programmers }}}{./name}: {./hobbies[0]}
+{{{ end }}}
+{{{ each myarray }}}{myobject[@value]} {{{ end }}}
+{users[count]["icon:text"]}
+{people[missing].name}
+{{{ each myarray }}}{myarray[@index]}{{{ end }}}